@@ -0,0 +1,298 @@
+use std::error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+use serial;
+use structopt::clap;
+use xmodem;
+
+/// Exit status when the command line, a profile or a config file is invalid.
+pub const EXIT_USAGE: i32 = 2;
+/// Exit status when the received data failed verification.
+pub const EXIT_VERIFICATION: i32 = 3;
+/// Exit status when the serial device can't be found, opened, configured, read
+/// or written.
+pub const EXIT_DEVICE: i32 = 4;
+/// Exit status when the other side didn't respond in time.
+pub const EXIT_TIMEOUT: i32 = 5;
+/// Exit status when the transfer failed: corrupted packets, too many retries
+/// or unexpected responses.
+pub const EXIT_PROTOCOL: i32 = 6;
+/// Exit status when the other side cancelled the transfer.
+pub const EXIT_CANCELLED: i32 = 7;
+
+/// The exit statuses, as listed in `--help`.
+pub const EXIT_STATUS_HELP: &str = "EXIT STATUS:
+    0    success
+    1    reading or writing a local file failed
+    2    invalid command line, profile or config file
+    3    the received data failed verification
+    4    the serial device can't be found, opened, configured, read or written
+    5    the other side didn't respond in time
+    6    the transfer failed: corrupted packets, too many retries, unexpected responses
+    7    the other side cancelled the transfer";
+
+/// An error ending a `ttywrite` run, by what failed. Each kind exits with its
+/// own status.
+#[derive(Debug)]
+pub enum Error {
+    /// The command line couldn't be parsed. Its message is clap's own.
+    Arguments(clap::Error),
+    /// The options, a profile or a config file are invalid.
+    Usage(String),
+    /// The serial device can't be found, opened, configured, read or written.
+    /// The message names the device.
+    Device(io::Error),
+    /// The other side didn't respond in time.
+    Timeout,
+    /// The other side cancelled the transfer.
+    Cancelled,
+    /// The received data failed verification.
+    Verification,
+    /// The transfer failed.
+    Protocol(xmodem::Error),
+    /// Reading or writing a local file failed.
+    Io(io::Error),
+}
+
+impl Error {
+    /// Returns the error for the failure `e` to open the serial device at
+    /// `path`. The serial crate reports most failures as a missing device,
+    /// so the device is opened again to tell them apart.
+    pub fn open(path: &Path, e: serial::Error) -> Error {
+        let e = match OpenOptions::new().read(true).write(true).open(path) {
+            Err(e) => e,
+            Ok(_) => e.into(),
+        };
+
+        Error::device(path, e)
+    }
+
+    /// Returns the error `e` about the serial device at `path`.
+    pub fn device(path: &Path, e: io::Error) -> Error {
+        Error::Device(io::Error::new(
+            e.kind(),
+            format!("{}: {}", path.display(), e),
+        ))
+    }
+
+    /// Returns the error for `e`: `Error::Device` if it was returned by a
+    /// serial device wrapped in a [`DeviceIo`], `Error::Io` otherwise.
+    fn io(e: io::Error) -> Error {
+        if e.get_ref().is_some_and(|inner| inner.is::<DeviceError>()) {
+            Error::Device(e)
+        } else {
+            Error::Io(e)
+        }
+    }
+
+    /// Returns the status the process exits with.
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            Error::Arguments(_) | Error::Usage(_) => EXIT_USAGE,
+            Error::Device(_) => EXIT_DEVICE,
+            Error::Timeout => EXIT_TIMEOUT,
+            Error::Cancelled => EXIT_CANCELLED,
+            Error::Verification => EXIT_VERIFICATION,
+            Error::Protocol(_) => EXIT_PROTOCOL,
+            Error::Io(_) => 1,
+        }
+    }
+
+    /// Returns a suggestion to fix the error, if there is a usual cause.
+    pub fn hint(&self) -> Option<&'static str> {
+        match *self {
+            Error::Device(ref e) => match e.kind() {
+                io::ErrorKind::PermissionDenied => Some(
+                    "add yourself to the 'dialout' group (`sudo usermod -aG dialout $USER`), \
+                     then log in again",
+                ),
+                io::ErrorKind::NotFound => {
+                    Some("check that the device is plugged in; `ttywrite list` shows those found")
+                }
+                io::ErrorKind::ResourceBusy => {
+                    Some("close the other program using the device, such as a terminal emulator")
+                }
+                _ => None,
+            },
+            Error::Timeout => {
+                Some("check that the other side is waiting for the data, at the same baud rate")
+            }
+            Error::Protocol(_) => {
+                Some("check that both sides use the same baud rate, line settings and protocol")
+            }
+            _ => None,
+        }
+    }
+
+    /// Prints the error and its hint, if any, on stderr.
+    pub fn report(&self) {
+        if let Error::Arguments(ref e) = *self {
+            eprintln!("{}", e.message);
+            return;
+        }
+
+        eprintln!("error: {}", self);
+        if let Some(hint) = self.hint() {
+            eprintln!("hint: {}", hint);
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Arguments(ref e) => write!(f, "{}", e),
+            Error::Usage(ref msg) => write!(f, "{}", msg),
+            Error::Device(ref e) | Error::Io(ref e) => write!(f, "{}", e),
+            Error::Timeout => write!(f, "{}", xmodem::Error::Timeout),
+            Error::Cancelled => write!(f, "{}", xmodem::Error::Cancelled),
+            Error::Verification => write!(f, "{}", xmodem::Error::Verification),
+            Error::Protocol(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<xmodem::Error> for Error {
+    fn from(e: xmodem::Error) -> Error {
+        match e {
+            xmodem::Error::Timeout => Error::Timeout,
+            xmodem::Error::Cancelled => Error::Cancelled,
+            xmodem::Error::Verification => Error::Verification,
+            xmodem::Error::InvalidInput(msg) => Error::Usage(msg.into()),
+            xmodem::Error::Io(e) => Error::io(e),
+            e => Error::Protocol(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    /// Converts `e`, from a transfer, the serial device or a local file, by
+    /// its cause. Invalid input comes from options that don't fit together.
+    fn from(e: io::Error) -> Error {
+        match xmodem::Error::from(e) {
+            xmodem::Error::Io(ref e) if e.kind() == io::ErrorKind::InvalidInput => {
+                Error::Usage(e.to_string())
+            }
+            e => e.into(),
+        }
+    }
+}
+
+/// The message of an I/O error of the serial device, which names the device.
+#[derive(Debug)]
+struct DeviceError(String);
+
+impl fmt::Display for DeviceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for DeviceError {}
+
+/// The serial device at `path`, whose I/O errors convert to `Error::Device`
+/// rather than to the `Error::Io` of a local file. Timeouts are returned as
+/// they are: they mean the other side didn't respond, and the transfer
+/// handles them.
+pub struct DeviceIo<T> {
+    inner: T,
+    path: PathBuf,
+}
+
+impl<T> DeviceIo<T> {
+    pub fn new(inner: T, path: &Path) -> DeviceIo<T> {
+        DeviceIo {
+            inner,
+            path: path.to_path_buf(),
+        }
+    }
+
+    /// Returns `e` tagged as an error of the device.
+    fn error(&self, e: io::Error) -> io::Error {
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted => e,
+            kind => {
+                let msg = format!("{}: {}", self.path.display(), e);
+                io::Error::new(kind, DeviceError(msg))
+            }
+        }
+    }
+}
+
+impl<T: io::Read> io::Read for DeviceIo<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf).map_err(|e| self.error(e))
+    }
+}
+
+impl<T: io::Write> io::Write for DeviceIo<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf).map_err(|e| self.error(e))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush().map_err(|e| self.error(e))
+    }
+}
+
+impl<T: AsRawFd> AsRawFd for DeviceIo<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    /// A device whose reads and writes fail with `kind`.
+    struct Failing(io::ErrorKind);
+
+    impl Read for Failing {
+        fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+    }
+
+    impl Write for Failing {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn device_errors_exit_with_device_status() {
+        let path = Path::new("/dev/ttyUSB0");
+        let mut device = DeviceIo::new(Failing(io::ErrorKind::BrokenPipe), path);
+        let e = Error::from(device.write(b"x").expect_err("write fails"));
+        assert_eq!(e.exit_code(), EXIT_DEVICE);
+        assert!(e.to_string().starts_with("/dev/ttyUSB0: "), "{}", e);
+
+        let mut xmodem = xmodem::Xmodem::new(device);
+        let e = Error::from(xmodem.send(&[0u8; 128][..]).expect_err("send fails"));
+        assert_eq!(e.exit_code(), EXIT_DEVICE);
+    }
+
+    #[test]
+    fn device_timeouts_are_timeouts() {
+        let path = Path::new("/dev/ttyUSB0");
+        let mut device = DeviceIo::new(Failing(io::ErrorKind::TimedOut), path);
+        let e = Error::from(device.read(&mut [0]).expect_err("read fails"));
+        assert_eq!(e.exit_code(), EXIT_TIMEOUT);
+    }
+
+    #[test]
+    fn local_errors_exit_with_status_1() {
+        let e = Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert_eq!(e.exit_code(), 1);
+    }
+}
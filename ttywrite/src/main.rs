@@ -1,22 +1,41 @@
+// structopt-derive implements its traits inside an anonymous constant.
+#![allow(non_local_definitions)]
+
+extern crate libc;
 extern crate serial;
 extern crate structopt;
+extern crate toml;
 extern crate xmodem;
 #[macro_use]
 extern crate structopt_derive;
 
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::time::Duration;
 
-use serial::core::{BaudRate, CharSize, FlowControl, SerialDevice, SerialPortSettings, StopBits};
-use structopt::StructOpt;
-use xmodem::{Progress, Xmodem};
+use serial::core::{
+    BaudRate, CharSize, FlowControl, Parity, SerialDevice, SerialPortSettings, StopBits,
+};
+use structopt::{clap, StructOpt};
+use xmodem::{
+    Checkpoint, Digest, FileInfo, Kermit, PaddingPolicy, Progress, Status, TransferStats, Xmodem,
+};
 
+mod error;
 mod parsers;
+mod ports;
+mod profile;
+mod terminal;
+
+use error::{DeviceIo, Error};
+use profile::Profile;
 
 use parsers::{
-    parse_baud_rate, parse_flow_control, parse_mode, parse_stop_bits, parse_width, Mode,
+    parse_baud_rate, parse_digest, parse_flow_control, parse_line, parse_mode, parse_newline,
+    parse_padding, parse_parity, parse_protocol, parse_stop_bits, parse_width, Line, Mode, Newline,
+    Protocol,
 };
 
 #[derive(StructOpt, Debug)]
@@ -25,7 +44,7 @@ struct Opt {
     #[structopt(
         short = "m",
         long = "mode",
-        help = "Read or write mode",
+        help = "Read, write or terminal mode",
         parse(try_from_str = "parse_mode"),
         default_value = "write"
     )]
@@ -33,76 +52,245 @@ struct Opt {
 
     #[structopt(
         short = "i",
-        help = "Input file (defaults to stdin if not set)",
+        help = "Input file (defaults to the profile's file, then stdin)",
         parse(from_os_str)
     )]
     input: Option<PathBuf>,
 
+    #[structopt(
+        long = "profile",
+        help = "Load the settings of a named profile from the config files; options override them"
+    )]
+    profile: Option<String>,
+
     #[structopt(
         short = "b",
         long = "baud",
         parse(try_from_str = "parse_baud_rate"),
-        help = "Set baud rate",
-        default_value = "115200"
+        help = "Set baud rate [default: 115200]"
     )]
-    baud_rate: BaudRate,
+    baud_rate: Option<BaudRate>,
 
     #[structopt(
         short = "t",
         long = "timeout",
         parse(try_from_str),
-        help = "Set timeout in seconds",
-        default_value = "10"
+        help = "Set timeout in seconds [default: 10]"
     )]
-    timeout: u64,
+    timeout: Option<u64>,
 
     #[structopt(
         short = "w",
         long = "width",
         parse(try_from_str = "parse_width"),
-        help = "Set data character width in bits",
-        default_value = "8"
+        help = "Set data character width in bits [default: 8]"
+    )]
+    char_width: Option<CharSize>,
+
+    #[structopt(
+        help = "Path to TTY device, unless selected with --usb-serial or --usb-id",
+        parse(from_os_str)
     )]
-    char_width: CharSize,
+    tty_path: Option<PathBuf>,
 
-    #[structopt(help = "Path to TTY device", parse(from_os_str))]
-    tty_path: PathBuf,
+    #[structopt(
+        long = "usb-serial",
+        help = "Select the TTY device by the serial number of its USB device"
+    )]
+    usb_serial: Option<String>,
+
+    #[structopt(
+        long = "usb-id",
+        help = "Select the TTY device by the 'vendor:product' IDs of its USB device"
+    )]
+    usb_id: Option<String>,
 
     #[structopt(
         short = "f",
         long = "flow-control",
         parse(try_from_str = "parse_flow_control"),
-        help = "Enable flow control ('hardware' or 'software')",
-        default_value = "none"
+        help = "Enable flow control ('hardware' or 'software') [default: none]"
     )]
-    flow_control: FlowControl,
+    flow_control: Option<FlowControl>,
 
     #[structopt(
         short = "s",
         long = "stop-bits",
         parse(try_from_str = "parse_stop_bits"),
-        help = "Set number of stop bits",
-        default_value = "1"
+        help = "Set number of stop bits [default: 1]"
     )]
-    stop_bits: StopBits,
+    stop_bits: Option<StopBits>,
+
+    #[structopt(
+        long = "parity",
+        parse(try_from_str = "parse_parity"),
+        help = "Set parity ('none', 'odd' or 'even') [default: none]"
+    )]
+    parity: Option<Parity>,
+
+    #[structopt(
+        long = "line",
+        parse(try_from_str = "parse_line"),
+        help = "Set width, parity and stop bits as in '8N1'; -w, -s and --parity take precedence"
+    )]
+    line: Option<Line>,
 
     #[structopt(short = "r", long = "raw", help = "Disable XMODEM")]
     raw: bool,
+
+    #[structopt(
+        short = "P",
+        long = "protocol",
+        parse(try_from_str = "parse_protocol"),
+        help = "Transfer protocol ('xmodem' or 'kermit') [default: xmodem]"
+    )]
+    protocol: Option<Protocol>,
+
+    #[structopt(
+        short = "p",
+        long = "strip-padding",
+        parse(try_from_str = "parse_padding"),
+        help = "Strip trailing padding when reading ('none', 'sub' or 'nul')",
+        default_value = "none"
+    )]
+    padding: PaddingPolicy,
+
+    #[structopt(
+        short = "l",
+        long = "length",
+        parse(try_from_str),
+        help = "Truncate data read to this many bytes"
+    )]
+    length: Option<u64>,
+
+    #[structopt(
+        short = "R",
+        long = "resume",
+        help = "Save a checkpoint next to the input file if the transfer fails, and resume from it"
+    )]
+    resume: bool,
+
+    #[structopt(
+        long = "verify",
+        parse(try_from_str = "parse_digest"),
+        help = "Verify the whole transfer with a digest ('crc32' or 'sha256'); the peer must too"
+    )]
+    verify: Option<Digest>,
+
+    #[structopt(
+        long = "stats",
+        help = "Print the statistics of the XMODEM session when it ends"
+    )]
+    stats: bool,
+
+    #[structopt(
+        short = "T",
+        long = "terminal",
+        help = "Start terminal mode once the data is written"
+    )]
+    terminal: bool,
+
+    #[structopt(long = "echo", help = "Echo typed characters in terminal mode")]
+    echo: bool,
+
+    #[structopt(
+        long = "newline",
+        parse(try_from_str = "parse_newline"),
+        help = "What Enter sends in terminal mode ('cr', 'lf' or 'crlf')",
+        default_value = "cr"
+    )]
+    newline: Newline,
+
+    #[structopt(
+        long = "add-cr",
+        help = "Print received line feeds as CR LF in terminal mode"
+    )]
+    add_cr: bool,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+enum Command {
+    #[structopt(name = "list", about = "List serial devices and their USB attributes")]
+    List,
 }
 
 struct Tty {
-    serial: serial::SystemPort,
+    serial: DeviceIo<serial::SystemPort>,
     input: Option<PathBuf>,
     raw: bool,
+    protocol: Protocol,
+    /// The line carries 7 data bits: Kermit prefixes bytes with the 8th bit
+    /// set.
+    seven_bit: bool,
+    padding: PaddingPolicy,
+    resume: bool,
+    verify: Option<Digest>,
+    stats: bool,
+    /// Terminal mode settings, if it is started once the data is written.
+    terminal: Option<terminal::Options>,
+}
+
+/// A checkpoint loaded for `--resume`, and the file it is saved to.
+struct Resume {
+    path: PathBuf,
+    checkpoint: Checkpoint,
 }
 
 impl Tty {
+    /// Loads the checkpoint for the input file if `--resume` is set. A
+    /// missing checkpoint file starts the transfer from the beginning.
+    fn checkpoint(&self) -> io::Result<Option<Resume>> {
+        if !self.resume {
+            return Ok(None);
+        }
+
+        let input = match self.input {
+            Some(ref input) if !self.raw && self.protocol == Protocol::Xmodem => input,
+            _ => {
+                let message = "--resume requires an input file (-i) and XMODEM";
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+            }
+        };
+
+        let path = checkpoint_path(input);
+        let checkpoint = match Checkpoint::load(&path) {
+            Err(xmodem::Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                Checkpoint::new()
+            }
+            result => result?,
+        };
+
+        if checkpoint.bytes() > 0 {
+            eprintln!("resuming after {} bytes", checkpoint.bytes());
+        }
+
+        Ok(Some(Resume { path, checkpoint }))
+    }
+
     fn read(mut self) -> io::Result<()> {
+        let resume = self.checkpoint()?;
         let mut file;
         let mut stdout;
 
-        let mut writer: &mut io::Write = if let Some(pathbuf) = self.input {
-            file = File::create(pathbuf)?;
+        let mut writer: &mut dyn io::Write = if let Some(ref pathbuf) = self.input {
+            file = match resume {
+                Some(ref resume) => {
+                    // Keep the bytes the checkpoint covers, and only those.
+                    let mut file = OpenOptions::new()
+                        .read(true)
+                        .write(true)
+                        .create(true)
+                        .truncate(false)
+                        .open(pathbuf)?;
+                    resume.checkpoint.verify(&mut file)?;
+                    file.set_len(resume.checkpoint.bytes())?;
+                    file
+                }
+                None => File::create(pathbuf)?,
+            };
             &mut file
         } else {
             stdout = io::stdout();
@@ -112,23 +300,52 @@ impl Tty {
         if self.raw {
             io::copy(&mut self.serial, &mut writer)?;
             Ok(())
-        } else {
-            fn progress(progress: Progress) {
-                println!("progress: {:?}", progress);
+        } else if self.protocol == Protocol::Kermit {
+            let mut kermit = Kermit::new(self.serial);
+            kermit.set_eighth_bit_quoting(self.seven_bit);
+            while let Some(info) = kermit.recv_header()? {
+                let n = kermit.recv_data(&mut writer)?;
+                eprintln!("received {} ({} bytes)", info.name, n);
             }
-            let buf = vec![];
-            let mut cursor = io::Cursor::new(buf);
-            let n = Xmodem::receive_with_progress(self.serial, &mut cursor, progress)?;
-            writer.write_all(&cursor.into_inner()[..n])?;
+
             Ok(())
+        } else {
+            let mut xmodem = Xmodem::builder()
+                .padding_policy(self.padding)
+                .progress(progress_reporter())
+                .build(self.serial);
+            xmodem.set_verify(self.verify);
+            let result = resumable(&mut xmodem, resume, |xmodem| xmodem.recv(writer));
+            if self.stats {
+                print_stats(&xmodem.stats());
+            }
+
+            result
         }
     }
 
     fn write(mut self) -> io::Result<()> {
+        self.send()?;
+        match self.terminal {
+            Some(ref options) => terminal::run(&mut self.serial, options),
+            None => Ok(()),
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let resume = self.checkpoint()?;
+        let name = self
+            .input
+            .as_ref()
+            .and_then(|path| path.file_name())
+            .map_or_else(
+                || "stdin".into(),
+                |name| name.to_string_lossy().into_owned(),
+            );
         let mut file;
         let mut stdin;
 
-        let mut reader: &mut io::Read = if let Some(pathbuf) = self.input {
+        let mut reader: &mut dyn io::Read = if let Some(pathbuf) = self.input.take() {
             file = File::open(pathbuf)?;
             &mut file
         } else {
@@ -139,48 +356,315 @@ impl Tty {
         if self.raw {
             io::copy(&mut reader, &mut self.serial)?;
             Ok(())
-        } else {
-            fn progress(progress: Progress) {
-                println!("progress: {:?}", progress);
-            }
+        } else if self.protocol == Protocol::Kermit {
+            let info = FileInfo {
+                name,
+                len: None,
+                mtime: None,
+                mode: None,
+            };
 
+            let mut kermit = Kermit::new(&mut self.serial);
+            kermit.set_eighth_bit_quoting(self.seven_bit);
+            kermit.send_file(&info, reader)?;
+            Ok(kermit.finish()?)
+        } else {
             let mut buf = vec![];
             reader.read_to_end(&mut buf)?;
-            Xmodem::transmit_with_progress(io::Cursor::new(buf), self.serial, progress)?;
-            Ok(())
+            let total = buf.len() as u64;
+            let mut data = io::Cursor::new(buf);
+            if let Some(ref resume) = resume {
+                resume.checkpoint.verify(&mut data)?;
+            }
+
+            let mut xmodem = Xmodem::builder()
+                .total(total)
+                .progress(progress_reporter())
+                .build(&mut self.serial);
+            xmodem.set_try_read(|serial, buf| terminal::read_pending(serial, buf));
+            xmodem.set_verify(self.verify);
+            let result = resumable(&mut xmodem, resume, |xmodem| xmodem.send(data));
+            if self.stats {
+                print_stats(&xmodem.stats());
+            }
+
+            result
         }
     }
 }
 
-fn run() -> io::Result<()> {
-    let opt = Opt::from_args();
-
-    let mut serial = serial::open(&opt.tty_path).expect("path points to invalid TTY");
-    let mut tty_settings = serial.read_settings()?;
-    tty_settings.set_baud_rate(opt.baud_rate)?;
-    tty_settings.set_char_size(opt.char_width);
-    tty_settings.set_stop_bits(opt.stop_bits);
-    tty_settings.set_flow_control(opt.flow_control);
-    tty_settings.set_parity(serial::Parity::ParityNone);
-    serial.write_settings(&tty_settings)?;
-    serial.set_timeout(Duration::from_secs(opt.timeout))?;
-
-    let tty = Tty {
-        serial,
-        input: opt.input,
-        raw: opt.raw,
+/// Returns the path of the checkpoint `--resume` keeps for `input`.
+fn checkpoint_path(input: &Path) -> PathBuf {
+    let mut path = input.as_os_str().to_owned();
+    path.push(".checkpoint");
+    PathBuf::from(path)
+}
+
+/// Runs `transfer` with `xmodem`, resumed from `resume` if set. If the
+/// transfer fails, the checkpoint is saved so the next run can resume; once
+/// it succeeds, the checkpoint is removed.
+fn resumable<T, F>(xmodem: &mut Xmodem<T>, resume: Option<Resume>, transfer: F) -> io::Result<()>
+where
+    T: io::Read + io::Write,
+    F: FnOnce(&mut Xmodem<T>) -> xmodem::Result<usize>,
+{
+    if let Some(ref resume) = resume {
+        xmodem.resume(resume.checkpoint);
+    }
+
+    let result = transfer(xmodem);
+    let resume = match resume {
+        Some(resume) => resume,
+        None => return Ok(result.map(|_| ())?),
     };
 
-    match opt.mode {
-        Mode::Read => tty.read().unwrap(),
-        Mode::Write => tty.write().unwrap(),
+    match result {
+        Ok(_) => match fs::remove_file(&resume.path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        },
+        Err(e) => {
+            xmodem.checkpoint().save(&resume.path)?;
+            eprintln!("checkpoint saved to {}", resume.path.display());
+            Err(e.into())
+        }
+    }
+}
+
+/// Returns a progress closure that reports the transfer on stderr: a line for
+/// every event but packets, and a single updating line for packets.
+fn progress_reporter() -> impl FnMut(&Status) {
+    let mut packet_line = false;
+    move |status| {
+        let speed = status.throughput().unwrap_or(0.0) / 1024.0;
+        match status.progress {
+            Progress::Packet(_) => {
+                match status.fraction() {
+                    Some(fraction) => eprint!("\r{:3.0}% ", fraction * 100.0),
+                    None => eprint!("\r"),
+                }
+                eprint!(
+                    "{} bytes, {:.1} KiB/s, {} retries",
+                    status.bytes, speed, status.retries
+                );
+                packet_line = true;
+            }
+            progress => {
+                if packet_line {
+                    eprintln!();
+                    packet_line = false;
+                }
+                eprintln!("progress: {:?}", progress);
+            }
+        }
+    }
+}
+
+/// Prints the statistics of a session on stderr, whether it succeeded or not.
+fn print_stats(stats: &TransferStats) {
+    let speed = stats.throughput().unwrap_or(0.0) / 1024.0;
+    eprintln!(
+        "stats: {} bytes in {} packets, {:.2}s ({:.1} KiB/s), {:.2}s waiting for the handshake",
+        stats.bytes,
+        stats.packets,
+        stats.duration.as_secs_f64(),
+        speed,
+        stats.handshake.as_secs_f64()
+    );
+    eprintln!(
+        "stats: {} retransmissions ({} NAK, {} timeout, {} bad ACK, {} duplicate), {} cancels",
+        stats.retransmissions(),
+        stats.naks,
+        stats.timeouts,
+        stats.bad_acks,
+        stats.duplicates,
+        stats.cancels
+    );
+}
+
+/// Applies the line settings to `serial`. The settings are read back, as a
+/// port may ignore those its hardware doesn't support rather than fail.
+fn configure(
+    serial: &mut serial::SystemPort,
+    baud_rate: BaudRate,
+    line: Line,
+    flow_control: FlowControl,
+) -> io::Result<()> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+    let unsupported_baud = || invalid(format!("{} baud is not supported", baud_rate.speed()));
+
+    let mut settings = serial.read_settings()?;
+    settings
+        .set_baud_rate(baud_rate)
+        .map_err(|_| unsupported_baud())?;
+    settings.set_char_size(line.width);
+    settings.set_parity(line.parity);
+    settings.set_stop_bits(line.stop_bits);
+    settings.set_flow_control(flow_control);
+    serial.write_settings(&settings).map_err(|e| {
+        let e = io::Error::from(e);
+        invalid(format!(
+            "cannot set {} at {} baud: {}",
+            line,
+            baud_rate.speed(),
+            e
+        ))
+    })?;
+
+    let applied = serial.read_settings()?;
+    if applied.baud_rate().is_some_and(|rate| rate != baud_rate) {
+        return Err(unsupported_baud());
+    }
+
+    let kept = match (applied.char_size(), applied.parity(), applied.stop_bits()) {
+        (Some(width), Some(parity), Some(stop_bits)) => Some(Line {
+            width,
+            parity,
+            stop_bits,
+        }),
+        _ => None,
     };
 
+    if kept != Some(line) {
+        return Err(invalid(format!("{} is not supported", line)));
+    }
+
+    if applied.flow_control() != Some(flow_control) {
+        let kind = match flow_control {
+            FlowControl::FlowNone => "disabling",
+            FlowControl::FlowSoftware => "software",
+            FlowControl::FlowHardware => "hardware",
+        };
+        return Err(invalid(format!("{} flow control is not supported", kind)));
+    }
+
     Ok(())
 }
 
+fn run() -> Result<(), Error> {
+    let matches = Opt::clap()
+        .after_help(error::EXIT_STATUS_HELP)
+        .get_matches_safe()
+        .map_err(|e| match e.kind {
+            clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => e.exit(),
+            _ => Error::Arguments(e),
+        })?;
+    let opt = Opt::from_clap(matches);
+    if let Some(Command::List) = opt.command {
+        let ports = ports::list().map_err(|e| Error::device(Path::new("/dev"), e))?;
+        ports::print(&ports);
+        return Ok(());
+    }
+
+    let profile = match opt.profile {
+        Some(ref name) => profile::load(name).map_err(|e| Error::Usage(e.to_string()))?,
+        None => Profile::default(),
+    };
+
+    let protocol = opt
+        .protocol
+        .or(profile.protocol)
+        .unwrap_or(Protocol::Xmodem);
+    let line = Line {
+        width: opt
+            .char_width
+            .or(opt.line.map(|line| line.width))
+            .or(profile.char_width)
+            .unwrap_or(CharSize::Bits8),
+        parity: opt
+            .parity
+            .or(opt.line.map(|line| line.parity))
+            .or(profile.parity)
+            .unwrap_or(Parity::ParityNone),
+        stop_bits: opt
+            .stop_bits
+            .or(opt.line.map(|line| line.stop_bits))
+            .or(profile.stop_bits)
+            .unwrap_or(StopBits::Stop1),
+    };
+    if (opt.raw || protocol == Protocol::Kermit) && (opt.verify.is_some() || opt.stats) {
+        let msg = "--verify and --stats are only supported with XMODEM";
+        return Err(Error::Usage(msg.into()));
+    }
+
+    // A device given on the command line replaces the profile's entirely.
+    let (tty_path, selector) =
+        if opt.tty_path.is_some() || opt.usb_serial.is_some() || opt.usb_id.is_some() {
+            let selector = ports::Selector {
+                serial: opt.usb_serial,
+                id: opt.usb_id,
+            };
+            (opt.tty_path, selector)
+        } else {
+            let selector = ports::Selector {
+                serial: profile.usb_serial,
+                id: profile.usb_id,
+            };
+            (profile.device, selector)
+        };
+
+    let path = match (tty_path, selector.is_empty()) {
+        (Some(path), true) => path,
+        (None, false) => ports::find(&selector).map_err(Error::Device)?,
+        (tty_path, _) => {
+            let msg = match tty_path {
+                Some(_) => "give either a TTY path or --usb-serial/--usb-id, not both",
+                None => "a TTY path or --usb-serial/--usb-id is required",
+            };
+            return Err(Error::Usage(msg.into()));
+        }
+    };
+
+    let mut serial = serial::open(&path).map_err(|e| Error::open(&path, e))?;
+    let baud_rate = opt
+        .baud_rate
+        .or(profile.baud_rate)
+        .unwrap_or(BaudRate::Baud115200);
+    let flow_control = opt
+        .flow_control
+        .or(profile.flow_control)
+        .unwrap_or(FlowControl::FlowNone);
+    configure(&mut serial, baud_rate, line, flow_control).map_err(|e| Error::device(&path, e))?;
+    let timeout = opt.timeout.or(profile.timeout).unwrap_or(10);
+    serial
+        .set_timeout(Duration::from_secs(timeout))
+        .map_err(|e| Error::device(&path, e.into()))?;
+
+    let options = terminal::Options {
+        echo: opt.echo,
+        newline: opt.newline,
+        add_cr: opt.add_cr,
+    };
+
+    let mut tty = Tty {
+        serial: DeviceIo::new(serial, &path),
+        input: opt.input.or(profile.file),
+        raw: opt.raw,
+        padding: opt.length.map_or(opt.padding, PaddingPolicy::Truncate),
+        resume: opt.resume,
+        verify: opt.verify,
+        stats: opt.stats,
+        protocol,
+        seven_bit: line.width != CharSize::Bits8,
+        terminal: if opt.terminal { Some(options) } else { None },
+    };
+
+    let result = match opt.mode {
+        Mode::Read => tty.read(),
+        Mode::Write => tty.write(),
+        Mode::Terminal => terminal::run(&mut tty.serial, &options),
+    };
+
+    Ok(result?)
+}
+
+/// Runs `ttywrite`. On failure, the error is reported on stderr and the
+/// process exits with the status of its kind; see `error::EXIT_STATUS_HELP`.
 fn main() {
-    run().unwrap();
+    if let Err(e) = run() {
+        e.report();
+        process::exit(e.exit_code());
+    }
 }
 
 // fn read()
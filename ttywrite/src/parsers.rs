@@ -1,4 +1,7 @@
-use serial::core::{BaudRate, CharSize, FlowControl, StopBits};
+use std::fmt;
+
+use serial::core::{BaudRate, CharSize, FlowControl, Parity, StopBits};
+use xmodem::{Digest, PaddingPolicy};
 
 pub fn parse_width(s: &str) -> Result<CharSize, &str> {
     match s {
@@ -14,7 +17,7 @@ pub fn parse_stop_bits(s: &str) -> Result<StopBits, &str> {
     match s {
         "1" => Ok(StopBits::Stop1),
         "2" => Ok(StopBits::Stop2),
-        _ => Err("value must '1' or '2'"),
+        _ => Err("value must be '1' or '2'"),
     }
 }
 
@@ -27,20 +30,180 @@ pub fn parse_flow_control(s: &str) -> Result<FlowControl, &str> {
     }
 }
 
-pub fn parse_baud_rate(s: &str) -> Result<BaudRate, ::std::num::ParseIntError> {
-    Ok(BaudRate::from_speed(s.parse()?))
+pub fn parse_parity(s: &str) -> Result<Parity, &str> {
+    match s {
+        "none" => Ok(Parity::ParityNone),
+        "odd" => Ok(Parity::ParityOdd),
+        "even" => Ok(Parity::ParityEven),
+        _ => Err("value must be 'none', 'odd' or 'even'"),
+    }
+}
+
+pub fn parse_baud_rate(s: &str) -> Result<BaudRate, &str> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err("value must be a positive number of bits per second"),
+        Ok(speed) => Ok(BaudRate::from_speed(speed)),
+    }
+}
+
+/// Data width, parity and stop bits, as set together by `--line`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Line {
+    pub width: CharSize,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let width = match self.width {
+            CharSize::Bits5 => 5,
+            CharSize::Bits6 => 6,
+            CharSize::Bits7 => 7,
+            CharSize::Bits8 => 8,
+        };
+        let parity = match self.parity {
+            Parity::ParityNone => 'N',
+            Parity::ParityOdd => 'O',
+            Parity::ParityEven => 'E',
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::Stop1 => 1,
+            StopBits::Stop2 => 2,
+        };
+        write!(f, "{}{}{}", width, parity, stop_bits)
+    }
+}
+
+/// Parses the usual shorthand for line settings, such as `8N1` or `7E1`: the
+/// data width, the parity (`N`, `O` or `E`) and the stop bits.
+pub fn parse_line(s: &str) -> Result<Line, &str> {
+    const MESSAGE: &str =
+        "value must look like '8N1': width 5-8, parity N, O or E, stop bits 1 or 2";
+    if s.len() != 3 || !s.is_ascii() {
+        return Err(MESSAGE);
+    }
+
+    let parity = match &s[1..2] {
+        "N" | "n" => Parity::ParityNone,
+        "O" | "o" => Parity::ParityOdd,
+        "E" | "e" => Parity::ParityEven,
+        _ => return Err(MESSAGE),
+    };
+
+    Ok(Line {
+        width: parse_width(&s[..1]).map_err(|_| MESSAGE)?,
+        parity,
+        stop_bits: parse_stop_bits(&s[2..]).map_err(|_| MESSAGE)?,
+    })
 }
 
 #[derive(Debug)]
 pub enum Mode {
     Read,
     Write,
+    Terminal,
 }
 
 pub fn parse_mode(s: &str) -> Result<Mode, &str> {
     match s {
         "read" => Ok(Mode::Read),
         "write" => Ok(Mode::Write),
-        _ => Err("value must be 'read' or 'write"),
+        "terminal" | "term" => Ok(Mode::Terminal),
+        _ => Err("value must be 'read', 'write' or 'terminal'"),
+    }
+}
+
+/// What the Enter key sends in terminal mode.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Newline {
+    Cr,
+    Lf,
+    CrLf,
+}
+
+impl Newline {
+    pub fn bytes(&self) -> &'static [u8] {
+        match *self {
+            Newline::Cr => b"\r",
+            Newline::Lf => b"\n",
+            Newline::CrLf => b"\r\n",
+        }
+    }
+}
+
+pub fn parse_newline(s: &str) -> Result<Newline, &str> {
+    match s {
+        "cr" => Ok(Newline::Cr),
+        "lf" => Ok(Newline::Lf),
+        "crlf" => Ok(Newline::CrLf),
+        _ => Err("value must be 'cr', 'lf' or 'crlf'"),
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Protocol {
+    Xmodem,
+    Kermit,
+}
+
+pub fn parse_protocol(s: &str) -> Result<Protocol, &str> {
+    match s {
+        "xmodem" => Ok(Protocol::Xmodem),
+        "kermit" => Ok(Protocol::Kermit),
+        _ => Err("value must be 'xmodem' or 'kermit'"),
+    }
+}
+
+pub fn parse_padding(s: &str) -> Result<PaddingPolicy, &str> {
+    match s {
+        "none" => Ok(PaddingPolicy::Keep),
+        "sub" => Ok(PaddingPolicy::StripSub),
+        "nul" => Ok(PaddingPolicy::StripNul),
+        _ => Err("value must be 'none', 'sub' (0x1A), or 'nul' (0x00)"),
+    }
+}
+
+pub fn parse_digest(s: &str) -> Result<Digest, &str> {
+    match s {
+        "crc32" => Ok(Digest::Crc32),
+        "sha256" => Ok(Digest::Sha256),
+        _ => Err("value must be 'crc32' or 'sha256'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_line_valid() {
+        let line = parse_line("8N1").expect("8N1");
+        assert_eq!(line.width, CharSize::Bits8);
+        assert_eq!(line.parity, Parity::ParityNone);
+        assert_eq!(line.stop_bits, StopBits::Stop1);
+
+        let line = parse_line("7e2").expect("7e2");
+        assert_eq!(line.width, CharSize::Bits7);
+        assert_eq!(line.parity, Parity::ParityEven);
+        assert_eq!(line.stop_bits, StopBits::Stop2);
+
+        for s in &["5N1", "6O1", "7E1", "8N2"] {
+            assert_eq!(parse_line(s).expect(s).to_string(), *s);
+        }
+    }
+
+    #[test]
+    fn parse_line_wrong_length() {
+        for s in &["", "8N", "8N12", "8 N 1", "8N1 "] {
+            assert!(parse_line(s).is_err(), "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_line_bad_fields() {
+        for s in &["8X1", "8M1", "8S1", "9N1", "4N1", "8N3", "8N0", "N81", "8é"] {
+            assert!(parse_line(s).is_err(), "{:?}", s);
+        }
     }
 }
@@ -0,0 +1,192 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Attributes of the USB device behind a serial port, read from sysfs.
+#[derive(Debug, Clone)]
+pub struct UsbInfo {
+    pub vendor_id: String,
+    pub product_id: String,
+    pub serial: Option<String>,
+    pub manufacturer: Option<String>,
+    pub product: Option<String>,
+}
+
+/// A candidate serial device.
+#[derive(Debug, Clone)]
+pub struct Port {
+    pub path: PathBuf,
+    /// The `/dev/serial/by-id` link to the device, if any. Unlike the path,
+    /// it stays the same across reboots and replugs.
+    pub by_id: Option<PathBuf>,
+    pub usb: Option<UsbInfo>,
+}
+
+/// Attributes a port is selected by, instead of its path.
+#[derive(Debug, Clone, Default)]
+pub struct Selector {
+    /// The USB serial number.
+    pub serial: Option<String>,
+    /// The USB vendor and product IDs, as `vvvv:pppp`.
+    pub id: Option<String>,
+}
+
+impl Selector {
+    pub fn is_empty(&self) -> bool {
+        self.serial.is_none() && self.id.is_none()
+    }
+
+    fn matches(&self, port: &Port) -> bool {
+        let usb = match port.usb {
+            Some(ref usb) => usb,
+            None => return false,
+        };
+
+        let serial = self
+            .serial
+            .as_ref()
+            .is_none_or(|s| usb.serial.as_ref() == Some(s));
+        let id = self
+            .id
+            .as_ref()
+            .is_none_or(|id| id.eq_ignore_ascii_case(&usb.id()));
+        serial && id
+    }
+}
+
+impl UsbInfo {
+    /// Returns the vendor and product IDs as `vvvv:pppp`.
+    pub fn id(&self) -> String {
+        format!("{}:{}", self.vendor_id, self.product_id)
+    }
+}
+
+/// Prefixes of the names of serial devices in `/dev`: USB serial adapters,
+/// USB CDC ACM devices such as boards with native USB, and the Pi's UART.
+const PREFIXES: &[&str] = &["ttyUSB", "ttyACM", "ttyAMA", "cu.usbserial", "cu.usbmodem"];
+
+/// Returns the serial devices found in `/dev`, sorted by path: USB serial
+/// devices, UARTs and pseudo-terminals.
+pub fn list() -> io::Result<Vec<Port>> {
+    let mut ports = vec![];
+    for entry in fs::read_dir("/dev")? {
+        let name = entry?.file_name();
+        let name = name.to_string_lossy();
+        if PREFIXES.iter().any(|prefix| name.starts_with(prefix)) {
+            ports.push(port(Path::new("/dev").join(&*name)));
+        }
+    }
+
+    if let Ok(entries) = fs::read_dir("/dev/pts") {
+        for entry in entries {
+            let path = entry?.path();
+            if path.file_name().is_some_and(|name| name != "ptmx") {
+                ports.push(port(path));
+            }
+        }
+    }
+
+    let by_id = links("/dev/serial/by-id");
+    for port in ports.iter_mut() {
+        port.by_id = by_id
+            .iter()
+            .find(|link| link.1 == port.path)
+            .map(|link| link.0.clone());
+    }
+
+    ports.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ports)
+}
+
+/// Returns the path of the only port that matches `selector`.
+pub fn find(selector: &Selector) -> io::Result<PathBuf> {
+    let mut matches = list()?.into_iter().filter(|port| selector.matches(port));
+    match (matches.next(), matches.next()) {
+        (Some(port), None) => Ok(port.path),
+        (None, _) => Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no serial device matches; see `ttywrite list`",
+        )),
+        (Some(_), Some(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "several serial devices match; see `ttywrite list`",
+        )),
+    }
+}
+
+/// Prints `ports` one per line: the path, then the USB attributes and the
+/// stable link, where known.
+pub fn print(ports: &[Port]) {
+    for port in ports {
+        let mut line = port.path.display().to_string();
+        if let Some(ref usb) = port.usb {
+            line.push_str(&format!("  {}", usb.id()));
+            for field in &[&usb.manufacturer, &usb.product] {
+                if let Some(ref value) = **field {
+                    line.push_str(&format!(" {}", value));
+                }
+            }
+
+            if let Some(ref serial) = usb.serial {
+                line.push_str(&format!(", serial {}", serial));
+            }
+        }
+
+        if let Some(ref link) = port.by_id {
+            line.push_str(&format!("  ({})", link.display()));
+        }
+
+        println!("{}", line);
+    }
+}
+
+/// Returns the port at `path`, with the attributes of its USB device if it
+/// has one.
+fn port(path: PathBuf) -> Port {
+    let usb = path
+        .file_name()
+        .and_then(|name| usb_info(&Path::new("/sys/class/tty").join(name).join("device")));
+    Port {
+        path,
+        by_id: None,
+        usb,
+    }
+}
+
+/// Reads the attributes of the USB device `device` belongs to: the closest
+/// directory above it in sysfs with a vendor ID.
+fn usb_info(device: &Path) -> Option<UsbInfo> {
+    let device = fs::canonicalize(device).ok()?;
+    let dir = device
+        .ancestors()
+        .find(|dir| dir.join("idVendor").is_file())?;
+    let read = |name: &str| {
+        fs::read_to_string(dir.join(name))
+            .ok()
+            .map(|value| value.trim().to_string())
+    };
+
+    Some(UsbInfo {
+        vendor_id: read("idVendor")?,
+        product_id: read("idProduct")?,
+        serial: read("serial"),
+        manufacturer: read("manufacturer"),
+        product: read("product"),
+    })
+}
+
+/// Returns the symbolic links in `dir` and the paths they resolve to.
+fn links(dir: &str) -> Vec<(PathBuf, PathBuf)> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let target = fs::canonicalize(entry.path()).ok()?;
+            Some((entry.path(), target))
+        })
+        .collect()
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serial::core::{BaudRate, CharSize, FlowControl, Parity, StopBits};
+use toml::value::{Table, Value};
+
+use parsers::{
+    parse_baud_rate, parse_flow_control, parse_line, parse_parity, parse_protocol, parse_stop_bits,
+    parse_width, Protocol,
+};
+
+/// Name of the per-project configuration file, looked up in the current
+/// directory.
+const PROJECT_FILE: &str = ".ttywrite.toml";
+
+/// Connection settings saved under a name in a configuration file. A field
+/// that isn't set leaves the setting to the command line or its default.
+///
+/// Profiles are tables named `profile.<name>`, with the keys named after the
+/// long options they stand for:
+///
+/// ```toml
+/// [profile.pi3]
+/// usb-id = "0403:6001"
+/// baud = 115200
+/// width = 8
+/// stop-bits = 1
+/// parity = "none"
+/// flow-control = "none"
+/// timeout = 10
+/// protocol = "xmodem"
+/// file = "build/kernel.bin"
+/// ```
+///
+/// The device is given as a path with `device`, or selected with `usb-serial`
+/// and `usb-id`. Like `--line`, `line = "7E1"` sets the width, parity and stop
+/// bits at once.
+#[derive(Debug, Default)]
+pub struct Profile {
+    pub device: Option<PathBuf>,
+    pub usb_serial: Option<String>,
+    pub usb_id: Option<String>,
+    pub baud_rate: Option<BaudRate>,
+    pub char_width: Option<CharSize>,
+    pub stop_bits: Option<StopBits>,
+    pub parity: Option<Parity>,
+    pub flow_control: Option<FlowControl>,
+    pub timeout: Option<u64>,
+    pub protocol: Option<Protocol>,
+    pub file: Option<PathBuf>,
+}
+
+impl Profile {
+    /// Sets the field for `key` to `value`.
+    fn set(&mut self, key: &str, value: String) -> Result<(), String> {
+        match key {
+            "device" => self.device = Some(value.into()),
+            "usb-serial" => self.usb_serial = Some(value),
+            "usb-id" => self.usb_id = Some(value),
+            "baud" => self.baud_rate = Some(parse_baud_rate(&value)?),
+            "width" => self.char_width = Some(parse_width(&value)?),
+            "stop-bits" => self.stop_bits = Some(parse_stop_bits(&value)?),
+            "parity" => self.parity = Some(parse_parity(&value)?),
+            "line" => {
+                let line = parse_line(&value)?;
+                self.char_width = Some(line.width);
+                self.parity = Some(line.parity);
+                self.stop_bits = Some(line.stop_bits);
+            }
+            "flow-control" => self.flow_control = Some(parse_flow_control(&value)?),
+            "timeout" => self.timeout = Some(value.parse().map_err(|_| "expected seconds")?),
+            "protocol" => self.protocol = Some(parse_protocol(&value)?),
+            "file" => self.file = Some(value.into()),
+            _ => return Err(format!("unknown key `{}`", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the configuration files, in the order they are read: the user's,
+/// then the project's, whose settings take precedence.
+fn config_files() -> Vec<PathBuf> {
+    let mut files = vec![];
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(dir) = config_dir {
+        files.push(dir.join("ttywrite").join("config.toml"));
+    }
+
+    files.push(PathBuf::from(PROJECT_FILE));
+    files
+}
+
+/// Loads the profile `name` from the configuration files. A profile defined
+/// in both files is merged key by key.
+pub fn load(name: &str) -> io::Result<Profile> {
+    let files = config_files();
+    let mut profile = Profile::default();
+    let mut found = false;
+    for path in &files {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                let msg = format!("{}: {}", path.display(), e);
+                return Err(io::Error::new(e.kind(), msg));
+            }
+        };
+
+        found |= parse(&text, name, &mut profile).map_err(|msg| {
+            let msg = format!("{}: {}", path.display(), msg);
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        })?;
+    }
+
+    if !found {
+        let files: Vec<_> = files
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let msg = format!("no profile `{}` in {}", name, files.join(" or "));
+        return Err(io::Error::new(io::ErrorKind::NotFound, msg));
+    }
+
+    Ok(profile)
+}
+
+/// Applies the keys of the table of profile `name` in `text` to `profile`,
+/// returning whether the table was found. Every table is checked, so that a
+/// mistake is reported whichever profile is used.
+fn parse(text: &str, name: &str, profile: &mut Profile) -> Result<bool, String> {
+    let mut tables: BTreeMap<String, BTreeMap<String, Table>> =
+        toml::from_str(text).map_err(|e| e.to_string())?;
+    if let Some(table) = tables.keys().find(|table| *table != "profile") {
+        return Err(format!("expected `[profile.<name>]`, found `[{}]`", table));
+    }
+
+    let profiles = tables.remove("profile").unwrap_or_default();
+    for (profile_name, table) in &profiles {
+        let mut other = Profile::default();
+        let target = if profile_name == name {
+            &mut *profile
+        } else {
+            &mut other
+        };
+
+        for (key, value) in table {
+            let error = |msg: String| format!("profile.{}.{}: {}", profile_name, key, msg);
+            let value = match *value {
+                Value::String(ref s) => s.clone(),
+                Value::Integer(i) => i.to_string(),
+                _ => return Err(error("expected a string or an integer".into())),
+            };
+
+            target.set(key, value).map_err(error)?;
+        }
+    }
+
+    Ok(profiles.contains_key(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merges_files() {
+        let user = "[profile.pi3]\nbaud = 9600\ndevice = \"/dev/ttyUSB0\"\n";
+        let project = "[profile.pi3]\nbaud = 115200\nfile = \"kernel8.img\"\n";
+        let mut profile = Profile::default();
+        assert_eq!(parse(user, "pi3", &mut profile), Ok(true));
+        assert_eq!(parse(project, "pi3", &mut profile), Ok(true));
+        assert_eq!(profile.baud_rate, Some(BaudRate::Baud115200));
+        assert_eq!(profile.device, Some(PathBuf::from("/dev/ttyUSB0")));
+        assert_eq!(profile.file, Some(PathBuf::from("kernel8.img")));
+    }
+
+    #[test]
+    fn other_profiles_are_checked_but_not_applied() {
+        let text = "[profile.qemu]\nbaud = 9600\n\n[profile.pi3]\nwidth = 7\n";
+        let mut profile = Profile::default();
+        assert_eq!(parse(text, "pi3", &mut profile), Ok(true));
+        assert_eq!(profile.baud_rate, None);
+        assert_eq!(profile.char_width, Some(CharSize::Bits7));
+        assert_eq!(parse(text, "pi4", &mut Profile::default()), Ok(false));
+
+        let text = "[profile.qemu]\nbaud = \"fast\"\n[profile.pi3]\n";
+        let e = parse(text, "pi3", &mut Profile::default()).unwrap_err();
+        assert!(e.starts_with("profile.qemu.baud: "), "{}", e);
+    }
+
+    #[test]
+    fn bad_keys_and_tables() {
+        let text = "[profile.pi3]\nspeed = 9600\n";
+        let e = parse(text, "pi3", &mut Profile::default()).unwrap_err();
+        assert_eq!(e, "profile.pi3.speed: unknown key `speed`");
+
+        let text = "[profile.pi3]\nbaud = 1.5\n";
+        let e = parse(text, "pi3", &mut Profile::default()).unwrap_err();
+        assert_eq!(e, "profile.pi3.baud: expected a string or an integer");
+
+        let e = parse("[serial]\n", "pi3", &mut Profile::default()).unwrap_err();
+        assert_eq!(e, "expected `[profile.<name>]`, found `[serial]`");
+
+        assert!(parse("[profile.pi3\n", "pi3", &mut Profile::default()).is_err());
+    }
+}
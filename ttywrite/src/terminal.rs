@@ -0,0 +1,273 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{AsRawFd, RawFd};
+
+use libc;
+use serial::SystemPort;
+use xmodem::Xmodem;
+
+use error::DeviceIo;
+use parsers::Newline;
+use progress_reporter;
+
+/// The escape key, Ctrl-A. The key typed after it is a command.
+const ESCAPE: u8 = 0x01;
+
+const HELP: &str = "Ctrl-A q: quit, Ctrl-A b: send a break, \
+                    Ctrl-A u: upload a file with XMODEM, Ctrl-A Ctrl-A: send Ctrl-A";
+
+/// Settings of a terminal session.
+#[derive(Debug, Copy, Clone)]
+pub struct Options {
+    /// Write the characters typed to the screen as well.
+    pub echo: bool,
+    /// What the Enter key sends.
+    pub newline: Newline,
+    /// Print line feeds received as CR LF.
+    pub add_cr: bool,
+}
+
+/// Keeps the local terminal in raw mode: keys are passed on as they are
+/// typed, without line editing, echo or signals. The previous settings are
+/// restored when dropped.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    /// Puts the terminal `fd` in raw mode. Returns `None` if `fd` isn't a
+    /// terminal.
+    fn enter(fd: RawFd) -> io::Result<Option<RawMode>> {
+        if unsafe { libc::isatty(fd) } == 0 {
+            return Ok(None);
+        }
+
+        let mut saved: libc::termios = unsafe { mem::zeroed() };
+        check(unsafe { libc::tcgetattr(fd, &mut saved) })?;
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        check(unsafe { libc::tcsetattr(fd, libc::TCSANOW, &raw) })?;
+        Ok(Some(RawMode { fd, saved }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSANOW, &self.saved) };
+    }
+}
+
+/// Bridges the local terminal and `port` until the user quits or either side
+/// closes. Typed keys are sent to `port` and what it receives is written to
+/// stdout, both as soon as they arrive.
+pub fn run(port: &mut DeviceIo<SystemPort>, options: &Options) -> io::Result<()> {
+    let stdin = libc::STDIN_FILENO;
+    let mut raw = RawMode::enter(stdin)?;
+    message(&format!("terminal ready; {}", HELP));
+
+    let mut stdout = io::stdout();
+    let mut buf = [0u8; 1024];
+    let mut escaped = false;
+    loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: port.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: stdin,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+
+        match check(unsafe { libc::poll(fds.as_mut_ptr(), 2, -1) }) {
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => result?,
+        };
+
+        if fds[0].revents != 0 {
+            let n = match port.read(&mut buf) {
+                Ok(0) => {
+                    message("the serial line was closed");
+                    return Ok(());
+                }
+                Ok(n) => n,
+                Err(ref e) if is_transient(e) => continue,
+                Err(e) => return Err(e),
+            };
+
+            display(&mut stdout, &buf[..n], options.add_cr)?;
+        }
+
+        if fds[1].revents == 0 {
+            continue;
+        }
+
+        let n = match read_fd(stdin, &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(ref e) if is_transient(e) => continue,
+            Err(e) => return Err(e),
+        };
+
+        let mut typed = vec![];
+        for &byte in &buf[..n] {
+            if escaped {
+                escaped = false;
+                match byte {
+                    b'q' | b'Q' => return Ok(()),
+                    b'b' | b'B' => {
+                        check(unsafe { libc::tcsendbreak(port.as_raw_fd(), 0) })?;
+                        message("break sent");
+                    }
+                    b'u' | b'U' => {
+                        send(port, &mut stdout, &typed, options)?;
+                        typed.clear();
+                        upload(port, &mut raw)?;
+                    }
+                    ESCAPE => typed.push(ESCAPE),
+                    _ => message(HELP),
+                }
+            } else if byte == ESCAPE {
+                escaped = true;
+            } else if byte == b'\r' {
+                typed.extend_from_slice(options.newline.bytes());
+            } else {
+                typed.push(byte);
+            }
+        }
+
+        send(port, &mut stdout, &typed, options)?;
+    }
+}
+
+/// Sends the keys `typed` to `port`, echoing them if configured to.
+fn send(
+    port: &mut DeviceIo<SystemPort>,
+    stdout: &mut io::Stdout,
+    typed: &[u8],
+    options: &Options,
+) -> io::Result<()> {
+    if typed.is_empty() {
+        return Ok(());
+    }
+
+    port.write_all(typed)?;
+    port.flush()?;
+    if options.echo {
+        display(stdout, typed, options.add_cr)?;
+    }
+
+    Ok(())
+}
+
+/// Asks for the path of a file and sends it to `port` with XMODEM. The
+/// terminal leaves raw mode meanwhile, so the path can be edited and the
+/// upload interrupted with Ctrl-C.
+fn upload(port: &mut DeviceIo<SystemPort>, raw: &mut Option<RawMode>) -> io::Result<()> {
+    let was_raw = raw.take().is_some();
+    eprint!("\nupload file: ");
+    let mut line = vec![];
+    let mut byte = [0u8; 1];
+    while read_fd(libc::STDIN_FILENO, &mut byte)? == 1 && byte[0] != b'\n' {
+        line.push(byte[0]);
+    }
+
+    let path = String::from_utf8_lossy(&line).trim().to_string();
+    if !path.is_empty() {
+        let result = File::open(&path).and_then(|file| {
+            let total = file.metadata()?.len();
+            let mut xmodem = Xmodem::builder()
+                .total(total)
+                .progress(progress_reporter())
+                .build(&mut *port);
+            xmodem.set_try_read(|port, buf| read_pending(port, buf));
+            Ok(xmodem.send(file)?)
+        });
+
+        match result {
+            Ok(n) => eprintln!("\nuploaded {} bytes from {}", n, path),
+            Err(e) => eprintln!("\nupload failed: {}", e),
+        }
+    }
+
+    if was_raw {
+        *raw = RawMode::enter(libc::STDIN_FILENO)?;
+    }
+
+    Ok(())
+}
+
+/// Reads the bytes `port` has already received into `buf`, without waiting
+/// for more. Used by XMODEM senders to discard stale responses.
+pub fn read_pending(port: &mut DeviceIo<SystemPort>, buf: &mut [u8]) -> io::Result<usize> {
+    let mut fds = [libc::pollfd {
+        fd: port.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    if check(unsafe { libc::poll(fds.as_mut_ptr(), 1, 0) })? == 0 {
+        return Ok(0);
+    }
+
+    port.read(buf)
+}
+
+/// Writes `bytes` received from the line to `stdout`, with a CR before every
+/// LF if `add_cr` is set.
+fn display(stdout: &mut io::Stdout, bytes: &[u8], add_cr: bool) -> io::Result<()> {
+    if add_cr {
+        let mut translated = Vec::with_capacity(bytes.len());
+        for &byte in bytes {
+            if byte == b'\n' {
+                translated.push(b'\r');
+            }
+
+            translated.push(byte);
+        }
+
+        stdout.write_all(&translated)?;
+    } else {
+        stdout.write_all(bytes)?;
+    }
+
+    stdout.flush()
+}
+
+/// Prints a message from the terminal itself on a line of its own. The line
+/// ends with CR LF, as raw mode doesn't translate LF.
+fn message(text: &str) {
+    eprint!("\r\n[{}]\r\n", text);
+}
+
+/// Reads from the file descriptor `fd` without buffering, so that nothing is
+/// left behind when `poll()` is called again.
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> io::Result<usize> {
+    let n = unsafe { libc::read(fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+    if n < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(n as usize)
+    }
+}
+
+/// Returns the error of a libc call that returned `result`, if negative.
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Returns `true` if the read that failed with `e` can be retried.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
@@ -0,0 +1,11 @@
+//! Drives an XMODEM receiver with an arbitrary sender byte stream.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate xmodem_fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    xmodem_fuzz::receive(data);
+});
@@ -0,0 +1,11 @@
+//! Drives an XMODEM sender with an arbitrary receiver byte stream.
+
+#![no_main]
+
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate xmodem_fuzz;
+
+fuzz_target!(|data: &[u8]| {
+    xmodem_fuzz::send(data);
+});
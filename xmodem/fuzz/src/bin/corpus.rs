@@ -0,0 +1,79 @@
+//! Generates seed corpora for the fuzz targets from valid sessions.
+//!
+//! Run with `cargo run --bin corpus` from the `fuzz` directory. Every session
+//! transfers a payload over a perfect line with a tracer attached to each
+//! side; what the sender sent seeds the `receiver` target and what the
+//! receiver sent seeds the `sender` target.
+
+extern crate xmodem;
+extern crate xmodem_fuzz;
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use xmodem::{noisy_pipe, BlockSize, Checksum, Direction, Faults, RingTracer, Xmodem};
+use xmodem_fuzz::{payload, Options};
+
+const LENGTHS: &[usize] = &[0, 1, 128, 300, 1024, 1500, 4096];
+
+/// Runs a session transferring a payload of `len` bytes. Returns the bytes
+/// the sender and the receiver sent.
+fn session(options: Options, len: usize) -> (Vec<u8>, Vec<u8>) {
+    let (tx, rx) = noisy_pipe(0, Faults::new());
+    let tracer = Arc::new(Mutex::new(RingTracer::new(1 << 20)));
+    let sender_tracer = tracer.clone();
+    let sender = thread::spawn(move || {
+        let mut xmodem = Xmodem::builder().block_size(options.block_size).build(rx);
+        xmodem.set_tracer(sender_tracer);
+        xmodem.send(&payload(len)[..]).expect("send okay");
+    });
+
+    let receiver = Arc::new(Mutex::new(RingTracer::new(1 << 20)));
+    let mut xmodem = Xmodem::builder().checksum(options.checksum).build(tx);
+    xmodem.set_tracer(receiver.clone());
+    xmodem.recv(io::sink()).expect("receive okay");
+    sender.join().expect("sender join okay");
+
+    let sent = tracer.lock().unwrap().bytes(Direction::Tx);
+    let replies = receiver.lock().unwrap().bytes(Direction::Tx);
+    (sent, replies)
+}
+
+fn main() -> io::Result<()> {
+    let receiver = Path::new("corpus/receiver");
+    let sender = Path::new("corpus/sender");
+    fs::create_dir_all(receiver)?;
+    fs::create_dir_all(sender)?;
+
+    let mut count = 0;
+    for &checksum in &[Checksum::Crc16, Checksum::Standard] {
+        for &block_size in &[BlockSize::Standard, BlockSize::OneK] {
+            for &len in LENGTHS {
+                let options = Options {
+                    checksum,
+                    block_size,
+                    purge: false,
+                    timeout_every: 0,
+                };
+
+                let (sent, replies) = session(options, len);
+                let name = format!("session-{}", count);
+
+                let mut input = vec![options.encode()];
+                input.extend(sent);
+                fs::write(receiver.join(&name), input)?;
+
+                let mut input = vec![options.encode(), (len >> 8) as u8, len as u8];
+                input.extend(replies);
+                fs::write(sender.join(&name), input)?;
+                count += 1;
+            }
+        }
+    }
+
+    println!("wrote {} sessions to corpus/", count);
+    Ok(())
+}
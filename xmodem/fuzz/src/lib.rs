@@ -0,0 +1,215 @@
+//! Shared harness for the `receiver` and `sender` fuzz targets.
+//!
+//! Every input starts with an options byte selecting the session's
+//! configuration (see [`Options`]); the sender target follows it with two
+//! bytes of payload length. The rest of the input is what the peer sends,
+//! byte for byte.
+
+extern crate xmodem;
+
+use std::io;
+
+use xmodem::{BlockSize, Checksum, Error, Xmodem};
+
+/// Largest payload the sender target transmits.
+pub const MAX_PAYLOAD: usize = 4096;
+
+/// Byte written to the guard area after the receive buffer.
+const GUARD: u8 = 0xA5;
+
+/// Session configuration decoded from the first byte of an input.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Options {
+    /// Bit 0: 8-bit checksum instead of CRC-16.
+    pub checksum: Checksum,
+    /// Bit 1: 1024-byte blocks instead of 128-byte ones.
+    pub block_size: BlockSize,
+    /// Bit 2: purge the line before rejecting a packet.
+    pub purge: bool,
+    /// Bits 3 to 7: every `n`th read times out, `n` being the value plus
+    /// one; never if the value is 0.
+    pub timeout_every: usize,
+}
+
+impl Options {
+    pub fn decode(byte: u8) -> Options {
+        Options {
+            checksum: match byte & 1 {
+                0 => Checksum::Crc16,
+                _ => Checksum::Standard,
+            },
+            block_size: match byte & 2 {
+                0 => BlockSize::Standard,
+                _ => BlockSize::OneK,
+            },
+            purge: byte & 4 != 0,
+            timeout_every: match byte >> 3 {
+                0 => 0,
+                n => n as usize + 1,
+            },
+        }
+    }
+
+    pub fn encode(&self) -> u8 {
+        let mut byte = 0;
+        if self.checksum == Checksum::Standard {
+            byte |= 1;
+        }
+
+        if self.block_size == BlockSize::OneK {
+            byte |= 2;
+        }
+
+        if self.purge {
+            byte |= 4;
+        }
+
+        if self.timeout_every > 1 {
+            byte |= ((self.timeout_every - 1) as u8) << 3;
+        }
+
+        byte
+    }
+}
+
+/// A peer that sends the bytes of a fuzzer input and ignores what it is sent.
+///
+/// Reads return one byte at a time, as a serial port with little buffered
+/// does, and every `timeout_every`th read times out. Once the input is
+/// exhausted, reads return end of file.
+pub struct Peer<'a> {
+    input: &'a [u8],
+    timeout_every: usize,
+    /// Number of reads so far, including those that timed out.
+    pub reads: usize,
+}
+
+impl<'a> Peer<'a> {
+    pub fn new(input: &'a [u8], timeout_every: usize) -> Peer<'a> {
+        Peer {
+            input,
+            timeout_every,
+            reads: 0,
+        }
+    }
+}
+
+impl<'a> io::Read for Peer<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reads += 1;
+        if self.timeout_every > 0 && self.reads.is_multiple_of(self.timeout_every) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        }
+
+        match (self.input.split_first(), buf.first_mut()) {
+            (Some((&byte, rest)), Some(slot)) => {
+                *slot = byte;
+                self.input = rest;
+                Ok(1)
+            }
+            _ => Ok(0),
+        }
+    }
+}
+
+impl<'a> io::Write for Peer<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the most reads a session over `input` may perform: one per byte,
+/// plus the reads that time out, plus one to reach the end of the input.
+fn max_reads(input: &[u8], timeout_every: usize) -> usize {
+    let bytes = input.len() + 1;
+    match timeout_every {
+        0 => bytes,
+        n => bytes + bytes / (n - 1) + 1,
+    }
+}
+
+/// Receives packets from a peer sending `data` until the session ends.
+///
+/// Panics if the receiver writes outside of the packet it reports, reports a
+/// packet larger than its buffer, or keeps reading without consuming input.
+pub fn receive(data: &[u8]) {
+    let (&options, input) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+
+    let options = Options::decode(options);
+    let mut xmodem = Xmodem::builder()
+        .checksum(options.checksum)
+        .purge(options.purge)
+        .handshake_retries(4)
+        .build(Peer::new(input, options.timeout_every));
+
+    let size = options.block_size.size();
+    let mut buf = [GUARD; 1024 + 16];
+    let mut ended = false;
+    for _ in 0..=input.len() {
+        buf.iter_mut().for_each(|b| *b = GUARD);
+        let result = xmodem.read_packet(&mut buf[..size]);
+        assert!(buf[size..].iter().all(|&b| b == GUARD), "guard overwritten");
+        match result {
+            Ok(0) => {}
+            Ok(n) => {
+                assert!(n == 128 || n == 1024, "packet of {} bytes", n);
+                assert!(n <= size, "{}-byte packet in {}-byte buffer", n, size);
+                assert!(
+                    buf[n..size].iter().all(|&b| b == GUARD),
+                    "wrote past packet"
+                );
+                continue;
+            }
+            Err(Error::Checksum) => continue,
+            Err(_) => {}
+        }
+
+        ended = true;
+        break;
+    }
+
+    assert!(ended, "receiver didn't end after {} bytes", input.len());
+    let reads = xmodem.into_inner().reads;
+    let max = max_reads(input, options.timeout_every);
+    assert!(reads <= max, "{} reads for {} bytes", reads, input.len());
+}
+
+/// Sends a payload to a peer sending `data` until the session ends.
+///
+/// Panics if the sender reports more bytes sent than the payload holds, or
+/// keeps reading without consuming input.
+pub fn send(data: &[u8]) {
+    if data.len() < 3 {
+        return;
+    }
+
+    let options = Options::decode(data[0]);
+    let len = (data[1] as usize) << 8 | data[2] as usize;
+    let payload = payload(len % (MAX_PAYLOAD + 1));
+    let input = &data[3..];
+
+    let mut xmodem = Xmodem::builder()
+        .block_size(options.block_size)
+        .handshake_retries(4)
+        .build(Peer::new(input, options.timeout_every));
+
+    if let Ok(n) = xmodem.send(&payload[..]) {
+        assert_eq!(n, payload.len(), "sent {} of {} bytes", n, payload.len());
+    }
+
+    let reads = xmodem.into_inner().reads;
+    let max = max_reads(input, options.timeout_every);
+    assert!(reads <= max, "{} reads for {} bytes", reads, input.len());
+}
+
+/// Returns the payload of `len` bytes the sender target transmits.
+pub fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7) as u8).collect()
+}
@@ -0,0 +1,678 @@
+use std::cmp;
+use std::future::Future;
+use std::io;
+use std::mem;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::{self, Instant, Sleep};
+
+use builder::Config;
+use {BlockSize, Checksum, Error, Event, PaddingPolicy, Receiver, Result, Sender, CAN};
+
+/// What [`AsyncXmodem::poll_input()`] produced.
+enum Input {
+    /// A byte from the other side.
+    Byte(u8),
+    /// No byte arrived within the read timeout.
+    Timeout,
+    /// The session timeout passed.
+    Expired,
+}
+
+/// Implementation of the XMODEM protocol over an asynchronous reader/writer.
+///
+/// This is the asynchronous counterpart of [`Xmodem`](::Xmodem), available with
+/// the `async` feature. It works with any stream implementing tokio's
+/// `AsyncRead` and `AsyncWrite`; `futures` streams can be adapted with
+/// `tokio-util`'s `compat` module. Reads time out after the configured
+/// [`read_timeout`](::XmodemBuilder::read_timeout) using tokio's timer, so the
+/// stream itself needs no timeout, and the futures must be polled within a
+/// tokio runtime with the time driver enabled.
+///
+/// The futures returned by [`AsyncXmodem::read_packet()`] and
+/// [`AsyncXmodem::write_packet()`] keep their progress in the session: a
+/// dropped future must be followed by a call with the same buffer.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # extern crate tokio;
+/// # extern crate xmodem;
+/// # fn f(port: tokio::io::DuplexStream) -> xmodem::Result<()> {
+/// use tokio::runtime::Builder;
+/// use xmodem::AsyncXmodem;
+///
+/// let runtime = Builder::new_current_thread().enable_time().build()?;
+/// runtime.block_on(AsyncXmodem::transmit(&b"hello"[..], port))?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct AsyncXmodem<T> {
+    inner: T,
+    receiver: Receiver,
+    sender: Sender,
+    checksum: Checksum,
+    block_size: BlockSize,
+    padding_policy: PaddingPolicy,
+    config: Config,
+    /// Bytes read from `inner` that haven't been fed to a state machine.
+    input: [u8; 64],
+    input_pos: usize,
+    input_len: usize,
+    /// Bytes waiting to be written to `inner`.
+    output: Vec<u8>,
+    output_pos: usize,
+    /// The result of the last byte fed to a state machine, handled once the
+    /// machine's response has been written.
+    event: Option<Result<Option<Event>>>,
+    /// Whether the packet passed to `poll_write_packet` was queued.
+    queued: bool,
+    timer: Option<Pin<Box<Sleep>>>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncXmodem<T> {
+    /// Returns a new `AsyncXmodem` instance with the internal reader/writer
+    /// set to `inner`, for both receiving and sending.
+    pub fn new(inner: T) -> Self {
+        AsyncXmodem::with_config(inner, Config::default())
+    }
+
+    pub(crate) fn with_config(inner: T, config: Config) -> Self {
+        AsyncXmodem {
+            inner,
+            receiver: Receiver::new(config, Checksum::Crc16),
+            sender: Sender::new(config),
+            checksum: Checksum::Crc16,
+            block_size: BlockSize::Standard,
+            padding_policy: PaddingPolicy::Keep,
+            config,
+            input: [0; 64],
+            input_pos: 0,
+            input_len: 0,
+            output: Vec::new(),
+            output_pos: 0,
+            event: None,
+            queued: false,
+            timer: None,
+            deadline: None,
+        }
+    }
+
+    /// Transmits `data` to the receiver `to`. See [`Xmodem::transmit()`].
+    ///
+    /// [`Xmodem::transmit()`]: ::Xmodem::transmit
+    pub fn transmit<R: AsyncRead + Unpin>(data: R, to: T) -> Transmit<R, T> {
+        AsyncXmodem::new(to).send(data)
+    }
+
+    /// Receives data from `from` and writes it into `into`. See
+    /// [`Xmodem::receive()`].
+    ///
+    /// [`Xmodem::receive()`]: ::Xmodem::receive
+    pub fn receive<W: AsyncWrite + Unpin>(from: T, into: W) -> Receive<T, W> {
+        AsyncXmodem::new(from).recv(into)
+    }
+
+    /// Returns the inner reader/writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns the packet checksum mode. See [`Xmodem::checksum()`].
+    ///
+    /// [`Xmodem::checksum()`]: ::Xmodem::checksum
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Sets the packet checksum mode a receiver requests from the sender. See
+    /// [`Xmodem::set_checksum()`].
+    ///
+    /// [`Xmodem::set_checksum()`]: ::Xmodem::set_checksum
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+        self.receiver.set_checksum(checksum);
+    }
+
+    /// Returns the packet size used by [`AsyncXmodem::send()`].
+    pub fn block_size(&self) -> BlockSize {
+        self.block_size
+    }
+
+    /// Sets the packet size used by [`AsyncXmodem::send()`]. See
+    /// [`Xmodem::set_block_size()`].
+    ///
+    /// [`Xmodem::set_block_size()`]: ::Xmodem::set_block_size
+    pub fn set_block_size(&mut self, block_size: BlockSize) {
+        self.block_size = block_size;
+    }
+
+    /// Sets what [`AsyncXmodem::recv()`] does with the padding at the end of
+    /// a transmission. The default is `PaddingPolicy::Keep`.
+    pub fn set_padding_policy(&mut self, policy: PaddingPolicy) {
+        self.padding_policy = policy;
+    }
+
+    /// Returns a future that transmits `data` to the receiver and resolves to
+    /// the number of bytes sent, excluding padding. See [`Xmodem::send()`].
+    ///
+    /// [`Xmodem::send()`]: ::Xmodem::send
+    pub fn send<R: AsyncRead + Unpin>(self, data: R) -> Transmit<R, T> {
+        Transmit {
+            xmodem: self,
+            data,
+            block: [0; 1024],
+            state: TransmitState::Handshake,
+            written: 0,
+        }
+    }
+
+    /// Returns a future that receives data from the sender, writes it into
+    /// `into` and resolves to the number of bytes written. See
+    /// [`Xmodem::recv()`].
+    ///
+    /// [`Xmodem::recv()`]: ::Xmodem::recv
+    pub fn recv<W: AsyncWrite + Unpin>(self, into: W) -> Receive<T, W> {
+        let limit = match self.padding_policy {
+            PaddingPolicy::Truncate(len) => len,
+            _ => u64::MAX,
+        };
+
+        Receive {
+            xmodem: self,
+            into,
+            packet: [0; 1024],
+            held: [0; 1024],
+            held_len: 0,
+            limit,
+            written: 0,
+            state: ReceiveState::Receive,
+        }
+    }
+
+    /// Returns a future that reads a single packet into `buf`. See
+    /// [`Xmodem::read_packet()`].
+    ///
+    /// [`Xmodem::read_packet()`]: ::Xmodem::read_packet
+    pub fn read_packet<'a>(&'a mut self, buf: &'a mut [u8]) -> ReadPacket<'a, T> {
+        ReadPacket { xmodem: self, buf }
+    }
+
+    /// Returns a future that sends a single packet, or the end of the
+    /// transmission if `buf` is empty. See [`Xmodem::write_packet()`].
+    ///
+    /// [`Xmodem::write_packet()`]: ::Xmodem::write_packet
+    pub fn write_packet<'a>(&'a mut self, buf: &'a [u8]) -> WritePacket<'a, T> {
+        WritePacket { xmodem: self, buf }
+    }
+
+    /// Polls for a single packet to be read into `buf`.
+    pub fn poll_read_packet(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<Result<usize>> {
+        if buf.len() < 128 {
+            return Poll::Ready(Err(Error::InvalidInput(
+                "output buffer must be at least 128 bytes",
+            )));
+        }
+
+        self.receiver.set_max_block_size(if buf.len() < 1024 {
+            BlockSize::Standard
+        } else {
+            BlockSize::OneK
+        });
+
+        self.start_session();
+        self.receiver.start();
+        loop {
+            self.output.extend_from_slice(self.receiver.take_output());
+            ready!(self.poll_output(cx))?;
+            self.checksum = self.receiver.checksum();
+            match self.event.take() {
+                Some(Ok(Some(Event::Packet(_)))) => {
+                    let packet = self.receiver.packet();
+                    buf[..packet.len()].copy_from_slice(packet);
+                    return Poll::Ready(Ok(packet.len()));
+                }
+                Some(Ok(Some(Event::Done))) => return Poll::Ready(Ok(0)),
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                _ => {}
+            }
+
+            let handshake = !self.receiver.is_started();
+            match ready!(self.poll_input(cx, handshake))? {
+                Input::Byte(byte) => self.event = Some(self.receiver.feed(byte)),
+                Input::Timeout => self.event = Some(self.receiver.timeout()),
+                Input::Expired => self.expire(),
+            }
+        }
+    }
+
+    /// Polls for the packet `buf` to be sent and acknowledged.
+    pub fn poll_write_packet(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<Result<usize>> {
+        ready!(self.poll_handshake(cx))?;
+        loop {
+            self.output.extend_from_slice(self.sender.take_output());
+            ready!(self.poll_output(cx))?;
+            match self.event.take() {
+                Some(Ok(Some(Event::Packet(_)))) => {
+                    self.queued = false;
+                    if self.sender.is_streaming() {
+                        self.feed_pending(cx)?;
+                    }
+
+                    return Poll::Ready(Ok(buf.len()));
+                }
+                Some(Ok(Some(Event::Done))) => {
+                    self.queued = false;
+                    return Poll::Ready(Ok(0));
+                }
+                Some(Ok(Some(Event::Timeout(_)))) | Some(Ok(Some(Event::Garbled(_)))) => {
+                    self.queued = false;
+                }
+                Some(Err(e)) => {
+                    self.queued = false;
+                    return Poll::Ready(Err(e));
+                }
+                _ => {}
+            }
+
+            if !self.queued {
+                self.feed_pending(cx)?;
+                self.sender.send_packet(buf)?;
+                self.queued = true;
+                self.event = self.sender.take_event().map(|event| Ok(Some(event)));
+                continue;
+            }
+
+            ready!(self.poll_sender_input(cx))?;
+        }
+    }
+
+    /// Polls for the receiver's handshake, which starts the session, if it
+    /// hasn't started yet.
+    fn poll_handshake(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        self.start_session();
+        loop {
+            self.output.extend_from_slice(self.sender.take_output());
+            ready!(self.poll_output(cx))?;
+            match self.event.take() {
+                Some(Ok(Some(Event::Started))) => self.checksum = self.sender.checksum(),
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                _ => {}
+            }
+
+            if self.sender.is_started() {
+                return Poll::Ready(Ok(()));
+            }
+
+            ready!(self.poll_sender_input(cx))?;
+        }
+    }
+
+    /// Polls for the next byte from the receiver, or a timeout, and passes it
+    /// to the sender state machine.
+    fn poll_sender_input(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        match ready!(self.poll_input(cx, false))? {
+            Input::Byte(byte) => self.event = Some(self.sender.feed(byte)),
+            Input::Timeout => self.event = Some(self.sender.timeout()),
+            Input::Expired => self.expire(),
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Passes the bytes the receiver has already sent to the sender state
+    /// machine, without waiting for more: before a packet is queued, so that
+    /// a stale response isn't taken for the response to it, and after a
+    /// packet is streamed, so that the sender notices a cancellation.
+    fn feed_pending(&mut self, cx: &mut Context) -> Result<()> {
+        loop {
+            if self.input_pos == self.input_len {
+                let mut buf = ReadBuf::new(&mut self.input);
+                match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+                    Poll::Ready(result) => result?,
+                    Poll::Pending => return Ok(()),
+                }
+
+                if buf.filled().is_empty() {
+                    return Ok(());
+                }
+
+                self.input_pos = 0;
+                self.input_len = buf.filled().len();
+            }
+
+            while self.input_pos < self.input_len {
+                let byte = self.input[self.input_pos];
+                self.input_pos += 1;
+                self.sender.feed(byte)?;
+            }
+        }
+    }
+
+    /// Returns the packet size [`AsyncXmodem::send()`] uses with the receiver.
+    /// See [`Xmodem::send()`].
+    ///
+    /// [`Xmodem::send()`]: ::Xmodem::send
+    fn negotiated_block_size(&self) -> BlockSize {
+        match self.sender.checksum() {
+            Checksum::Standard => BlockSize::Standard,
+            Checksum::Crc16 => self.block_size,
+        }
+    }
+
+    /// Starts the session timer, if it isn't running yet and a session timeout
+    /// is configured.
+    fn start_session(&mut self) {
+        if self.deadline.is_none() {
+            if let Some(timeout) = self.config.session_timeout {
+                self.deadline = Some(Box::pin(time::sleep(timeout)));
+            }
+        }
+    }
+
+    /// Aborts the session: the pending output is replaced by `CAN` bytes and
+    /// the session fails with `Error::Timeout` once they are written.
+    fn expire(&mut self) {
+        self.output.clear();
+        self.output_pos = 0;
+        self.output.resize(self.config.cancel_count, CAN);
+        self.event = Some(Err(Error::Timeout));
+    }
+
+    /// Returns how long to wait for a byte. While waiting for a sender to
+    /// respond to the handshake, the handshake interval is used, if set.
+    fn read_timeout(&self, handshake: bool) -> Duration {
+        let interval = self.config.handshake_interval;
+        if handshake && interval > Duration::from_secs(0) {
+            interval
+        } else {
+            self.config.read_timeout
+        }
+    }
+
+    /// Restarts the read timer with the timeout for the current phase.
+    fn reset_timer(&mut self, handshake: bool) {
+        let deadline = Instant::now() + self.read_timeout(handshake);
+        match self.timer {
+            Some(ref mut timer) => timer.as_mut().reset(deadline),
+            None => self.timer = Some(Box::pin(time::sleep_until(deadline))),
+        }
+    }
+
+    /// Polls for the pending output to be written and flushed. The read timer
+    /// restarts once it has been.
+    fn poll_output(&mut self, cx: &mut Context) -> Poll<Result<()>> {
+        if self.output.is_empty() {
+            return Poll::Ready(Ok(()));
+        }
+
+        while self.output_pos < self.output.len() {
+            let pending = &self.output[self.output_pos..];
+            match ready!(Pin::new(&mut self.inner).poll_write(cx, pending))? {
+                0 => return Poll::Ready(Err(io::Error::from(io::ErrorKind::WriteZero).into())),
+                n => self.output_pos += n,
+            }
+        }
+
+        ready!(Pin::new(&mut self.inner).poll_flush(cx))?;
+        self.output.clear();
+        self.output_pos = 0;
+        self.timer = None;
+        Poll::Ready(Ok(()))
+    }
+
+    /// Polls for the next byte from the other side, or for the read or session
+    /// timeout to pass.
+    fn poll_input(&mut self, cx: &mut Context, handshake: bool) -> Poll<Result<Input>> {
+        if let Some(ref mut deadline) = self.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Ok(Input::Expired));
+            }
+        }
+
+        if self.input_pos == self.input_len {
+            if self.timer.is_none() {
+                self.reset_timer(handshake);
+            }
+
+            let mut buf = ReadBuf::new(&mut self.input);
+            match Pin::new(&mut self.inner).poll_read(cx, &mut buf) {
+                Poll::Ready(result) => {
+                    result?;
+                    if buf.filled().is_empty() {
+                        let eof = io::Error::from(io::ErrorKind::UnexpectedEof);
+                        return Poll::Ready(Err(eof.into()));
+                    }
+
+                    self.input_pos = 0;
+                    self.input_len = buf.filled().len();
+                }
+                Poll::Pending => {
+                    let timer = self.timer.as_mut().expect("read timer");
+                    ready!(timer.as_mut().poll(cx));
+                    self.timer = None;
+                    return Poll::Ready(Ok(Input::Timeout));
+                }
+            }
+        }
+
+        let byte = self.input[self.input_pos];
+        self.input_pos += 1;
+        self.timer = None;
+        Poll::Ready(Ok(Input::Byte(byte)))
+    }
+}
+
+/// Future returned by [`AsyncXmodem::read_packet()`].
+pub struct ReadPacket<'a, T: 'a> {
+    xmodem: &'a mut AsyncXmodem<T>,
+    buf: &'a mut [u8],
+}
+
+impl<'a, T: AsyncRead + AsyncWrite + Unpin> Future for ReadPacket<'a, T> {
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.xmodem.poll_read_packet(cx, this.buf)
+    }
+}
+
+/// Future returned by [`AsyncXmodem::write_packet()`].
+pub struct WritePacket<'a, T: 'a> {
+    xmodem: &'a mut AsyncXmodem<T>,
+    buf: &'a [u8],
+}
+
+impl<'a, T: AsyncRead + AsyncWrite + Unpin> Future for WritePacket<'a, T> {
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        this.xmodem.poll_write_packet(cx, this.buf)
+    }
+}
+
+enum TransmitState {
+    /// Waiting for the receiver's handshake, which picks the packet size.
+    Handshake,
+    /// Reading the next block; `.0` bytes have been read.
+    Read(usize),
+    /// Sending `len` bytes of the block, padded, in `size`-byte packets. The
+    /// packets before `offset` have been sent.
+    Send {
+        len: usize,
+        size: usize,
+        offset: usize,
+    },
+    /// Sending the end of the transmission.
+    Eot,
+}
+
+/// Future returned by [`AsyncXmodem::transmit()`] and [`AsyncXmodem::send()`].
+pub struct Transmit<R, W> {
+    xmodem: AsyncXmodem<W>,
+    data: R,
+    block: [u8; 1024],
+    state: TransmitState,
+    written: usize,
+}
+
+impl<R, W> Future for Transmit<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncRead + AsyncWrite + Unpin,
+{
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.state {
+                TransmitState::Handshake => {
+                    ready!(this.xmodem.poll_handshake(cx))?;
+                    this.state = TransmitState::Read(0);
+                }
+                TransmitState::Read(filled) => {
+                    let block_size = this.xmodem.negotiated_block_size().size();
+                    if filled < block_size {
+                        let mut buf = ReadBuf::new(&mut this.block[filled..block_size]);
+                        ready!(Pin::new(&mut this.data).poll_read(cx, &mut buf))?;
+                        let n = buf.filled().len();
+                        if n > 0 {
+                            this.state = TransmitState::Read(filled + n);
+                            continue;
+                        }
+                    }
+
+                    if filled == 0 {
+                        this.state = TransmitState::Eot;
+                        continue;
+                    }
+
+                    let size = this.xmodem.negotiated_block_size().packet_size(filled);
+                    let padded = filled.div_ceil(size) * size;
+                    let padding = this.xmodem.config.padding;
+                    this.block[filled..padded]
+                        .iter_mut()
+                        .for_each(|b| *b = padding);
+                    this.state = TransmitState::Send {
+                        len: filled,
+                        size,
+                        offset: 0,
+                    };
+                }
+                TransmitState::Send { len, size, offset } => {
+                    let packet = &this.block[offset..offset + size];
+                    match ready!(this.xmodem.poll_write_packet(cx, packet)) {
+                        Err(Error::Checksum) => continue,
+                        Err(e) => return Poll::Ready(Err(e)),
+                        Ok(_) => {}
+                    }
+
+                    let offset = offset + size;
+                    if offset < len {
+                        this.state = TransmitState::Send { len, size, offset };
+                    } else {
+                        this.written += len;
+                        this.state = TransmitState::Read(0);
+                    }
+                }
+                TransmitState::Eot => {
+                    match ready!(this.xmodem.poll_write_packet(cx, &[])) {
+                        Err(Error::Checksum) => continue,
+                        result => result?,
+                    };
+
+                    return Poll::Ready(Ok(this.written));
+                }
+            }
+        }
+    }
+}
+
+enum ReceiveState {
+    /// Receiving the next packet.
+    Receive,
+    /// Writing the bytes of `packet` before `len`, of which those before
+    /// `pos` have been written. `last` is set at the end of the transmission.
+    Write { pos: usize, len: usize, last: bool },
+}
+
+/// Future returned by [`AsyncXmodem::receive()`] and [`AsyncXmodem::recv()`].
+pub struct Receive<R, W> {
+    xmodem: AsyncXmodem<R>,
+    into: W,
+    packet: [u8; 1024],
+    /// The packet received last, which is written once the next one arrives.
+    held: [u8; 1024],
+    held_len: usize,
+    limit: u64,
+    written: usize,
+    state: ReceiveState,
+}
+
+impl<R, W> Future for Receive<R, W>
+where
+    R: AsyncRead + AsyncWrite + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        loop {
+            match this.state {
+                ReceiveState::Receive => {
+                    let n = match ready!(this.xmodem.poll_read_packet(cx, &mut this.packet)) {
+                        Err(Error::Checksum) => continue,
+                        result => result?,
+                    };
+
+                    mem::swap(&mut this.packet, &mut this.held);
+                    let len = match n {
+                        0 => this
+                            .xmodem
+                            .padding_policy
+                            .strip(&this.packet[..this.held_len]),
+                        _ => this.held_len,
+                    };
+
+                    let remaining = this.limit - this.written as u64;
+                    this.held_len = n;
+                    this.state = ReceiveState::Write {
+                        pos: 0,
+                        len: cmp::min(len as u64, remaining) as usize,
+                        last: n == 0,
+                    };
+                }
+                ReceiveState::Write { pos, len, last } => {
+                    if pos < len {
+                        let buf = &this.packet[pos..len];
+                        let n = ready!(Pin::new(&mut this.into).poll_write(cx, buf))?;
+                        if n == 0 {
+                            let e = io::Error::from(io::ErrorKind::WriteZero);
+                            return Poll::Ready(Err(e.into()));
+                        }
+
+                        this.written += n;
+                        this.state = ReceiveState::Write {
+                            pos: pos + n,
+                            len,
+                            last,
+                        };
+                    } else if last {
+                        ready!(Pin::new(&mut this.into).poll_flush(cx))?;
+                        return Poll::Ready(Ok(this.written));
+                    } else {
+                        this.state = ReceiveState::Receive;
+                    }
+                }
+            }
+        }
+    }
+}
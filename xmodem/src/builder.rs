@@ -0,0 +1,342 @@
+use core::fmt;
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::io;
+
+#[cfg(feature = "std")]
+use progress::ProgressCallback;
+#[cfg(feature = "async")]
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "async")]
+use AsyncXmodem;
+#[cfg(feature = "std")]
+use Digest;
+use {BlockSize, Checksum, PaddingPolicy, Receiver, Sender};
+#[cfg(feature = "std")]
+use {Result, Status, TransferStats, Xmodem};
+
+/// Retry, timeout and cancellation policy of an [`Xmodem`] session.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct Config {
+    /// Number of times a packet is sent or received before giving up.
+    pub retries: usize,
+    /// Number of times a receiver sends its `'C'`/`NAK` handshake, and a
+    /// sender waits for one, before giving up.
+    pub handshake_retries: usize,
+    /// Minimum time between two handshake bytes sent by a receiver.
+    pub handshake_interval: Duration,
+    /// Maximum duration of a session, if any.
+    pub session_timeout: Option<Duration>,
+    /// How long an asynchronous session waits for a byte.
+    #[cfg(feature = "async")]
+    pub read_timeout: Duration,
+    /// Number of `CAN` bytes written to abort a session.
+    pub cancel_count: usize,
+    /// Byte used to pad the last packet of a transmission.
+    pub padding: u8,
+    /// Whether a receiver waits for the line to be quiet before rejecting a
+    /// corrupted packet.
+    pub purge: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            retries: 10,
+            handshake_retries: 10,
+            handshake_interval: Duration::from_secs(0),
+            session_timeout: None,
+            #[cfg(feature = "async")]
+            read_timeout: Duration::from_secs(10),
+            cancel_count: 2,
+            padding: 0,
+            purge: false,
+        }
+    }
+}
+
+/// Builder for [`Xmodem`] sessions, and [`Receiver`] and [`Sender`] state
+/// machines, with a custom configuration.
+///
+/// Read timeouts are those of the underlying stream: a read that times out
+/// with an error of `TimedOut` or `WouldBlock` counts as one retry. The
+/// handshake interval and session timeout are only enforced by [`Xmodem`] and,
+/// with the `async` feature, `AsyncXmodem`, which times reads itself; drivers
+/// of the state machines keep time themselves.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::io;
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use std::time::Duration;
+/// use xmodem::Xmodem;
+///
+/// let n = Xmodem::builder()
+///     .handshake_retries(60)
+///     .handshake_interval(Duration::from_secs(1))
+///     .session_timeout(Duration::from_secs(120))
+///     .cancel_count(5)
+///     .progress(|status| {
+///         if let Some(fraction) = status.fraction() {
+///             eprint!("\r{:3.0}%", fraction * 100.0);
+///         }
+///     })
+///     .total(5)
+///     .transmit(&b"hello"[..], port)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct XmodemBuilder {
+    config: Config,
+    checksum: Checksum,
+    block_size: BlockSize,
+    padding_policy: PaddingPolicy,
+    total: Option<u64>,
+    #[cfg(feature = "std")]
+    progress: Option<ProgressCallback>,
+    #[cfg(feature = "std")]
+    verify: Option<Digest>,
+}
+
+impl fmt::Debug for XmodemBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut f = f.debug_struct("XmodemBuilder");
+        f.field("config", &self.config)
+            .field("checksum", &self.checksum)
+            .field("block_size", &self.block_size)
+            .field("padding_policy", &self.padding_policy)
+            .field("total", &self.total);
+        #[cfg(feature = "std")]
+        f.field("verify", &self.verify);
+        f.finish()
+    }
+}
+
+impl Default for XmodemBuilder {
+    fn default() -> XmodemBuilder {
+        XmodemBuilder::new()
+    }
+}
+
+impl XmodemBuilder {
+    /// Returns a builder with the default configuration.
+    pub fn new() -> XmodemBuilder {
+        XmodemBuilder {
+            config: Config::default(),
+            checksum: Checksum::Crc16,
+            block_size: BlockSize::Standard,
+            padding_policy: PaddingPolicy::Keep,
+            total: None,
+            #[cfg(feature = "std")]
+            progress: None,
+            #[cfg(feature = "std")]
+            verify: None,
+        }
+    }
+
+    /// Sets the number of times a packet is sent (or waited for, when
+    /// receiving) before the transfer fails: with `Error::RetriesExhausted` if
+    /// the packet kept being rejected or arriving corrupted, or with
+    /// `Error::Timeout` if the other side stopped responding. The default is
+    /// 10.
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.config.retries = retries;
+        self
+    }
+
+    /// Sets the number of times a receiver sends its `'C'` or `NAK` to start
+    /// the session before giving up with `Error::Timeout`. A sender waits for
+    /// the same number of read timeouts for the handshake. The default is 10.
+    pub fn handshake_retries(mut self, retries: usize) -> Self {
+        self.config.handshake_retries = retries;
+        self
+    }
+
+    /// Sets the minimum time between two handshake bytes sent by a receiver.
+    /// Read timeouts shorter than `interval` are waited out before the
+    /// handshake is resent. The default is zero: the handshake is resent after
+    /// every read timeout.
+    pub fn handshake_interval(mut self, interval: Duration) -> Self {
+        self.config.handshake_interval = interval;
+        self
+    }
+
+    /// Sets the maximum duration of a session, from the start of the
+    /// handshake. A session that takes longer is cancelled and fails with
+    /// `Error::Timeout`. By default, sessions can take any amount of time.
+    pub fn session_timeout(mut self, timeout: Duration) -> Self {
+        self.config.session_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how long an [`AsyncXmodem`](::AsyncXmodem) session waits for a
+    /// byte before counting a retry. Blocking sessions use the read timeout of
+    /// their stream instead. The default is 10 seconds.
+    #[cfg(feature = "async")]
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// Sets the number of `CAN` bytes written to abort a session. Sessions
+    /// are only aborted by two consecutive `CAN` bytes, so that a single
+    /// `CAN` can be line noise, and `lrzsz` sends five or more; the default
+    /// is 2.
+    pub fn cancel_count(mut self, count: usize) -> Self {
+        self.config.cancel_count = count;
+        self
+    }
+
+    /// Sets the byte the last packet of a transmission is padded with. The
+    /// default is 0; CP/M and many receivers expect `0x1A` (`SUB`).
+    pub fn padding(mut self, padding: u8) -> Self {
+        self.config.padding = padding;
+        self
+    }
+
+    /// Sets whether a receiver purges the line before rejecting a corrupted
+    /// packet: the rest of the packet is discarded until a read times out, and
+    /// only then is the `NAK` sent, so the sender's retransmission can't be
+    /// mistaken for leftovers of the corrupted packet. The inner stream must
+    /// have a read timeout. The default is `false`: the `NAK` is sent right
+    /// away and leftovers are skipped as noise.
+    pub fn purge(mut self, purge: bool) -> Self {
+        self.config.purge = purge;
+        self
+    }
+
+    /// Sets the packet checksum mode a receiver requests. The default is
+    /// `Checksum::Crc16`. See [`Xmodem::set_checksum()`].
+    pub fn checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = checksum;
+        self
+    }
+
+    /// Sets the packet size used for transmission. The default is
+    /// `BlockSize::Standard`. See [`Xmodem::set_block_size()`].
+    pub fn block_size(mut self, block_size: BlockSize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    /// Sets what a receiver does with the padding at the end of a
+    /// transmission. The default is `PaddingPolicy::Keep`. See
+    /// [`Xmodem::set_padding_policy()`].
+    pub fn padding_policy(mut self, policy: PaddingPolicy) -> Self {
+        self.padding_policy = policy;
+        self
+    }
+
+    /// Sets the number of bytes a transmission is expected to carry, reported
+    /// to the progress closure. By default, the total is unknown.
+    pub fn total(mut self, total: u64) -> Self {
+        self.total = Some(total);
+        self
+    }
+
+    /// Sets the progress closure. It is called with a [`Status`] for every
+    /// [`Progress`](::Progress) event.
+    #[cfg(feature = "std")]
+    pub fn progress<F: FnMut(&Status) + Send + 'static>(mut self, f: F) -> Self {
+        self.progress = Some(Box::new(f));
+        self
+    }
+
+    /// Verifies the whole transfer with `digest` once the data is sent or
+    /// received. The peer must verify with the same digest. See [`Digest`]
+    /// and [`Xmodem::set_verify()`].
+    #[cfg(feature = "std")]
+    pub fn verify(mut self, digest: Digest) -> Self {
+        self.verify = Some(digest);
+        self
+    }
+
+    /// Returns a receiver state machine with this configuration.
+    pub fn receiver(&self) -> Receiver {
+        Receiver::new(self.config, self.checksum)
+    }
+
+    /// Returns a sender state machine with this configuration.
+    pub fn sender(&self) -> Sender {
+        Sender::new(self.config)
+    }
+
+    /// Returns a new `Xmodem` instance with this configuration and the
+    /// internal reader/writer set to `inner`.
+    #[cfg(feature = "std")]
+    pub fn build<T: io::Read + io::Write>(self, inner: T) -> Xmodem<T> {
+        let progress = self.progress.unwrap_or_else(|| Box::new(|_: &Status| {}));
+        let mut xmodem = Xmodem::with_config(inner, self.config, progress);
+        xmodem.set_checksum(self.checksum);
+        xmodem.set_block_size(self.block_size);
+        xmodem.set_padding_policy(self.padding_policy);
+        xmodem.set_total(self.total);
+        xmodem.set_verify(self.verify);
+        xmodem
+    }
+
+    /// Returns a new [`AsyncXmodem`](::AsyncXmodem) instance with this
+    /// configuration and the internal reader/writer set to `inner`. Progress
+    /// is not reported for asynchronous sessions.
+    #[cfg(feature = "async")]
+    pub fn build_async<T>(self, inner: T) -> AsyncXmodem<T>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut xmodem = AsyncXmodem::with_config(inner, self.config);
+        xmodem.set_checksum(self.checksum);
+        xmodem.set_block_size(self.block_size);
+        xmodem.set_padding_policy(self.padding_policy);
+        xmodem
+    }
+
+    /// Transmits `data` to the receiver `to` with this configuration. See
+    /// [`Xmodem::transmit()`].
+    #[cfg(feature = "std")]
+    pub fn transmit<R, W>(self, data: R, to: W) -> Result<usize>
+    where
+        W: io::Read + io::Write,
+        R: io::Read,
+    {
+        self.build(to).send(data)
+    }
+
+    /// Receives data from `from` with this configuration and writes it into
+    /// `into`. See [`Xmodem::receive()`].
+    #[cfg(feature = "std")]
+    pub fn receive<R, W>(self, from: R, into: W) -> Result<usize>
+    where
+        R: io::Read + io::Write,
+        W: io::Write,
+    {
+        self.build(from).recv(into)
+    }
+
+    /// Transmits `data` to the receiver `to` with this configuration, and
+    /// returns the statistics of the session. See [`TransferStats`].
+    #[cfg(feature = "std")]
+    pub fn transmit_with_stats<R, W>(self, data: R, to: W) -> Result<TransferStats>
+    where
+        W: io::Read + io::Write,
+        R: io::Read,
+    {
+        let mut xmodem = self.build(to);
+        xmodem.send(data)?;
+        Ok(xmodem.stats())
+    }
+
+    /// Receives data from `from` with this configuration and writes it into
+    /// `into`, and returns the statistics of the session. See
+    /// [`TransferStats`].
+    #[cfg(feature = "std")]
+    pub fn receive_with_stats<R, W>(self, from: R, into: W) -> Result<TransferStats>
+    where
+        R: io::Read + io::Write,
+        W: io::Write,
+    {
+        let mut xmodem = self.build(from);
+        xmodem.recv(into)?;
+        Ok(xmodem.stats())
+    }
+}
@@ -0,0 +1,185 @@
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+
+use checksum::crc32_update;
+use read_ext::ReadExt;
+use {Error, Result};
+
+/// How far a transfer got, so that it can be resumed after it failed.
+///
+/// A sender's checkpoint covers the packets the receiver acknowledged; a
+/// receiver's covers the packets it wrote out, including the packet it holds
+/// back when the session fails. The two agree, or the sender's is one packet
+/// behind when the receiver's last `ACK` was lost, which
+/// [`Xmodem::resume()`](::Xmodem::resume) allows for.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::{Checkpoint, Xmodem};
+///
+/// let checkpoint = Checkpoint::load("kernel.img.checkpoint")?;
+/// let mut file = File::open("kernel.img")?;
+/// checkpoint.verify(&mut file)?;
+///
+/// let mut xmodem = Xmodem::new(port);
+/// xmodem.resume(checkpoint);
+/// if let Err(e) = xmodem.send(file) {
+///     xmodem.checkpoint().save("kernel.img.checkpoint")?;
+///     return Err(e);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    packets: u64,
+    bytes: u64,
+    /// The raw CRC-32 register, inverted when reported.
+    crc: u32,
+}
+
+impl Default for Checkpoint {
+    fn default() -> Checkpoint {
+        Checkpoint::new()
+    }
+}
+
+/// First line of a saved checkpoint.
+const MAGIC: &str = "xmodem checkpoint";
+
+impl Checkpoint {
+    /// Returns the checkpoint at the start of a transfer.
+    pub fn new() -> Checkpoint {
+        Checkpoint {
+            packets: 0,
+            bytes: 0,
+            crc: !0,
+        }
+    }
+
+    /// Returns the number of packets the checkpoint covers.
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Returns the number of payload bytes the checkpoint covers, excluding
+    /// padding.
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Returns the CRC-32 of the payload bytes the checkpoint covers.
+    pub fn crc32(&self) -> u32 {
+        !self.crc
+    }
+
+    /// Returns the number of the packet following the checkpoint.
+    pub fn next_packet(&self) -> u8 {
+        self.packets.wrapping_add(1) as u8
+    }
+
+    /// Records that a packet carrying `data` was transferred.
+    pub(crate) fn commit(&mut self, data: &[u8]) {
+        self.packets += 1;
+        self.bytes += data.len() as u64;
+        self.crc = crc32_update(self.crc, data);
+    }
+
+    /// Reads the bytes the checkpoint covers from `data` and checks that they
+    /// are the ones transferred. On success, `data` is positioned right after
+    /// them, where a resumed transfer continues.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if `data` ends early or its bytes don't
+    /// match, and any error reading `data`.
+    pub fn verify<R: Read>(&self, mut data: R) -> Result<()> {
+        let mut buf = [0u8; 4096];
+        let mut crc = !0;
+        let mut left = self.bytes;
+        while left > 0 {
+            let n = left.min(buf.len() as u64) as usize;
+            if data.read_max(&mut buf[..n])? < n {
+                return Err(Error::InvalidInput("data is shorter than the checkpoint"));
+            }
+
+            crc = crc32_update(crc, &buf[..n]);
+            left -= n as u64;
+        }
+
+        if crc != self.crc {
+            return Err(Error::InvalidInput("data doesn't match the checkpoint"));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the checkpoint to `out` in a line-based text format.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", MAGIC)?;
+        writeln!(out, "packets {}", self.packets)?;
+        writeln!(out, "bytes {}", self.bytes)?;
+        writeln!(out, "crc32 {:08x}", self.crc32())
+    }
+
+    /// Reads a checkpoint written by [`Checkpoint::write_to()`].
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if the input isn't a checkpoint, and any
+    /// error reading `input`.
+    pub fn read_from<R: BufRead>(input: R) -> Result<Checkpoint> {
+        const MALFORMED: Error = Error::InvalidInput("malformed checkpoint");
+
+        let mut lines = input.lines();
+        if lines.next().transpose()?.as_deref() != Some(MAGIC) {
+            return Err(MALFORMED);
+        }
+
+        let mut field = |name: &str, radix: u32| -> Result<u64> {
+            let line = lines.next().transpose()?.ok_or(MALFORMED)?;
+            match line.split_once(' ') {
+                Some((key, value)) if key == name => {
+                    u64::from_str_radix(value, radix).map_err(|_| MALFORMED)
+                }
+                _ => Err(MALFORMED),
+            }
+        };
+
+        let packets = field("packets", 10)?;
+        let bytes = field("bytes", 10)?;
+        let crc32 = field("crc32", 16)?;
+        if crc32 > u32::MAX as u64 {
+            return Err(MALFORMED);
+        }
+
+        Ok(Checkpoint {
+            packets,
+            bytes,
+            crc: !(crc32 as u32),
+        })
+    }
+
+    /// Saves the checkpoint to the file at `path`. The file is replaced
+    /// atomically, so it holds either the previous checkpoint or this one.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+
+        let mut file = fs::File::create(&temporary)?;
+        self.write_to(&mut file)?;
+        file.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Loads a checkpoint saved with [`Checkpoint::save()`].
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Checkpoint> {
+        let file = fs::File::open(path)?;
+        Checkpoint::read_from(io::BufReader::new(file))
+    }
+}
@@ -0,0 +1,80 @@
+/// The integrity check appended to every XMODEM packet.
+///
+/// The receiver chooses the mode when it starts the session: a `NAK` requests
+/// the original 8-bit additive checksum while a `'C'` requests a 16-bit CRC.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Checksum {
+    /// One byte: the sum of all payload bytes, modulo 256.
+    Standard,
+    /// Two bytes: CRC-16/XMODEM of the payload, most significant byte first.
+    Crc16,
+}
+
+impl Checksum {
+    /// Returns the number of trailer bytes this mode appends to a packet.
+    pub fn size(&self) -> usize {
+        match *self {
+            Checksum::Standard => 1,
+            Checksum::Crc16 => 2,
+        }
+    }
+
+    /// Computes the trailer for `data` and writes it into the first
+    /// [`Checksum::size()`] bytes of `out`.
+    pub(crate) fn compute(&self, data: &[u8], out: &mut [u8]) {
+        match *self {
+            Checksum::Standard => out[0] = checksum(data),
+            Checksum::Crc16 => {
+                let crc = crc16(data);
+                out[0] = (crc >> 8) as u8;
+                out[1] = crc as u8;
+            }
+        }
+    }
+}
+
+/// Returns the 8-bit additive checksum of `data`.
+pub(crate) fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |a: u8, b| a.wrapping_add(*b))
+}
+
+/// Returns the CRC-16/XMODEM (polynomial `0x1021`, initial value `0`) of
+/// `data`.
+pub(crate) fn crc16(data: &[u8]) -> u16 {
+    crc16_update(0, data)
+}
+
+/// Continues the CRC-16/XMODEM computation `crc` with the bytes in `data`.
+pub(crate) fn crc16_update(crc: u16, data: &[u8]) -> u16 {
+    data.iter().fold(crc, |mut crc, &byte| {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+
+        crc
+    })
+}
+
+/// Continues the CRC-32 (ISO-HDLC, as used by ZMODEM and zlib) computation
+/// `crc` with the bytes in `data`. `crc` is the raw register: start with `!0`
+/// and invert the final value.
+#[cfg(feature = "std")]
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |mut crc, &byte| {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    })
+}
@@ -0,0 +1,255 @@
+use checkpoint::Checkpoint;
+use {Error, Result};
+
+/// The whole-file digest a sender announces after a transfer, when
+/// verification is enabled with
+/// [`XmodemBuilder::verify()`](::XmodemBuilder::verify).
+///
+/// After the end of the transmission, the receiver starts a second exchange,
+/// as YMODEM does between files, and the sender answers with a single 128-byte
+/// packet numbered 0 announcing the length of the data and its digest. The
+/// receiver acknowledges the packet and compares; on a mismatch it cancels the
+/// session instead of acknowledging the final `EOT`, and both sides fail with
+/// `Error::Verification`.
+///
+/// The announced length also settles the padding of the last packet: a
+/// verifying receiver writes exactly the announced number of bytes, whatever
+/// its [`PaddingPolicy`](::PaddingPolicy).
+///
+/// Both sides must enable verification with the same digest: a peer that
+/// doesn't support it ends the session after the first `EOT`, and the other
+/// side fails with `Error::Timeout`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Digest {
+    /// CRC-32 (ISO-HDLC, as used by ZMODEM and zlib). Cheap, and carried over
+    /// by [`Xmodem::resume()`](::Xmodem::resume).
+    Crc32,
+    /// SHA-256. Can't be used to verify a resumed transfer.
+    Sha256,
+}
+
+impl Digest {
+    /// Returns the identifier of this digest in a verification block.
+    fn id(&self) -> u8 {
+        match *self {
+            Digest::Crc32 => 1,
+            Digest::Sha256 => 2,
+        }
+    }
+
+    /// Returns the number of bytes in a digest value.
+    fn size(&self) -> usize {
+        match *self {
+            Digest::Crc32 => 4,
+            Digest::Sha256 => 32,
+        }
+    }
+}
+
+/// First bytes of a verification block.
+const MAGIC: &[u8; 8] = b"XMVERIFY";
+
+/// Computes a [`Digest`] of the data transferred.
+#[derive(Clone)]
+pub(crate) struct Hasher {
+    digest: Digest,
+    sha256: Sha256,
+}
+
+impl Hasher {
+    pub(crate) fn new(digest: Digest) -> Hasher {
+        Hasher {
+            digest,
+            sha256: Sha256::new(),
+        }
+    }
+
+    /// Returns `true` if the digest can cover the data in `checkpoint`. A
+    /// SHA-256 can't be carried over from an earlier session.
+    pub(crate) fn covers(&self, checkpoint: &Checkpoint) -> bool {
+        self.digest == Digest::Crc32 || self.sha256.total == checkpoint.bytes()
+    }
+
+    /// Adds `data` to the digest. The CRC-32 is kept by the checkpoint.
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        if self.digest == Digest::Sha256 {
+            self.sha256.update(data);
+        }
+    }
+
+    /// Returns the verification block for the data covered by `checkpoint`,
+    /// which `update()` has been called with.
+    pub(crate) fn block(&self, checkpoint: &Checkpoint) -> [u8; 128] {
+        let mut block = [0u8; 128];
+        block[..8].copy_from_slice(MAGIC);
+        block[8] = self.digest.id();
+        block[9..17].copy_from_slice(&checkpoint.bytes().to_be_bytes());
+        let size = self.digest.size();
+        block[17..17 + size].copy_from_slice(&self.value(checkpoint)[..size]);
+        block
+    }
+
+    /// Checks the verification block `block` against the data covered by
+    /// `checkpoint`, followed by `tail`, a prefix of which completes the
+    /// announced length. Returns the length of that prefix.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if `block` isn't a verification block, and
+    /// `Error::Verification` if it announces another digest or the data
+    /// doesn't match.
+    pub(crate) fn check(
+        &self,
+        block: &[u8],
+        checkpoint: &Checkpoint,
+        tail: &[u8],
+    ) -> Result<usize> {
+        if block.len() < 128 || &block[..8] != MAGIC {
+            return Err(Error::Protocol("expected a verification block"));
+        }
+
+        if block[8] != self.digest.id() {
+            return Err(Error::Verification);
+        }
+
+        let mut length = [0u8; 8];
+        length.copy_from_slice(&block[9..17]);
+        let length = u64::from_be_bytes(length);
+        let extra = match length.checked_sub(checkpoint.bytes()) {
+            Some(extra) if extra <= tail.len() as u64 => extra as usize,
+            _ => return Err(Error::Verification),
+        };
+
+        let mut whole = *checkpoint;
+        whole.commit(&tail[..extra]);
+        let mut hasher = self.clone();
+        hasher.update(&tail[..extra]);
+
+        let size = self.digest.size();
+        if hasher.value(&whole)[..size] != block[17..17 + size] {
+            return Err(Error::Verification);
+        }
+
+        Ok(extra)
+    }
+
+    /// Returns the digest value, in the first `digest.size()` bytes.
+    fn value(&self, checkpoint: &Checkpoint) -> [u8; 32] {
+        let mut value = [0u8; 32];
+        match self.digest {
+            Digest::Crc32 => value[..4].copy_from_slice(&checkpoint.crc32().to_be_bytes()),
+            Digest::Sha256 => value = self.sha256.clone().finish(),
+        }
+
+        value
+    }
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// SHA-256, as specified by FIPS 180-4.
+#[derive(Clone)]
+pub(crate) struct Sha256 {
+    state: [u32; 8],
+    block: [u8; 64],
+    /// Number of bytes in `block`.
+    len: usize,
+    /// Number of bytes hashed in total.
+    total: u64,
+}
+
+impl Sha256 {
+    pub(crate) fn new() -> Sha256 {
+        Sha256 {
+            state: [
+                0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+                0x5be0cd19,
+            ],
+            block: [0; 64],
+            len: 0,
+            total: 0,
+        }
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        self.total += data.len() as u64;
+        while !data.is_empty() {
+            let n = (64 - self.len).min(data.len());
+            self.block[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+            if self.len == 64 {
+                self.compress();
+                self.len = 0;
+            }
+        }
+    }
+
+    pub(crate) fn finish(mut self) -> [u8; 32] {
+        let bits = self.total * 8;
+        self.update(&[0x80]);
+        while self.len != 56 {
+            self.update(&[0]);
+        }
+
+        self.update(&bits.to_be_bytes());
+        let mut out = [0u8; 32];
+        for (chunk, word) in out.chunks_mut(4).zip(self.state.iter()) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+
+        out
+    }
+
+    fn compress(&mut self) {
+        let mut w = [0u32; 64];
+        for (i, chunk) in self.block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        }
+
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip(&[a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(*value);
+        }
+    }
+}
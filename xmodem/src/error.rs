@@ -0,0 +1,125 @@
+use core::fmt;
+use core::result;
+#[cfg(feature = "std")]
+use std::error;
+#[cfg(feature = "std")]
+use std::io;
+
+/// Errors returned by XMODEM, YMODEM, ZMODEM and Kermit transfers.
+///
+/// With the `std` feature, an `Error` converts to and from an `io::Error`, so
+/// transfers can be used from functions returning `io::Result`. Converting an
+/// `Error` into an `io::Error` and back yields the original `Error`.
+#[derive(Debug)]
+pub enum Error {
+    /// A packet was corrupted in transit: its checksum or CRC didn't match
+    /// its contents.
+    Checksum,
+    /// A packet was received with a packet number that is neither the
+    /// expected one nor that of the previous packet.
+    PacketNumber {
+        /// The number of the packet that was expected.
+        expected: u8,
+        /// The number of the packet that was received.
+        received: u8,
+    },
+    /// The other side cancelled the session.
+    Cancelled,
+    /// The other side didn't respond in time.
+    Timeout,
+    /// A packet or frame was retried too many times without success.
+    RetriesExhausted,
+    /// The data received doesn't match the length and digest the sender
+    /// announced after the transfer.
+    Verification,
+    /// The other side sent something the protocol doesn't allow at this
+    /// point. The message describes what was expected.
+    Protocol(&'static str),
+    /// An argument, such as a buffer or a file name, is unsuitable for the
+    /// requested operation.
+    InvalidInput(&'static str),
+    /// Reading from or writing to the underlying stream or file failed.
+    #[cfg(feature = "std")]
+    Io(io::Error),
+}
+
+/// Type alias for results of transfers.
+pub type Result<T> = result::Result<T, Error>;
+
+#[cfg(feature = "std")]
+impl Error {
+    /// Returns the `io::ErrorKind` this error converts to.
+    pub fn kind(&self) -> io::ErrorKind {
+        match *self {
+            Error::Checksum
+            | Error::PacketNumber { .. }
+            | Error::Verification
+            | Error::Protocol(_) => io::ErrorKind::InvalidData,
+            Error::Cancelled => io::ErrorKind::ConnectionAborted,
+            Error::Timeout => io::ErrorKind::TimedOut,
+            Error::RetriesExhausted => io::ErrorKind::BrokenPipe,
+            Error::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            Error::Io(ref e) => e.kind(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Checksum => write!(f, "packet checksum mismatch"),
+            Error::PacketNumber { expected, received } => write!(
+                f,
+                "expected packet {}, received packet {}",
+                expected, received
+            ),
+            Error::Cancelled => write!(f, "transfer cancelled by peer"),
+            Error::Timeout => write!(f, "timed out waiting for peer"),
+            Error::RetriesExhausted => write!(f, "too many retries"),
+            Error::Verification => write!(f, "received data failed verification"),
+            Error::Protocol(msg) => write!(f, "protocol error: {}", msg),
+            Error::InvalidInput(msg) => write!(f, "invalid input: {}", msg),
+            #[cfg(feature = "std")]
+            Error::Io(ref e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<io::Error> for Error {
+    /// Converts `e` into an `Error`. Timeouts become `Error::Timeout` and an
+    /// `io::Error` created from an `Error` is unwrapped.
+    fn from(e: io::Error) -> Error {
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            let inner = e.into_inner().expect("inner error");
+            return *inner.downcast::<Error>().expect("xmodem error");
+        }
+
+        match e.kind() {
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for io::Error {
+    /// Converts `e` into an `io::Error` of kind [`Error::kind()`]. I/O errors
+    /// are returned as they are.
+    fn from(e: Error) -> io::Error {
+        match e {
+            Error::Io(e) => e,
+            e => io::Error::new(e.kind(), e),
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+
+use ymodem::FileInfo;
+use {Error, Result};
+
+mod packet;
+#[cfg(test)]
+mod tests;
+
+use self::packet::*;
+
+/// Number of consecutive timeouts, corrupted packets or `NAK`s tolerated.
+const MAX_RETRIES: usize = 10;
+
+/// Number of bytes skipped while looking for the start of a packet before
+/// giving up.
+const MAX_GARBAGE: usize = 2 * MAX_LONG;
+
+/// Seconds the other side is asked to wait for a packet before timing out.
+const TIMEOUT_SECS: u8 = 10;
+
+/// Default number of packets sent before waiting for an acknowledgement.
+const DEFAULT_WINDOW: usize = 4;
+
+/// Largest window: sequence numbers are taken modulo 64 and a window must not
+/// overlap the previous one.
+const MAX_WINDOW: usize = 31;
+
+/// Default longest packet accepted.
+const DEFAULT_PACKET_LENGTH: usize = 4096;
+
+/// A data packet sent and not yet acknowledged.
+struct Pending {
+    seq: u8,
+    data: Vec<u8>,
+    acked: bool,
+}
+
+/// Implementation of the Kermit file transfer protocol.
+///
+/// A session starts with a `Send-Init` (`S`) exchange in which both sides
+/// describe what they can receive: the longest packet, the control and
+/// 8th-bit prefixes, the block check and whether long packets and sliding
+/// windows are supported. Every file is then announced by a file header
+/// (`F`), sent in data packets (`D`) and ended by an end-of-file packet (`Z`);
+/// a break packet (`B`) ends the session.
+///
+/// Data packets carry only printable characters: control characters are
+/// prefixed with `#`, and bytes with the 8th bit set are prefixed with `&`
+/// when either side asks for it, so files go through 7-bit lines. With
+/// sliding windows, up to 31 data packets are sent before the first is
+/// acknowledged; corrupted or lost packets are resent individually.
+///
+/// File headers use the same [`FileInfo`] as YMODEM; only the name is
+/// transferred. Timeouts are those of the inner stream, whose reads must
+/// eventually fail with `io::ErrorKind::TimedOut` when the other side is
+/// silent.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::{FileInfo, Kermit};
+///
+/// let file = File::open("kernel8.img")?;
+/// let info = FileInfo::new("kernel8.img", file.metadata()?.len());
+///
+/// let mut kermit = Kermit::new(port);
+/// kermit.send_file(&info, file)?;
+/// kermit.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Kermit<T> {
+    inner: T,
+    /// The `Send-Init` exchange is done and the negotiated parameters are in
+    /// effect.
+    started: bool,
+    /// Sequence number of the next packet sent, or expected.
+    seq: u8,
+    /// Longest packet accepted.
+    packet_length: usize,
+    window_size: usize,
+    eighth_bit_quoting: bool,
+    /// Negotiated block check.
+    check: Check,
+    /// Prefixing of the data sent and of the data received.
+    tx: Quoting,
+    rx: Quoting,
+    /// Longest data field the other side accepts.
+    max_data: usize,
+    /// Negotiated window size.
+    window: usize,
+    /// Padding and end of line the other side asked for.
+    npad: u8,
+    padc: u8,
+    eol: u8,
+    out: Vec<u8>,
+    /// The last reply sent, resent when the packet it answers is repeated.
+    reply: Vec<u8>,
+}
+
+impl<T: io::Read + io::Write> Kermit<T> {
+    /// Returns a new `Kermit` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving
+    /// (downloading) and sending (uploading).
+    pub fn new(inner: T) -> Self {
+        let quoting = Quoting {
+            qctl: QCTL,
+            qbin: None,
+        };
+
+        Kermit {
+            inner,
+            started: false,
+            seq: 0,
+            packet_length: DEFAULT_PACKET_LENGTH,
+            window_size: DEFAULT_WINDOW,
+            eighth_bit_quoting: false,
+            check: Check::Sum6,
+            tx: quoting,
+            rx: quoting,
+            max_data: MAX_NORMAL - 2 - Check::Sum6.size(),
+            window: 1,
+            npad: 0,
+            padc: 0,
+            eol: CR,
+            out: Vec::with_capacity(DEFAULT_PACKET_LENGTH + 8),
+            reply: vec![],
+        }
+    }
+
+    /// Sets the longest packet accepted, from 10 to 9024 characters. Packets
+    /// longer than 94 characters are long packets, used if the other side
+    /// supports them. The default is 4096. Takes effect at the start of the
+    /// next session.
+    pub fn set_packet_length(&mut self, len: usize) {
+        self.packet_length = len.clamp(10, MAX_LONG);
+    }
+
+    /// Sets the number of data packets sent before waiting for an
+    /// acknowledgement, from 1 to 31. The smaller of the two sides' window
+    /// sizes is used, and 1 if either doesn't support sliding windows. The
+    /// default is 4. Takes effect at the start of the next session.
+    pub fn set_window_size(&mut self, size: usize) {
+        self.window_size = size.clamp(1, MAX_WINDOW);
+    }
+
+    /// Sets whether bytes with the 8th bit set are prefixed, as needed on
+    /// lines with 7 data bits or parity. The other side can also ask for it.
+    /// The default is `false`. Takes effect at the start of the next session.
+    pub fn set_eighth_bit_quoting(&mut self, quote: bool) {
+        self.eighth_bit_quoting = quote;
+    }
+
+    /// Sends the header `info` followed by the contents of the file, read from
+    /// `data`. The session is started first if needed.
+    ///
+    /// Returns the number of bytes sent.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if the file name is empty or doesn't fit
+    /// in a packet. `Error::Cancelled` is returned if the receiver sends an
+    /// error packet and `Error::RetriesExhausted` if it repeatedly doesn't
+    /// respond. Otherwise, returns any error from reading `data` or reading
+    /// and writing the inner stream.
+    pub fn send_file<R: io::Read>(&mut self, info: &FileInfo, data: R) -> Result<u64> {
+        if info.name.is_empty() {
+            return Err(Error::InvalidInput("Kermit file name must not be empty"));
+        }
+
+        if !self.started {
+            self.start_send()?;
+        }
+
+        let mut name = vec![];
+        info.name.bytes().for_each(|b| self.tx.encode(b, &mut name));
+        if name.len() > self.max_data {
+            return Err(Error::InvalidInput(
+                "file name too long for a Kermit packet",
+            ));
+        }
+
+        self.exchange(FILE_HEADER, &name)?;
+        let sent = self.send_data(io::BufReader::new(data))?;
+        self.exchange(EOF, &[])?;
+        Ok(sent)
+    }
+
+    /// Ends the session. This must be called after the last file has been
+    /// sent.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.started {
+            self.start_send()?;
+        }
+
+        self.exchange(BREAK, &[])?;
+        self.started = false;
+        Ok(())
+    }
+
+    /// Receives the header of the next file. The session is started first if
+    /// needed. Returns `None` when the sender has ended the session.
+    /// Otherwise, the file must be received with [`Kermit::recv_data()`]
+    /// before the next header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if the sender sends something other than a
+    /// file header or the end of the session, `Error::Cancelled` if it sends
+    /// an error packet and `Error::RetriesExhausted` if it repeatedly doesn't
+    /// respond. Otherwise, returns any error from reading or writing the
+    /// inner stream.
+    pub fn recv_header(&mut self) -> Result<Option<FileInfo>> {
+        if !self.started {
+            self.start_recv()?;
+        }
+
+        let mut retries = 0;
+        while retries < MAX_RETRIES {
+            let packet = match self.read_packet() {
+                Ok(packet) => packet,
+                Err(ref e) if is_retryable(e) => {
+                    retries += 1;
+                    let seq = self.seq;
+                    self.send_packet(seq, NAK, &[])?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if packet.seq != self.seq {
+                // The sender missed the reply to its previous packet.
+                if next(packet.seq) == self.seq {
+                    self.resend_reply()?;
+                }
+
+                retries += 1;
+                continue;
+            }
+
+            match packet.kind {
+                FILE_HEADER => {
+                    let mut name = vec![];
+                    self.rx.decode(&packet.data, &mut name)?;
+                    let name = String::from_utf8(name)
+                        .map_err(|_| Error::Protocol("invalid Kermit file name"))?;
+                    self.reply(packet.seq, ACK, &[])?;
+                    self.seq = next(packet.seq);
+                    return Ok(Some(FileInfo {
+                        name,
+                        len: None,
+                        mtime: None,
+                        mode: None,
+                    }));
+                }
+                BREAK => {
+                    self.reply(packet.seq, ACK, &[])?;
+                    self.started = false;
+                    return Ok(None);
+                }
+                ERROR => return Err(Error::Cancelled),
+                _ => return Err(Error::Protocol("expected a Kermit file header")),
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Receives the contents of the file described by the last header
+    /// returned from [`Kermit::recv_header()`] and writes them into `into`.
+    ///
+    /// Returns the number of bytes written to `into`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if a data packet is malformed,
+    /// `Error::Cancelled` if the sender sends an error packet or discards the
+    /// file and `Error::RetriesExhausted` if it repeatedly doesn't respond or
+    /// sends corrupted packets. Otherwise, returns any error from writing to
+    /// `into` or reading and writing the inner stream.
+    pub fn recv_data<W: io::Write>(&mut self, mut into: W) -> Result<u64> {
+        let mut slots: Vec<Option<Vec<u8>>> = vec![None; 64];
+        let mut written = 0;
+        let mut retries = 0;
+        // The packet NAKed since the window last moved, if any.
+        let mut nak = None;
+        while retries < MAX_RETRIES {
+            let packet = match self.read_packet() {
+                Ok(packet) => packet,
+                Err(ref e) if is_retryable(e) => {
+                    retries += 1;
+                    let seq = self.seq;
+                    self.send_packet(seq, NAK, &[])?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            let ahead = (packet.seq + 64 - self.seq) as usize % 64;
+            let behind = (self.seq + 64 - packet.seq) as usize % 64;
+            match packet.kind {
+                DATA if ahead < self.window => {
+                    retries = 0;
+                    if slots[packet.seq as usize].is_none() {
+                        let mut data = vec![];
+                        self.rx.decode(&packet.data, &mut data)?;
+                        slots[packet.seq as usize] = Some(data);
+                    }
+
+                    self.send_packet(packet.seq, ACK, &[])?;
+                    while let Some(data) = slots[self.seq as usize].take() {
+                        into.write_all(&data)?;
+                        written += data.len() as u64;
+                        self.seq = next(self.seq);
+                        nak = None;
+                    }
+
+                    // A packet arrived ahead of a missing one.
+                    if ahead > 0 && nak != Some(self.seq) {
+                        let seq = self.seq;
+                        self.send_packet(seq, NAK, &[])?;
+                        nak = Some(seq);
+                    }
+                }
+                EOF if ahead == 0 => {
+                    self.reply(packet.seq, ACK, &[])?;
+                    self.seq = next(packet.seq);
+                    return match &packet.data[..] {
+                        b"D" => Err(Error::Cancelled),
+                        _ => Ok(written),
+                    };
+                }
+                ERROR => return Err(Error::Cancelled),
+                _ if behind > 0 && behind <= self.window => {
+                    // A packet whose acknowledgement was lost.
+                    self.send_packet(packet.seq, ACK, &[])?;
+                }
+                _ => retries += 1,
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Starts a session as a sender with the `Send-Init` exchange.
+    fn start_send(&mut self) -> Result<()> {
+        self.seq = 0;
+        let ours = self.params();
+        let ack = self.exchange(SEND_INIT, &ours.encode())?;
+        self.negotiate(&ours, &Params::decode(&ack.data));
+        Ok(())
+    }
+
+    /// Starts a session as a receiver: waits for the sender's `Send-Init`
+    /// and acknowledges it with our parameters, `NAK`ing on timeouts.
+    fn start_recv(&mut self) -> Result<()> {
+        self.seq = 0;
+        for _ in 0..MAX_RETRIES {
+            match self.read_packet() {
+                Ok(ref packet) if packet.kind == SEND_INIT => {
+                    let theirs = Params::decode(&packet.data);
+                    let ours = self.reply_params(&theirs);
+                    self.reply(packet.seq, ACK, &ours.encode())?;
+                    self.negotiate(&ours, &theirs);
+                    self.seq = next(packet.seq);
+                    return Ok(());
+                }
+                Ok(ref packet) if packet.kind == ERROR => return Err(Error::Cancelled),
+                Ok(_) => continue,
+                Err(ref e) if is_retryable(e) => self.send_packet(0, NAK, &[])?,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Returns the parameters a sender proposes.
+    fn params(&self) -> Params {
+        let mut capas = CAPAS_WINDOWS;
+        if self.packet_length > MAX_NORMAL {
+            capas |= CAPAS_LONG;
+        }
+
+        Params {
+            maxl: cmp::min(self.packet_length, MAX_NORMAL),
+            time: TIMEOUT_SECS,
+            npad: 0,
+            padc: 0,
+            eol: CR,
+            qctl: QCTL,
+            qbin: if self.eighth_bit_quoting {
+                QBIN
+            } else {
+                QBIN_WILLING
+            },
+            chkt: Check::Crc16.field(),
+            capas,
+            window: self.window_size as u8,
+            maxlx: self.packet_length,
+        }
+    }
+
+    /// Returns the parameters a receiver answers the sender's `theirs` with:
+    /// it accepts the sender's block check and 8th-bit prefix.
+    fn reply_params(&self, theirs: &Params) -> Params {
+        let mut params = self.params();
+        params.qbin = if is_prefix(theirs.qbin) {
+            QBIN_WILLING
+        } else if theirs.qbin == QBIN_WILLING && self.eighth_bit_quoting {
+            QBIN
+        } else {
+            b'N'
+        };
+
+        if Check::from_field(theirs.chkt).is_some() {
+            params.chkt = theirs.chkt;
+        } else {
+            params.chkt = Check::Sum6.field();
+        }
+
+        params
+    }
+
+    /// Puts the parameters agreed on in the `Send-Init` exchange in effect.
+    fn negotiate(&mut self, ours: &Params, theirs: &Params) {
+        self.check = match Check::from_field(ours.chkt) {
+            Some(check) if ours.chkt == theirs.chkt => check,
+            _ => Check::Sum6,
+        };
+
+        let qbin =
+            if is_prefix(ours.qbin) && (theirs.qbin == QBIN_WILLING || theirs.qbin == ours.qbin) {
+                Some(ours.qbin)
+            } else if is_prefix(theirs.qbin) && ours.qbin == QBIN_WILLING {
+                Some(theirs.qbin)
+            } else {
+                None
+            };
+
+        self.tx = Quoting {
+            qctl: ours.qctl,
+            qbin,
+        };
+        self.rx = Quoting {
+            qctl: theirs.qctl,
+            qbin,
+        };
+
+        let both = |capa: u8| ours.capas & theirs.capas & capa != 0;
+        let length = if both(CAPAS_LONG) {
+            // Sequence number, type, extended length and header check.
+            cmp::min(theirs.maxlx, MAX_LONG).saturating_sub(5)
+        } else {
+            // Sequence number and type.
+            cmp::min(theirs.maxl, MAX_NORMAL).saturating_sub(2)
+        };
+
+        self.max_data = cmp::max(length.saturating_sub(self.check.size()), 4);
+        self.window = match both(CAPAS_WINDOWS) {
+            true => cmp::max(1, cmp::min(ours.window, theirs.window) as usize),
+            false => 1,
+        };
+
+        self.npad = theirs.npad;
+        self.padc = theirs.padc;
+        self.eol = theirs.eol;
+        self.started = true;
+    }
+
+    /// Sends the contents of `data` in data packets, keeping up to a window
+    /// of them unacknowledged. Returns the number of bytes sent.
+    fn send_data<R: BufRead>(&mut self, mut data: R) -> Result<u64> {
+        let mut window: VecDeque<Pending> = VecDeque::new();
+        let mut sent = 0;
+        let mut end = false;
+        let mut retries = 0;
+        loop {
+            while !end && window.len() < self.window {
+                let mut field = vec![];
+                let n = self.fill(&mut data, &mut field)?;
+                if n == 0 {
+                    end = true;
+                    break;
+                }
+
+                let seq = self.seq;
+                self.seq = next(seq);
+                self.send_packet(seq, DATA, &field)?;
+                window.push_back(Pending {
+                    seq,
+                    data: field,
+                    acked: false,
+                });
+                sent += n as u64;
+            }
+
+            if window.is_empty() {
+                return Ok(sent);
+            } else if retries >= MAX_RETRIES {
+                return Err(Error::RetriesExhausted);
+            }
+
+            match self.read_packet() {
+                Ok(packet) => match packet.kind {
+                    ACK => {
+                        if let Some(pending) = window.iter_mut().find(|p| p.seq == packet.seq) {
+                            pending.acked = true;
+                            retries = 0;
+                        }
+                    }
+                    NAK => match window.iter().position(|p| p.seq == packet.seq) {
+                        Some(i) => {
+                            retries += 1;
+                            self.send_packet(packet.seq, DATA, &window[i].data)?;
+                        }
+                        // A NAK for the next packet acknowledges all before it.
+                        None if packet.seq == self.seq => {
+                            window.iter_mut().for_each(|p| p.acked = true);
+                        }
+                        None => {}
+                    },
+                    ERROR => return Err(Error::Cancelled),
+                    _ => {}
+                },
+                Err(ref e) if is_retryable(e) => {
+                    retries += 1;
+                    if let Some(pending) = window.iter().find(|p| !p.acked) {
+                        self.send_packet(pending.seq, DATA, &pending.data)?;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+
+            while window.front().is_some_and(|p| p.acked) {
+                window.pop_front();
+            }
+        }
+    }
+
+    /// Appends the bytes read from `data` to `field`, prefixed, until the
+    /// next one doesn't fit in a data field. Returns the number of bytes
+    /// read, 0 at the end of `data`.
+    fn fill<R: BufRead>(&self, data: &mut R, field: &mut Vec<u8>) -> Result<usize> {
+        let mut count = 0;
+        loop {
+            let buf = data.fill_buf()?;
+            if buf.is_empty() {
+                return Ok(count);
+            }
+
+            let mut used = 0;
+            for &byte in buf {
+                if field.len() + self.tx.encoded_len(byte) > self.max_data {
+                    break;
+                }
+
+                self.tx.encode(byte, field);
+                used += 1;
+            }
+
+            let full = used < buf.len();
+            data.consume(used);
+            count += used;
+            if full {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Sends a packet of type `kind` and waits for its acknowledgement,
+    /// resending the packet on timeouts and `NAK`s. Returns the
+    /// acknowledgement.
+    fn exchange(&mut self, kind: u8, data: &[u8]) -> Result<Packet> {
+        let seq = self.seq;
+        for _ in 0..MAX_RETRIES {
+            self.send_packet(seq, kind, data)?;
+            loop {
+                match self.read_packet() {
+                    Ok(packet) => match packet.kind {
+                        ACK if packet.seq == seq => {
+                            self.seq = next(seq);
+                            return Ok(packet);
+                        }
+                        // A NAK for the next packet acknowledges this one,
+                        // unless the parameters in the ACK are needed.
+                        NAK if packet.seq == next(seq) && kind != SEND_INIT => {
+                            self.seq = next(seq);
+                            return Ok(packet);
+                        }
+                        NAK if packet.seq == seq => break,
+                        ERROR => return Err(Error::Cancelled),
+                        _ => continue,
+                    },
+                    Err(ref e) if is_retryable(e) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Reads the next packet, skipping anything that precedes it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Checksum` if the packet is corrupted and
+    /// `Error::Protocol` if no packet starts within `MAX_GARBAGE` bytes.
+    fn read_packet(&mut self) -> Result<Packet> {
+        let mut skipped = 0;
+        while self.read_byte()? != MARK {
+            skipped += 1;
+            if skipped > MAX_GARBAGE {
+                return Err(Error::Protocol("no Kermit packet found"));
+            }
+        }
+
+        loop {
+            if let Some(packet) = self.read_body()? {
+                return Ok(packet);
+            }
+        }
+    }
+
+    /// Reads the rest of a packet after its `MARK`. Returns `None` if another
+    /// `MARK` interrupts it: a new packet starts there.
+    fn read_body(&mut self) -> Result<Option<Packet>> {
+        let mut header = [0u8; 6];
+        if !self.read_chars(&mut header[..3])? {
+            return Ok(None);
+        }
+
+        let check = self.check_for(header[2]);
+        let (header_len, rest) = match unchar(header[0]) as usize {
+            0 => {
+                if !self.read_chars(&mut header[3..])? {
+                    return Ok(None);
+                }
+
+                if sum6(header[..5].iter().fold(0, |s, &b| s + b as u32)) != header[5] {
+                    return Err(Error::Checksum);
+                }
+
+                let extended = unchar(header[3]) as usize * 95 + unchar(header[4]) as usize;
+                (6, extended)
+            }
+            len @ 2..=MAX_NORMAL => (3, len - 2),
+            _ => return Err(Error::Checksum),
+        };
+
+        if rest < check.size() || rest > MAX_LONG {
+            return Err(Error::Checksum);
+        }
+
+        let mut body = vec![0u8; header_len + rest];
+        body[..header_len].copy_from_slice(&header[..header_len]);
+        if !self.read_chars(&mut body[header_len..])? {
+            return Ok(None);
+        }
+
+        let end = body.len() - check.size();
+        let mut expected = [0u8; 3];
+        check.compute(&body[..end], &mut expected);
+        if body[end..] != expected[..check.size()] {
+            return Err(Error::Checksum);
+        }
+
+        Ok(Some(Packet {
+            seq: unchar(header[1]) % 64,
+            kind: header[2],
+            data: body[header_len..end].to_vec(),
+        }))
+    }
+
+    /// Fills `buf` with the next characters of a packet. Returns `false` if
+    /// a `MARK` is read instead.
+    fn read_chars(&mut self, buf: &mut [u8]) -> Result<bool> {
+        for slot in buf.iter_mut() {
+            *slot = self.read_byte()?;
+            if *slot == MARK {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    /// Returns the block check of packets of type `kind`: `Send-Init`
+    /// exchanges use type 1.
+    fn check_for(&self, kind: u8) -> Check {
+        match kind {
+            _ if !self.started => Check::Sum6,
+            SEND_INIT => Check::Sum6,
+            _ => self.check,
+        }
+    }
+
+    fn send_packet(&mut self, seq: u8, kind: u8, data: &[u8]) -> Result<()> {
+        let check = self.check_for(kind);
+        self.out.clear();
+        self.out.resize(self.npad as usize, self.padc);
+        encode(seq, kind, data, check, self.eol, &mut self.out);
+        self.inner.write_all(&self.out)?;
+        Ok(self.inner.flush()?)
+    }
+
+    /// Sends a reply and keeps it to be resent by `resend_reply()`.
+    fn reply(&mut self, seq: u8, kind: u8, data: &[u8]) -> Result<()> {
+        self.send_packet(seq, kind, data)?;
+        self.reply.clear();
+        self.reply.extend_from_slice(&self.out);
+        Ok(())
+    }
+
+    fn resend_reply(&mut self) -> Result<()> {
+        self.inner.write_all(&self.reply)?;
+        Ok(self.inner.flush()?)
+    }
+}
+
+/// Returns `true` if `e` indicates a timeout or a corrupted packet, after
+/// which the packet can be requested again.
+fn is_retryable(e: &Error) -> bool {
+    matches!(*e, Error::Timeout | Error::Checksum)
+}
@@ -0,0 +1,329 @@
+use {Error, Result};
+
+/// Start of every packet.
+pub const MARK: u8 = 0x01;
+/// End of line sent after every packet unless the peer asks for another.
+pub const CR: u8 = b'\r';
+
+pub const SEND_INIT: u8 = b'S';
+pub const FILE_HEADER: u8 = b'F';
+pub const DATA: u8 = b'D';
+pub const EOF: u8 = b'Z';
+pub const BREAK: u8 = b'B';
+pub const ACK: u8 = b'Y';
+pub const NAK: u8 = b'N';
+pub const ERROR: u8 = b'E';
+
+/// Control character prefix.
+pub const QCTL: u8 = b'#';
+/// 8th-bit prefix requested by a side that needs 8th-bit quoting.
+pub const QBIN: u8 = b'&';
+/// 8th-bit field of a side that quotes if the other one asks to.
+pub const QBIN_WILLING: u8 = b'Y';
+
+/// `CAPAS` bit: long packets are supported.
+pub const CAPAS_LONG: u8 = 0x02;
+/// `CAPAS` bit: sliding windows are supported.
+pub const CAPAS_WINDOWS: u8 = 0x04;
+
+/// Longest normal packet: the `LEN` field counts up to 94 characters.
+pub const MAX_NORMAL: usize = 94;
+/// Longest long packet: the extended length counts up to 95 * 95 - 1
+/// characters.
+pub const MAX_LONG: usize = 95 * 95 - 1;
+
+/// Encodes a number from 0 to 94 as a printable character.
+pub fn tochar(x: u8) -> u8 {
+    x + 32
+}
+
+/// Decodes a character encoded with `tochar()`.
+pub fn unchar(c: u8) -> u8 {
+    c.wrapping_sub(32)
+}
+
+/// Returns the sequence number following `seq`.
+pub fn next(seq: u8) -> u8 {
+    (seq + 1) % 64
+}
+
+/// The block check trailing every packet, chosen in the `Send-Init` exchange.
+/// `Send-Init` packets and their acknowledgement always use type 1.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Check {
+    /// Type 1: a 6-bit checksum, in one character.
+    Sum6,
+    /// Type 2: a 12-bit checksum, in two characters.
+    Sum12,
+    /// Type 3: CRC-16/KERMIT, in three characters.
+    Crc16,
+}
+
+impl Check {
+    /// Returns the block check type for the `CHKT` field `c`.
+    pub fn from_field(c: u8) -> Option<Check> {
+        match c {
+            b'1' => Some(Check::Sum6),
+            b'2' => Some(Check::Sum12),
+            b'3' => Some(Check::Crc16),
+            _ => None,
+        }
+    }
+
+    /// Returns the `CHKT` field requesting this block check type.
+    pub fn field(&self) -> u8 {
+        match *self {
+            Check::Sum6 => b'1',
+            Check::Sum12 => b'2',
+            Check::Crc16 => b'3',
+        }
+    }
+
+    /// Returns the number of characters in the block check.
+    pub fn size(&self) -> usize {
+        match *self {
+            Check::Sum6 => 1,
+            Check::Sum12 => 2,
+            Check::Crc16 => 3,
+        }
+    }
+
+    /// Computes the block check of `data` into the first `size()` bytes of
+    /// `out`.
+    pub fn compute(&self, data: &[u8], out: &mut [u8; 3]) {
+        let sum = || data.iter().fold(0u32, |s, &b| s + b as u32);
+        match *self {
+            Check::Sum6 => out[0] = sum6(sum()),
+            Check::Sum12 => {
+                let s = sum() & 0xFFF;
+                out[0] = tochar((s >> 6) as u8 & 0x3F);
+                out[1] = tochar(s as u8 & 0x3F);
+            }
+            Check::Crc16 => {
+                let crc = crc16_kermit(data);
+                out[0] = tochar((crc >> 12) as u8 & 0x0F);
+                out[1] = tochar((crc >> 6) as u8 & 0x3F);
+                out[2] = tochar(crc as u8 & 0x3F);
+            }
+        }
+    }
+}
+
+/// Folds the sum `s` into a 6-bit checksum character.
+pub fn sum6(s: u32) -> u8 {
+    tochar(((s + ((s & 0xC0) >> 6)) & 0x3F) as u8)
+}
+
+/// Returns the CRC-16/KERMIT (reflected polynomial `0x8408`, initial value
+/// `0`) of `data`.
+pub fn crc16_kermit(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, &byte| {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+
+        crc
+    })
+}
+
+/// A decoded packet. `data` is still prefixed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub seq: u8,
+    pub kind: u8,
+    pub data: Vec<u8>,
+}
+
+/// Appends a packet to `out`: `MARK`, the header, `data`, the block check
+/// and `eol`. A long packet is used if `data` doesn't fit in a normal one.
+pub fn encode(seq: u8, kind: u8, data: &[u8], check: Check, eol: u8, out: &mut Vec<u8>) {
+    out.push(MARK);
+    let start = out.len();
+    let len = 2 + data.len() + check.size();
+    if len <= MAX_NORMAL {
+        out.extend_from_slice(&[tochar(len as u8), tochar(seq), kind]);
+    } else {
+        let extended = data.len() + check.size();
+        out.extend_from_slice(&[tochar(0), tochar(seq), kind]);
+        out.push(tochar((extended / 95) as u8));
+        out.push(tochar((extended % 95) as u8));
+        let hcheck = sum6(out[start..].iter().fold(0, |s, &b| s + b as u32));
+        out.push(hcheck);
+    }
+
+    out.extend_from_slice(data);
+    let mut block = [0u8; 3];
+    check.compute(&out[start..], &mut block);
+    out.extend_from_slice(&block[..check.size()]);
+    out.push(eol);
+}
+
+/// Prefixes data field bytes so that the data field holds only printable
+/// characters, as negotiated in the `Send-Init` exchange.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Quoting {
+    pub qctl: u8,
+    /// The 8th-bit prefix, if 8th-bit quoting is in effect.
+    pub qbin: Option<u8>,
+}
+
+impl Quoting {
+    /// Returns the number of characters `byte` is encoded in.
+    pub fn encoded_len(&self, byte: u8) -> usize {
+        let mut len = 1;
+        let mut c = byte;
+        if self.qbin.is_some() && c & 0x80 != 0 {
+            len += 1;
+            c &= 0x7F;
+        }
+
+        if self.needs_prefix(c) {
+            len += 1;
+        }
+
+        len
+    }
+
+    /// Appends `byte`, prefixed as needed, to `out`.
+    pub fn encode(&self, byte: u8, out: &mut Vec<u8>) {
+        let mut c = byte;
+        if let Some(qbin) = self.qbin {
+            if c & 0x80 != 0 {
+                out.push(qbin);
+                c &= 0x7F;
+            }
+        }
+
+        let low = c & 0x7F;
+        if low < 32 || low == 127 {
+            out.push(self.qctl);
+            c ^= 0x40;
+        } else if self.needs_prefix(c) {
+            out.push(self.qctl);
+        }
+
+        out.push(c);
+    }
+
+    /// Appends the bytes encoded in `data` to `out`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if `data` ends with a prefix.
+    pub fn decode(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        const TRUNCATED: Error = Error::Protocol("Kermit data ends with a prefix");
+
+        let mut bytes = data.iter().cloned();
+        while let Some(mut c) = bytes.next() {
+            let mut high = 0;
+            if Some(c) == self.qbin {
+                high = 0x80;
+                c = bytes.next().ok_or(TRUNCATED)?;
+            }
+
+            if c == self.qctl {
+                c = bytes.next().ok_or(TRUNCATED)?;
+                if (0x3F..=0x5F).contains(&(c & 0x7F)) {
+                    c ^= 0x40;
+                }
+            }
+
+            out.push(c | high);
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if `c` needs the control prefix: it is a control
+    /// character or one of the prefixes in use.
+    fn needs_prefix(&self, c: u8) -> bool {
+        let low = c & 0x7F;
+        low < 32 || low == 127 || low == self.qctl || Some(low) == self.qbin
+    }
+}
+
+/// Returns `true` if `c` can be used as an 8th-bit prefix.
+pub fn is_prefix(c: u8) -> bool {
+    (33..=62).contains(&c) || (96..=126).contains(&c)
+}
+
+/// Session parameters exchanged in a `Send-Init` packet and its
+/// acknowledgement. Each side describes what it wants to receive.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Params {
+    /// Longest normal packet the side can receive.
+    pub maxl: usize,
+    /// Seconds the other side should wait for a packet before timing out.
+    pub time: u8,
+    /// Padding characters to send before every packet, and which one.
+    pub npad: u8,
+    pub padc: u8,
+    /// Character to send after every packet.
+    pub eol: u8,
+    pub qctl: u8,
+    pub qbin: u8,
+    pub chkt: u8,
+    pub capas: u8,
+    /// Largest window the side supports.
+    pub window: u8,
+    /// Longest long packet the side can receive.
+    pub maxlx: usize,
+}
+
+impl Params {
+    /// Encodes the parameters into the data field of a `Send-Init` packet or
+    /// its acknowledgement.
+    pub fn encode(&self) -> Vec<u8> {
+        vec![
+            tochar(self.maxl as u8),
+            tochar(self.time),
+            tochar(self.npad),
+            self.padc ^ 0x40,
+            tochar(self.eol),
+            self.qctl,
+            self.qbin,
+            self.chkt,
+            b' ',
+            tochar(self.capas),
+            tochar(self.window),
+            tochar((self.maxlx / 95) as u8),
+            tochar((self.maxlx % 95) as u8),
+        ]
+    }
+
+    /// Decodes the parameters in `data`. Fields that are missing or blank
+    /// take their default values.
+    pub fn decode(data: &[u8]) -> Params {
+        let field = |i: usize| data.get(i).cloned().filter(|&c| c != b' ');
+        let number = |i: usize| field(i).map(unchar);
+        let capas = number(9).unwrap_or(0);
+        // A CAPAS byte with the low bit set is followed by more CAPAS bytes.
+        let extra = (9..data.len())
+            .take_while(|&i| unchar(data[i]) & 1 != 0)
+            .count();
+        let at = |i: usize| number(i + extra);
+        let maxlx = match (at(11), at(12)) {
+            (Some(high), Some(low)) => high as usize * 95 + low as usize,
+            _ => 500,
+        };
+
+        Params {
+            maxl: number(0).map_or(80, |n| n as usize),
+            time: number(1).unwrap_or(5),
+            npad: number(2).unwrap_or(0),
+            padc: field(3).map_or(0, |c| c ^ 0x40),
+            eol: number(4).unwrap_or(CR),
+            qctl: field(5).unwrap_or(QCTL),
+            qbin: field(6).unwrap_or(b'N'),
+            chkt: field(7).unwrap_or(b'1'),
+            capas,
+            window: at(10).unwrap_or(1),
+            maxlx,
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use super::*;
+use std::io::Cursor;
+use tests::pipe;
+use {noisy_pipe, Faults};
+
+fn tricky_data(len: usize) -> Vec<u8> {
+    let pattern = [
+        MARK, CR, QCTL, QBIN, b'~', 0x7F, 0x80, 0x81, 0x8D, 0xA3, 0xFF,
+    ];
+    (0..len)
+        .map(|i| match i % 3 {
+            0 => pattern[i % pattern.len()],
+            _ => (i % 251) as u8,
+        })
+        .collect()
+}
+
+/// Sends `files` from one thread and receives them on another, with each
+/// side configured by `configure`. Returns what the receiver got.
+fn transfer<T>(
+    files: Vec<(FileInfo, Vec<u8>)>,
+    sender: T,
+    receiver: T,
+    configure: fn(&mut Kermit<T>),
+) -> Vec<(String, Vec<u8>)>
+where
+    T: io::Read + io::Write + Send + 'static,
+{
+    let tx_thread = std::thread::spawn(move || {
+        let mut kermit = Kermit::new(sender);
+        configure(&mut kermit);
+        for (info, data) in files.iter() {
+            let n = kermit.send_file(info, &data[..]).expect("send file");
+            assert_eq!(n, data.len() as u64);
+        }
+
+        kermit.finish().expect("finish session");
+        // Keeps the line open until the receiver has sent its last reply.
+        kermit
+    });
+
+    let mut kermit = Kermit::new(receiver);
+    configure(&mut kermit);
+    let mut received = vec![];
+    while let Some(info) = kermit.recv_header().expect("header") {
+        let mut data = vec![];
+        let n = kermit.recv_data(&mut data).expect("data");
+        assert_eq!(n, data.len() as u64);
+        received.push((info.name, data));
+    }
+
+    tx_thread.join().expect("tx join okay");
+    received
+}
+
+fn names_and_data(files: &[(FileInfo, Vec<u8>)]) -> Vec<(String, Vec<u8>)> {
+    files
+        .iter()
+        .map(|(info, data)| (info.name.clone(), data.clone()))
+        .collect()
+}
+
+#[test]
+fn test_block_checks() {
+    assert_eq!(crc16_kermit(b"123456789"), 0x2189);
+
+    let mut out = vec![];
+    encode(0, NAK, &[], Check::Sum6, CR, &mut out);
+    assert_eq!(&out[..], &b"\x01# N3\r"[..]);
+
+    for &check in [Check::Sum6, Check::Sum12, Check::Crc16].iter() {
+        let mut block = [0u8; 3];
+        check.compute(b"\x01\x7f\xff hello", &mut block);
+        assert!(block[..check.size()]
+            .iter()
+            .all(|&c| (32..=126).contains(&c)));
+        assert_eq!(Check::from_field(check.field()), Some(check));
+    }
+}
+
+#[test]
+fn test_quoting() {
+    let data: Vec<u8> = (0..=255).collect();
+    for &qbin in [None, Some(QBIN)].iter() {
+        let quoting = Quoting { qctl: QCTL, qbin };
+        let mut encoded = vec![];
+        for &byte in data.iter() {
+            let len = encoded.len();
+            quoting.encode(byte, &mut encoded);
+            assert_eq!(encoded.len() - len, quoting.encoded_len(byte));
+        }
+
+        assert!(encoded.iter().all(|&c| c & 0x7F >= 32 && c & 0x7F != 127));
+        if qbin.is_some() {
+            assert!(encoded.iter().all(|&c| c < 127));
+        }
+
+        let mut decoded = vec![];
+        quoting.decode(&encoded, &mut decoded).expect("decode");
+        assert_eq!(decoded, data);
+    }
+
+    let quoting = Quoting {
+        qctl: QCTL,
+        qbin: None,
+    };
+
+    let e = quoting.decode(b"ab#", &mut vec![]).expect_err("truncated");
+    assert!(matches!(e, Error::Protocol(_)), "{:?}", e);
+}
+
+#[test]
+fn test_params() {
+    let kermit = Kermit::new(Cursor::new(vec![]));
+    let params = kermit.params();
+    assert_eq!(Params::decode(&params.encode()), params);
+
+    // A minimal Send-Init: only the packet length is given.
+    let params = Params::decode(b"~");
+    assert_eq!(params.maxl, 94);
+    assert_eq!(params.eol, CR);
+    assert_eq!(params.qctl, QCTL);
+    assert_eq!(params.chkt, b'1');
+    assert_eq!(params.window, 1);
+}
+
+#[test]
+fn test_negotiation() {
+    let mut sender = Kermit::new(Cursor::new(vec![]));
+    sender.set_eighth_bit_quoting(true);
+    sender.set_window_size(8);
+    let mut receiver = Kermit::new(Cursor::new(vec![]));
+    receiver.set_packet_length(94);
+
+    let proposed = sender.params();
+    let reply = receiver.reply_params(&proposed);
+    sender.negotiate(&proposed, &reply);
+    receiver.negotiate(&reply, &proposed);
+    for kermit in [&sender, &receiver].iter() {
+        assert_eq!(kermit.check, Check::Crc16);
+        assert_eq!(kermit.tx.qbin, Some(QBIN));
+        assert_eq!(kermit.window, 4);
+    }
+
+    // Long packets are only used if both sides support them.
+    assert_eq!(sender.max_data, 94 - 2 - 3);
+    assert_eq!(receiver.max_data, 94 - 2 - 3);
+
+    // Peers that don't support windows or CRCs fall back.
+    let minimal = Params::decode(b"~");
+    let reply = receiver.reply_params(&minimal);
+    assert_eq!(reply.chkt, b'1');
+    assert_eq!(reply.qbin, b'N');
+    receiver.negotiate(&reply, &minimal);
+    assert_eq!(receiver.check, Check::Sum6);
+    assert_eq!(receiver.tx.qbin, None);
+    assert_eq!(receiver.window, 1);
+}
+
+#[test]
+fn test_long_packet() {
+    let mut kermit = Kermit::new(Cursor::new(vec![]));
+    let data = vec![b'x'; 3000];
+    let mut out = b"garbage".to_vec();
+    encode(5, DATA, &data, Check::Crc16, CR, &mut out);
+    kermit.started = true;
+    kermit.check = Check::Crc16;
+    kermit.inner = Cursor::new(out);
+
+    let packet = kermit.read_packet().expect("packet");
+    assert_eq!(packet.seq, 5);
+    assert_eq!(packet.kind, DATA);
+    assert_eq!(packet.data, data);
+}
+
+#[test]
+fn test_corrupted_packet() {
+    let mut out = vec![];
+    encode(1, DATA, b"hello", Check::Sum6, CR, &mut out);
+    out[5] ^= 0x02;
+
+    let mut kermit = Kermit::new(Cursor::new(out));
+    let e = kermit.read_packet().expect_err("bad check");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+
+    // A packet interrupted by the start of another is skipped.
+    let mut out = vec![];
+    encode(1, DATA, b"hello", Check::Sum6, CR, &mut out);
+    out.truncate(4);
+    encode(2, DATA, b"world", Check::Sum6, CR, &mut out);
+    let mut kermit = Kermit::new(Cursor::new(out));
+    assert_eq!(kermit.read_packet().expect("packet").data, b"world");
+}
+
+#[test]
+fn test_batch() {
+    let files = vec![
+        (FileInfo::new("kernel8.img", 100_000), tricky_data(100_000)),
+        (FileInfo::new("empty", 0), vec![]),
+        (FileInfo::new("config.txt", 13), b"arm_64bit=1\n\n".to_vec()),
+    ];
+
+    let (tx, rx) = pipe();
+    let received = transfer(files.clone(), rx, tx, |_| {});
+    assert_eq!(received, names_and_data(&files));
+}
+
+#[test]
+fn test_seven_bit_stop_and_wait() {
+    let files = vec![(FileInfo::new("seven", 5000), tricky_data(5000))];
+
+    let (tx, rx) = pipe();
+    let received = transfer(files.clone(), rx, tx, |kermit| {
+        kermit.set_eighth_bit_quoting(true);
+        kermit.set_window_size(1);
+        kermit.set_packet_length(94);
+    });
+
+    assert_eq!(received, names_and_data(&files));
+}
+
+#[test]
+fn test_noisy_transfer() {
+    let files = vec![(FileInfo::new("noisy", 50_000), tricky_data(50_000))];
+    let faults = Faults::new()
+        .bit_flips(0.0003)
+        .drops(0.0003)
+        .garbage(0.0003)
+        .duplicates(0.0003);
+
+    for seed in 0..4 {
+        let (tx, rx) = noisy_pipe(seed, faults);
+        let received = transfer(files.clone(), rx, tx, |kermit| {
+            kermit.set_window_size(8);
+            kermit.set_packet_length(200);
+        });
+
+        assert_eq!(received, names_and_data(&files));
+    }
+}
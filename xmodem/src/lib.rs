@@ -1,41 +1,244 @@
+//! Implementations of the XMODEM, YMODEM, ZMODEM and Kermit file transfer
+//! protocols.
+//!
+//! The XMODEM protocol is implemented by the [`Receiver`] and [`Sender`] state
+//! machines, which do no I/O and need neither `std` nor an allocator. With the
+//! default `std` feature, [`Xmodem`] runs them over any `io::Read + io::Write`
+//! stream, and YMODEM, ZMODEM and Kermit are available. The `async` feature adds
+//! `AsyncXmodem`, which runs them over tokio's asynchronous streams.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+extern crate core;
+#[cfg(all(test, unix))]
+extern crate libc;
+#[cfg(test)]
+#[macro_use]
+extern crate proptest;
+#[cfg(feature = "async")]
+extern crate tokio;
+
+#[cfg(feature = "std")]
+use std::cmp;
+#[cfg(feature = "std")]
 use std::io;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod builder;
+#[cfg(feature = "std")]
+mod checkpoint;
+mod checksum;
+#[cfg(feature = "std")]
+mod digest;
+mod error;
+#[cfg(feature = "std")]
+mod kermit;
+mod machine;
+#[cfg(feature = "std")]
+mod noise;
 mod progress;
+#[cfg(feature = "std")]
 mod read_ext;
+#[cfg(feature = "std")]
+mod stats;
+#[cfg(feature = "std")]
+mod stream;
 #[cfg(test)]
 mod tests;
-mod write_ext;
+mod trace;
+#[cfg(feature = "std")]
+mod ymodem;
+#[cfg(feature = "std")]
+mod zmodem;
 
-pub use progress::{Progress, ProgressFn};
+#[cfg(feature = "async")]
+pub use asynchronous::{AsyncXmodem, ReadPacket, Receive, Transmit, WritePacket};
+pub use builder::XmodemBuilder;
+#[cfg(feature = "std")]
+pub use checkpoint::Checkpoint;
+pub use checksum::Checksum;
+#[cfg(feature = "std")]
+pub use digest::Digest;
+pub use error::{Error, Result};
+#[cfg(feature = "std")]
+pub use kermit::Kermit;
+pub use machine::{Event, Receiver, Sender};
+#[cfg(feature = "std")]
+pub use noise::{noisy_pipe, Faults, NoisyPipe};
+pub use progress::{Progress, ProgressFn, Status};
+#[cfg(feature = "std")]
+pub use stats::TransferStats;
+#[cfg(feature = "std")]
+pub use stream::{XmodemReader, XmodemWriter};
+pub use trace::{Direction, Frame, Tracer};
+#[cfg(feature = "std")]
+pub use trace::{FrameDumper, Record, RingTracer};
+#[cfg(feature = "std")]
+pub use ymodem::{FileInfo, Ymodem};
+#[cfg(feature = "std")]
+pub use zmodem::Zmodem;
 
+#[cfg(feature = "std")]
+use builder::Config;
+#[cfg(feature = "std")]
+use digest::Hasher;
+#[cfg(feature = "std")]
+use progress::ProgressCallback;
+#[cfg(feature = "std")]
 use read_ext::ReadExt;
-use write_ext::WriteExt;
+#[cfg(feature = "std")]
+use trace::Tap;
 
 const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
 const EOT: u8 = 0x04;
 const ACK: u8 = 0x06;
 const NAK: u8 = 0x15;
 const CAN: u8 = 0x18;
+const CRC: u8 = b'C';
+/// A receiver's request for streamed CRC-16 packets, as in YMODEM-g.
+const STREAMING: u8 = b'G';
+
+/// Number of times a receiver sends `'C'` before falling back to `NAK`.
+const CRC_HANDSHAKE_ATTEMPTS: usize = 3;
+
+/// Number of bytes read at a time from the input that is already available.
+#[cfg(feature = "std")]
+const INPUT_CHUNK: usize = 64;
+
+/// Number of bytes in a packet's payload.
+///
+/// Receivers accept both sizes, even mixed in one session. Senders use the
+/// configured size (see [`Xmodem::set_block_size()`]) for all but the final
+/// packets of a transmission.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlockSize {
+    /// 128-byte packets, introduced by `SOH`.
+    Standard,
+    /// 1024-byte packets, introduced by `STX` (XMODEM-1K).
+    OneK,
+}
+
+impl BlockSize {
+    /// Returns the number of payload bytes in a packet of this size.
+    pub fn size(&self) -> usize {
+        match *self {
+            BlockSize::Standard => 128,
+            BlockSize::OneK => 1024,
+        }
+    }
+
+    /// Returns the size of the packets that carry `n` bytes, at most a block's
+    /// worth, at the end of a transmission: bytes that fit in seven or fewer
+    /// 128-byte packets are sent in 128-byte packets to reduce padding.
+    #[cfg(feature = "std")]
+    fn packet_size(&self, n: usize) -> usize {
+        if n > self.size() - 128 {
+            self.size()
+        } else {
+            128
+        }
+    }
+}
 
-pub static mut DEBUG_BUFFER: [u8; 1024] = [0; 1024];
-pub static mut DEBUG_BUFFER_OFFSET: usize = 0;
+/// What [`Xmodem::recv()`] does with the padding at the end of a transmission.
+///
+/// XMODEM only transfers whole packets, so the last packet is padded and the
+/// receiver can't tell padding from data. The stripping policies remove
+/// trailing padding bytes from the last packet only; a file that really ends
+/// with such bytes loses them.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// Keep the padding: every packet is written out in full.
+    Keep,
+    /// Strip trailing `SUB` (`0x1A`) bytes, the CP/M end-of-file marker many
+    /// senders pad with.
+    StripSub,
+    /// Strip trailing `NUL` bytes.
+    StripNul,
+    /// Write no more than `.0` bytes, the length of the file when known by
+    /// other means.
+    Truncate(u64),
+}
+
+impl PaddingPolicy {
+    /// Returns the length of `last`, the last packet of a transmission, once
+    /// its padding is stripped.
+    #[cfg(feature = "std")]
+    fn strip(&self, last: &[u8]) -> usize {
+        let padding = match *self {
+            PaddingPolicy::StripSub => 0x1A,
+            PaddingPolicy::StripNul => 0,
+            PaddingPolicy::Keep | PaddingPolicy::Truncate(_) => return last.len(),
+        };
+
+        last.iter()
+            .rposition(|&b| b != padding)
+            .map_or(0, |i| i + 1)
+    }
+}
 
-/// Implementation of the XMODEM protocol.
+/// Implementation of the XMODEM protocol over a blocking reader/writer.
+///
+/// The protocol itself is implemented by the [`Receiver`] and [`Sender`] state
+/// machines; `Xmodem` moves bytes between them and the inner stream.
+#[cfg(feature = "std")]
 pub struct Xmodem<R> {
-    packet: u8,
     inner: R,
-    started: bool,
-    progress: ProgressFn,
+    receiver: Receiver,
+    sender: Sender,
+    checksum: Checksum,
+    block_size: BlockSize,
+    padding_policy: PaddingPolicy,
+    progress: ProgressCallback,
+    /// Number of payload bytes transferred.
+    bytes: u64,
+    /// Number of payload bytes expected, if known.
+    total: Option<u64>,
+    /// Number of packets sent or received again.
+    retries: usize,
+    /// Statistics of the session; see [`Xmodem::stats()`].
+    stats: TransferStats,
+    /// The packets acknowledged by `send()` or written out by `recv()`.
+    checkpoint: Checkpoint,
+    /// Digest of the data transferred, when verification is enabled.
+    hasher: Option<Hasher>,
+    config: Config,
+    /// When the session started.
+    started_at: Option<Instant>,
+    deadline: Option<Instant>,
+    /// When bytes were last written to `inner`.
+    last_write: Instant,
+    tap: Option<Tap>,
+    /// Reads the bytes already received from `inner` without waiting.
+    try_read: Option<TryRead<R>>,
 }
 
+/// Reads a stream without waiting; see [`Xmodem::set_try_read()`].
+#[cfg(feature = "std")]
+type TryRead<R> = fn(&mut R, &mut [u8]) -> io::Result<usize>;
+
+#[cfg(feature = "std")]
 impl Xmodem<()> {
+    /// Returns a builder for sessions with custom retry, timeout, cancellation
+    /// and padding settings.
+    pub fn builder() -> XmodemBuilder {
+        XmodemBuilder::new()
+    }
+
     /// Transmits `data` to the receiver `to` using the XMODEM protocol. If the
     /// length of the total data yielded by `data` is not a multiple of 128
-    /// bytes, the data is padded with zeroes and sent to the receiver.
+    /// bytes, the data is padded with zeroes and sent to the receiver. The
+    /// packet checksum mode (8-bit checksum or CRC-16) is chosen by the
+    /// receiver.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
     #[inline]
-    pub fn transmit<R, W>(data: R, to: W) -> io::Result<usize>
+    pub fn transmit<R, W>(data: R, to: W) -> Result<usize>
     where
         W: io::Read + io::Write,
         R: io::Read,
@@ -51,42 +254,23 @@ impl Xmodem<()> {
     /// the transmission. See the [`Progress`] enum for more information.
     ///
     /// Returns the number of bytes written to `to`, excluding padding zeroes.
-    pub fn transmit_with_progress<R, W>(mut data: R, to: W, f: ProgressFn) -> io::Result<usize>
+    pub fn transmit_with_progress<R, W>(data: R, to: W, f: ProgressFn) -> Result<usize>
     where
         W: io::Read + io::Write,
         R: io::Read,
     {
-        let mut transmitter = Xmodem::new_with_progress(to, f);
-        let mut packet = [0u8; 128];
-        let mut written = 0;
-        'next_packet: loop {
-            let n = data.read_max(&mut packet)?;
-            packet[n..].iter_mut().for_each(|b| *b = 0);
-
-            if n == 0 {
-                transmitter.write_packet(&[])?;
-                return Ok(written);
-            }
-
-            for _ in 0..10 {
-                match transmitter.write_packet(&packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(_) => {
-                        written += n;
-                        continue 'next_packet;
-                    }
-                }
-            }
-
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "bad transmit"));
-        }
+        XmodemBuilder::new()
+            .progress(move |status: &Status| f(status.progress))
+            .transmit(data, to)
     }
 
     /// Receives `data` from `from` using the XMODEM protocol and writes it into
     /// `into`. Returns the number of bytes read from `from`, a multiple of 128.
+    ///
+    /// CRC-16 packets are requested from the sender; if the sender never
+    /// answers, reception falls back to the 8-bit checksum.
     #[inline]
-    pub fn receive<R, W>(from: R, into: W) -> io::Result<usize>
+    pub fn receive<R, W>(from: R, into: W) -> Result<usize>
     where
         R: io::Read + io::Write,
         W: io::Write,
@@ -99,48 +283,44 @@ impl Xmodem<()> {
     ///
     /// The function `f` is used as a callback to indicate progress throughout
     /// the reception. See the [`Progress`] enum for more information.
-    pub fn receive_with_progress<R, W>(from: R, mut into: W, f: ProgressFn) -> io::Result<usize>
+    pub fn receive_with_progress<R, W>(from: R, into: W, f: ProgressFn) -> Result<usize>
     where
         R: io::Read + io::Write,
         W: io::Write,
     {
-        let mut receiver = Xmodem::new_with_progress(from, f);
-        let mut packet = [0u8; 128];
-        let mut received = 0;
-        'next_packet: loop {
-            for _ in 0..10 {
-                match receiver.read_packet(&mut packet) {
-                    Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
-                    Err(e) => return Err(e),
-                    Ok(0) => {
-                        break 'next_packet;
-                    }
-                    Ok(n) => {
-                        received += n;
-                        into.write_all(&packet)?;
-                        continue 'next_packet;
-                    }
-                }
-            }
+        XmodemBuilder::new()
+            .progress(move |status: &Status| f(status.progress))
+            .receive(from, into)
+    }
 
-            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "bad receive"));
-        }
+    /// Transmits `data` to the receiver `to` like [`Xmodem::transmit()`], and
+    /// returns the statistics of the session.
+    pub fn transmit_with_stats<R, W>(data: R, to: W) -> Result<TransferStats>
+    where
+        W: io::Read + io::Write,
+        R: io::Read,
+    {
+        XmodemBuilder::new().transmit_with_stats(data, to)
+    }
 
-        Ok(received)
+    /// Receives data from `from` into `into` like [`Xmodem::receive()`], and
+    /// returns the statistics of the session.
+    pub fn receive_with_stats<R, W>(from: R, into: W) -> Result<TransferStats>
+    where
+        R: io::Read + io::Write,
+        W: io::Write,
+    {
+        XmodemBuilder::new().receive_with_stats(from, into)
     }
 }
 
+#[cfg(feature = "std")]
 impl<T: io::Read + io::Write> Xmodem<T> {
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
     /// `inner`. The returned instance can be used for both receiving
     /// (downloading) and sending (uploading).
     pub fn new(inner: T) -> Self {
-        Xmodem {
-            packet: 1,
-            started: false,
-            inner,
-            progress: progress::noop,
-        }
+        Xmodem::with_config(inner, Config::default(), Box::new(|_: &Status| {}))
     }
 
     /// Returns a new `Xmodem` instance with the internal reader/writer set to
@@ -149,192 +329,628 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// callback to indicate progress throughout the transfer. See the
     /// [`Progress`] enum for more information.
     pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
+        let progress = move |status: &Status| f(status.progress);
+        Xmodem::with_config(inner, Config::default(), Box::new(progress))
+    }
+
+    pub(crate) fn with_config(inner: T, config: Config, f: ProgressCallback) -> Self {
         Xmodem {
-            packet: 1,
-            started: false,
             inner,
+            receiver: Receiver::new(config, Checksum::Crc16),
+            sender: Sender::new(config),
+            checksum: Checksum::Crc16,
+            block_size: BlockSize::Standard,
+            padding_policy: PaddingPolicy::Keep,
             progress: f,
+            bytes: 0,
+            total: None,
+            retries: 0,
+            stats: TransferStats::default(),
+            checkpoint: Checkpoint::new(),
+            hasher: None,
+            config,
+            started_at: None,
+            deadline: None,
+            last_write: Instant::now(),
+            tap: None,
+            try_read: None,
+        }
+    }
+
+    /// Returns the inner reader/writer.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns how far [`Xmodem::send()`] or [`Xmodem::recv()`] got, to be
+    /// saved when the transfer fails and passed to [`Xmodem::resume()`] later.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.checkpoint
+    }
+
+    /// Returns the statistics of the session so far. The duration is measured
+    /// up to this call.
+    pub fn stats(&self) -> TransferStats {
+        let mut stats = self.stats;
+        if let Some(started_at) = self.started_at {
+            stats.duration = started_at.elapsed();
         }
+
+        stats
+    }
+
+    /// Enables or disables the verification of the whole transfer by
+    /// [`Xmodem::send()`] and [`Xmodem::recv()`] with `digest`. See
+    /// [`Digest`] for how the data is verified. Disabled by default.
+    pub fn set_verify(&mut self, digest: Option<Digest>) {
+        self.hasher = digest.map(Hasher::new);
     }
 
-    /// Reads a single byte from the inner I/O stream. If `abort_on_can` is
-    /// `true`, an error of `ConnectionAborted` is returned if the read byte is
-    /// `CAN`.
+    /// Resumes an interrupted transfer from `checkpoint`, which must have
+    /// been taken by the same side of the transfer.
     ///
-    /// # Errors
+    /// The session starts with the usual handshake and continues with the
+    /// packet after the checkpoint, so both sides must resume from matching
+    /// checkpoints: the receiver acknowledges and discards a copy of the last
+    /// packet it wrote, whose `ACK` the sender may have missed, but cancels
+    /// the session with `Error::PacketNumber` for any other packet. The data
+    /// passed to [`Xmodem::send()`] must start right after the checkpoint;
+    /// [`Checkpoint::verify()`] checks and skips the bytes before it.
+    pub fn resume(&mut self, checkpoint: Checkpoint) {
+        let packet = checkpoint.next_packet();
+        self.receiver.resume(packet);
+        self.sender.restart(packet);
+        self.checkpoint = checkpoint;
+        self.bytes = checkpoint.bytes();
+    }
+
+    /// Returns the packet checksum mode. Before a transfer has started this is
+    /// the mode a receiver will request; afterwards it is the mode that was
+    /// negotiated with the other side.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Sets the packet checksum mode a receiver requests from the sender. The
+    /// default is `Checksum::Crc16`. Senders use whichever mode the receiver
+    /// requests, so this has no effect on transmission.
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        self.checksum = checksum;
+        self.receiver.set_checksum(checksum);
+    }
+
+    /// Returns the packet size used by [`Xmodem::send()`].
+    pub fn block_size(&self) -> BlockSize {
+        self.block_size
+    }
+
+    /// Sets the packet size used by [`Xmodem::send()`]. The default is
+    /// `BlockSize::Standard`. 1024-byte packets should only be sent to
+    /// receivers that support XMODEM-1K, which typically request CRC-16: to
+    /// receivers that request the 8-bit checksum, `send()` sends 128-byte
+    /// packets.
+    pub fn set_block_size(&mut self, block_size: BlockSize) {
+        self.block_size = block_size;
+    }
+
+    /// Returns the padding policy used by [`Xmodem::recv()`].
+    pub fn padding_policy(&self) -> PaddingPolicy {
+        self.padding_policy
+    }
+
+    /// Sets what [`Xmodem::recv()`] does with the padding at the end of a
+    /// transmission. The default is `PaddingPolicy::Keep`.
+    pub fn set_padding_policy(&mut self, policy: PaddingPolicy) {
+        self.padding_policy = policy;
+    }
+
+    /// Sets the progress closure, replacing the progress callback. It is called
+    /// with a [`Status`] for every [`Progress`] event.
+    pub fn set_progress<F: FnMut(&Status) + Send + 'static>(&mut self, f: F) {
+        self.progress = Box::new(f);
+    }
+
+    /// Sets the number of bytes the transfer is expected to carry, reported
+    /// to the progress closure. By default, the total is unknown.
+    pub fn set_total(&mut self, total: Option<u64>) {
+        self.total = total;
+    }
+
+    /// Starts counting the bytes of a new transfer within the same session,
+    /// as YMODEM does for every file, with `total` bytes expected.
+    pub(crate) fn reset_progress(&mut self, total: Option<u64>) {
+        self.bytes = 0;
+        self.total = total;
+    }
+
+    /// Calls the progress closure with `progress` and the transfer's status.
+    fn report(&mut self, progress: Progress) {
+        let status = Status {
+            progress,
+            bytes: self.bytes,
+            total: self.total,
+            retries: self.retries,
+            elapsed: self
+                .started_at
+                .map_or(Duration::from_secs(0), |t| t.elapsed()),
+        };
+
+        (self.progress)(&status);
+    }
+
+    /// Sets the tracer that observes every byte sent and received from now
+    /// on, along with the protocol elements decoded from them. See
+    /// [`RingTracer`] and [`FrameDumper`].
+    pub fn set_tracer<U: Tracer + Send + 'static>(&mut self, tracer: U) {
+        self.tap = Some(Tap::new(Box::new(tracer)));
+    }
+
+    /// Sets how to read the bytes already received from the inner stream
+    /// without waiting for more: `f` reads into its buffer and returns the
+    /// number of bytes read, 0 if there are none. A read that fails with
+    /// `TimedOut` or `WouldBlock` also means there are none.
     ///
-    /// Returns an error if reading from the inner stream fails or if
-    /// `abort_on_can` is `true` and the read byte is `CAN`.
-    fn read_byte(&mut self, abort_on_can: bool) -> io::Result<u8> {
-        let mut buf = [0u8; 1];
-        self.inner.read_exact(&mut buf)?;
+    /// A generic stream can't be checked for input without waiting, so by
+    /// default the sender only reads the responses it waits for. With `f`
+    /// set, the bytes already received are discarded before every packet is
+    /// sent, so that a response duplicated by the line, or arriving late,
+    /// isn't taken for the response to that packet. They are also checked
+    /// after every streamed packet, when the receiver requested them with
+    /// `'G'`, so that a receiver cancelling the session is noticed right away
+    /// rather than at the end of the transmission.
+    pub fn set_try_read(&mut self, f: TryRead<T>) {
+        self.try_read = Some(f);
+    }
 
-        let byte = buf[0];
+    /// Transmits `data` to the receiver using the XMODEM protocol. If the
+    /// length of the total data yielded by `data` is not a multiple of 128
+    /// bytes, the data is padded with zeroes and sent to the receiver.
+    ///
+    /// The receiver's handshake is waited for before any data is read.
+    /// Packets are sent with the configured [`BlockSize`], or in 128-byte
+    /// packets if the receiver requested the 8-bit checksum. When 1024-byte
+    /// packets are used, data at the end of the transmission that fits in
+    /// seven or fewer 128-byte packets is sent in 128-byte packets to reduce
+    /// padding.
+    ///
+    /// Returns the number of bytes written, excluding padding zeroes. If
+    /// verification is enabled, the length and digest of the data are then
+    /// announced to the receiver, which fails the transfer with
+    /// `Error::Verification` if they don't match what it received.
+    pub fn send<R: io::Read>(&mut self, mut data: R) -> Result<usize> {
+        self.check_hasher()?;
+        self.wait_for_receiver()?;
+        let mut block = [0u8; 1024];
+        let mut written = 0;
+        loop {
+            let block_size = self.negotiated_block_size().size();
+            let n = data.read_max(&mut block[..block_size])?;
+            if n == 0 {
+                self.send_packet(&[])?;
+                if self.hasher.is_some() {
+                    self.send_verification()?;
+                }
 
-        unsafe {
-            if DEBUG_BUFFER_OFFSET < DEBUG_BUFFER.len() {
-                DEBUG_BUFFER[DEBUG_BUFFER_OFFSET] = byte;
-                DEBUG_BUFFER_OFFSET += 1;
+                return Ok(written);
             }
+
+            self.send_block(&mut block, n)?;
+            written += n;
         }
+    }
 
-        if abort_on_can && byte == CAN {
-            return Err(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "received CAN",
-            ));
+    /// Sends the first `n` bytes of `block`, at most one block's worth, padding
+    /// them to whole packets. `block` must hold 1024 bytes.
+    fn send_block(&mut self, block: &mut [u8], n: usize) -> Result<()> {
+        let packet_size = self.negotiated_block_size().packet_size(n);
+        let padded = n.div_ceil(packet_size) * packet_size;
+        let padding = self.config.padding;
+        block[n..padded].iter_mut().for_each(|b| *b = padding);
+        for (i, packet) in block[..padded].chunks(packet_size).enumerate() {
+            self.send_packet(packet)?;
+            let len = cmp::min(packet_size, n - i * packet_size);
+            self.checkpoint.commit(&packet[..len]);
+            self.stats.bytes += len as u64;
+            if let Some(ref mut hasher) = self.hasher {
+                hasher.update(&packet[..len]);
+            }
         }
 
-        Ok(byte)
+        Ok(())
     }
 
-    /// Writes a single byte to the inner I/O stream.
-    ///
-    /// # Errors
-    ///
-    /// Returns an error if writing to the inner stream fails.
-    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
-        self.inner.write_all(&[byte]).unwrap();
-        Ok(())
+    /// Returns `Error::InvalidInput` if the verification digest can't cover
+    /// the data before the checkpoint.
+    fn check_hasher(&self) -> Result<()> {
+        match self.hasher {
+            Some(ref hasher) if !hasher.covers(&self.checkpoint) => Err(Error::InvalidInput(
+                "SHA-256 verification can't be used with a resumed transfer",
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the verification block once the receiver has started a new
+    /// exchange, then ends the exchange. The receiver cancels the session if
+    /// the data doesn't match.
+    fn send_verification(&mut self) -> Result<()> {
+        let block = match self.hasher {
+            Some(ref hasher) => hasher.block(&self.checkpoint),
+            None => return Ok(()),
+        };
+
+        let bytes = self.bytes;
+        self.sender.restart(0);
+        self.send_packet(&block)?;
+        let result = match self.send_packet(&[]) {
+            Err(Error::Cancelled) => Err(Error::Verification),
+            result => result,
+        };
+
+        self.bytes = bytes;
+        result
+    }
+
+    /// Writes the packet `buf`, or the end of the transmission if `buf` is
+    /// empty, resending it when the receiver rejects it or doesn't respond.
+    /// After the configured number of attempts, the sender gives up with
+    /// `Error::RetriesExhausted` if the receiver kept rejecting the packet, or
+    /// with `Error::Timeout` if it stopped responding.
+    fn send_packet(&mut self, buf: &[u8]) -> Result<()> {
+        loop {
+            match self.write_packet(buf) {
+                Err(Error::Checksum) => continue,
+                result => return result.map(|_| ()),
+            }
+        }
     }
 
-    /// Reads a single byte from the inner I/O stream and compares it to `byte`.
-    /// If the bytes match, the byte is returned as an `Ok`. If they differ and
-    /// the read byte is not `CAN`, an error of `InvalidData` with the message
-    /// `expected` is returned. If they differ and the read byte is `CAN`, an
-    /// error of `ConnectionAborted` is returned. In either case, if they bytes
-    /// differ, a `CAN` byte is written out to the inner stream.
+    /// Receives data from the sender using the XMODEM protocol and writes it
+    /// into `into` as it arrives. Returns the number of bytes written.
     ///
-    /// # Errors
+    /// Packets are written out one packet late, so that the padding at the end
+    /// of the last one can be stripped according to the configured
+    /// [`PaddingPolicy`]. With the default, `PaddingPolicy::Keep`, the number
+    /// of bytes written is a multiple of 128. If the session fails, the packet
+    /// held back is written out before the error is returned, so that the
+    /// [`Xmodem::checkpoint()`] covers every packet acknowledged.
     ///
-    /// Returns an error if reading from the inner stream fails, if the read
-    /// byte was not `byte`, if the read byte was `CAN` and `byte` is not `CAN`,
-    /// or if writing the `CAN` byte failed on byte mismatch.
-    fn expect_byte_or_cancel(&mut self, byte: u8, msg: &'static str) -> io::Result<u8> {
-        match self.expect_byte(byte, msg) {
-            Ok(b) => Ok(b),
+    /// If verification is enabled, the length and digest announced by the
+    /// sender after the data are checked: the last packet is truncated to the
+    /// announced length and the session is cancelled with
+    /// `Error::Verification` if the data doesn't match.
+    pub fn recv<W: io::Write>(&mut self, mut into: W) -> Result<usize> {
+        self.check_hasher()?;
+        let mut packet = [0u8; 1024];
+        let mut held = [0u8; 1024];
+        let mut held_len = 0;
+        let start = self.checkpoint.bytes();
+        loop {
+            let n = match self.recv_packet(&mut packet) {
+                Ok(n) => n,
+                Err(e) => {
+                    if held_len > 0 {
+                        let _ = self.commit(&mut into, &held[..held_len]);
+                    }
+
+                    return Err(e);
+                }
+            };
+
+            if n == 0 && self.hasher.is_some() {
+                self.recv_verification(&mut into, &held[..held_len])?;
+                return Ok((self.checkpoint.bytes() - start) as usize);
+            }
+
+            if held_len > 0 {
+                let len = match n {
+                    0 => self.padding_policy.strip(&held[..held_len]),
+                    _ => held_len,
+                };
+
+                self.commit(&mut into, &held[..len])?;
+            }
+
+            if n == 0 {
+                return Ok((self.checkpoint.bytes() - start) as usize);
+            }
+
+            held[..n].copy_from_slice(&packet[..n]);
+            held_len = n;
+        }
+    }
+
+    /// Starts a new exchange to receive the verification block, and checks
+    /// the data written so far followed by `last`, the last packet, against
+    /// it. On success, writes the part of `last` within the announced length
+    /// and ends the exchange; otherwise, cancels the session.
+    fn recv_verification<W: io::Write>(&mut self, into: &mut W, last: &[u8]) -> Result<()> {
+        let bytes = self.bytes;
+        let mut block = [0u8; 1024];
+        self.receiver.restart(0);
+        let n = self.recv_packet(&mut block)?;
+        self.bytes = bytes;
+
+        let checked = match self.hasher {
+            Some(ref hasher) => hasher.check(&block[..n], &self.checkpoint, last),
+            None => return Ok(()),
+        };
+
+        let len = match checked {
+            Ok(len) => len,
             Err(e) => {
-                self.write_byte(CAN)?;
-                Err(e)
+                let _ = self.cancel();
+                return Err(e);
             }
+        };
+
+        if len > 0 {
+            self.commit(into, &last[..len])?;
+        }
+
+        match self.recv_packet(&mut block)? {
+            0 => Ok(()),
+            _ => Err(Error::Protocol("expected EOT after the verification block")),
         }
     }
 
-    /// Reads a single byte from the inner I/O stream and compares it to `byte`.
-    /// If they differ, an error of `InvalidData` with the message `expected` is
-    /// returned. Otherwise the byte is returned. If `byte` is not `CAN` and the
-    /// read byte is `CAN`, a `ConnectionAborted` error is returned.
+    /// Writes the payload of a received packet into `into`, truncated
+    /// according to the padding policy, and adds it to the checkpoint.
+    fn commit<W: io::Write>(&mut self, into: &mut W, data: &[u8]) -> Result<()> {
+        let limit = match self.padding_policy {
+            PaddingPolicy::Truncate(len) => len,
+            _ => u64::MAX,
+        };
+
+        let left = limit.saturating_sub(self.checkpoint.bytes());
+        let data = &data[..cmp::min(data.len() as u64, left) as usize];
+        into.write_all(data)?;
+        self.checkpoint.commit(data);
+        self.stats.bytes += data.len() as u64;
+        if let Some(ref mut hasher) = self.hasher {
+            hasher.update(data);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a packet into `buf`, waiting for the sender to resend it when its
+    /// checksum fails. After the configured number of attempts, the receiver
+    /// gives up with `Error::RetriesExhausted` if the packets kept arriving
+    /// corrupted, or with `Error::Timeout` if the sender stopped sending.
+    fn recv_packet(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            match self.read_packet(buf) {
+                Err(Error::Checksum) => continue,
+                result => return result,
+            }
+        }
+    }
+
+    /// Prepares for a new exchange within the same session: the next packet is
+    /// numbered `packet` and the receiver's `'C'`/`NAK` handshake is performed
+    /// again. The negotiated checksum mode is kept.
+    fn restart(&mut self, packet: u8) {
+        self.receiver.restart(packet);
+        self.sender.restart(packet);
+    }
+
+    /// Starts the session timer, if it isn't running yet and a session timeout
+    /// is configured.
+    fn start_session(&mut self) {
+        if self.started_at.is_none() {
+            self.started_at = Some(Instant::now());
+        }
+
+        if self.deadline.is_none() {
+            self.deadline = self.config.session_timeout.map(|t| Instant::now() + t);
+        }
+    }
+
+    /// Aborts the session by writing the configured number of `CAN` bytes.
+    fn cancel(&mut self) -> Result<()> {
+        self.stats.cancels += 1;
+        for _ in 0..self.config.cancel_count {
+            self.inner.write_all(&[CAN])?;
+            self.trace(Direction::Tx, &[CAN]);
+        }
+
+        self.flush()
+    }
+
+    /// Cancels the session and returns `Error::Timeout` if the session timeout
+    /// has passed.
+    fn check_deadline(&mut self) -> Result<()> {
+        if self
+            .deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.cancel()?;
+            return Err(Error::Timeout);
+        }
+
+        Ok(())
+    }
+
+    /// Reads a single byte from the inner I/O stream.
     ///
     /// # Errors
     ///
-    /// Returns an error if reading from the inner stream fails, or if the read
-    /// byte was not `byte`. If the read byte differed and was `CAN`, an error
-    /// of `ConnectionAborted` is returned. Otherwise, the error kind is
-    /// `InvalidData`.
-    fn expect_byte(&mut self, byte: u8, expected: &'static str) -> io::Result<u8> {
-        match self.read_byte(byte != CAN)? {
-            b if b == byte => Ok(b),
-            b if b == CAN => Err(io::Error::new(
-                io::ErrorKind::ConnectionAborted,
-                "connection aborted",
-            )),
-            _ => Err(io::Error::new(io::ErrorKind::InvalidData, expected)),
+    /// Returns an error if reading from the inner stream fails.
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.inner.read_exact(&mut buf)?;
+
+        self.trace(Direction::Rx, &buf);
+        Ok(buf[0])
+    }
+
+    /// Passes `bytes` to the tracer, if there is one.
+    fn trace(&mut self, direction: Direction, bytes: &[u8]) {
+        if let Some(ref mut tap) = self.tap {
+            tap.record(direction, bytes, self.checksum);
         }
     }
 
-    fn handle_receive_eot(&mut self) -> io::Result<()> {
-        self.write_byte(NAK)?;
-        self.expect_byte_or_cancel(EOT, "expected EOT")?;
-        self.write_byte(ACK)?;
+    /// Returns `true` if a read timeout should be reported to the state
+    /// machines: at least one handshake interval has passed since the last
+    /// write.
+    fn interval_passed(&self) -> bool {
+        self.last_write.elapsed() >= self.config.handshake_interval
+    }
+
+    /// Writes the receiver's pending output to the inner stream.
+    fn write_receiver_output(&mut self) -> Result<()> {
+        let output = self.receiver.take_output();
+        if output.first() == Some(&CAN) {
+            self.stats.cancels += 1;
+        }
+
+        if !output.is_empty() {
+            self.inner.write_all(output)?;
+            self.inner.flush()?;
+            self.last_write = Instant::now();
+            if let Some(ref mut tap) = self.tap {
+                tap.record(Direction::Tx, output, self.checksum);
+            }
+        }
+
         Ok(())
     }
 
-    fn handle_send_eot(&mut self) -> io::Result<()> {
-        self.write_byte(EOT)?;
-        self.expect_byte_or_cancel(NAK, "expected NAK")?;
-        self.write_byte(EOT)?;
-        self.expect_byte_or_cancel(ACK, "expected ACK")?;
+    /// Writes the sender's pending output to the inner stream.
+    fn write_sender_output(&mut self) -> Result<()> {
+        let output = self.sender.take_output();
+        if output.first() == Some(&CAN) {
+            self.stats.cancels += 1;
+        }
+
+        if !output.is_empty() {
+            self.inner.write_all(output)?;
+            self.inner.flush()?;
+            self.last_write = Instant::now();
+            if let Some(ref mut tap) = self.tap {
+                tap.record(Direction::Tx, output, self.checksum);
+            }
+        }
+
         Ok(())
     }
 
     /// Reads (downloads) a single packet from the inner stream using the XMODEM
-    /// protocol. On success, returns the number of bytes read (always 128).
+    /// protocol. On success, returns the number of bytes read: 128 for an `SOH`
+    /// packet or 1024 for an `STX` packet.
+    ///
+    /// The first call starts the session: `'C'` is sent to request CRC-16
+    /// packets, falling back to `NAK` and the 8-bit checksum if the sender
+    /// doesn't respond. See [`Xmodem::set_checksum()`].
+    ///
+    /// A resent copy of the previous packet is acknowledged and discarded, and
+    /// bytes that can't start a packet are skipped.
     ///
     /// The progress callback is called with `Progress::Start` when reception
     /// for the first packet has started and subsequently with
-    /// `Progress::Packet` when a packet is received successfully.
+    /// `Progress::Packet` when a packet is received successfully. Discarded
+    /// duplicates, corrupted packets and skipped noise are reported with
+    /// `Progress::Duplicate`, `Progress::Corrupted` and `Progress::Resync`.
     ///
     /// # Errors
     ///
     /// Returns an error if reading or writing to the inner stream fails at any
     /// point. Also returns an error if the XMODEM protocol indicates an error.
-    /// In particular, an `InvalidData` error is returned when:
+    /// A packet that doesn't arrive in time is requested again with a `NAK`,
+    /// and `Error::Timeout` is returned once the configured number of retries
+    /// is exhausted.
     ///
-    ///   * The sender's first byte for a packet isn't `EOT` or `SOH`.
-    ///   * The sender doesn't send a second `EOT` after the first.
-    ///   * The received packet numbers don't match the expected values.
+    /// `Error::PacketNumber` is returned if a packet is neither the expected
+    /// one nor a copy of the previous one.
     ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
+    /// `Error::Checksum` is returned if a packet checksum or CRC fails, or a
+    /// packet number doesn't match its complement. The packet has been
+    /// rejected and the next call receives it again.
     ///
-    /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
-    /// received when not expected.
+    /// `Error::Cancelled` is returned if the sender sends two consecutive
+    /// `CAN` bytes outside of a packet.
     ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128`.
-    pub fn read_packet(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        if buf.len() != 128 {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
+    /// `Error::InvalidInput` is returned if `buf.len() < 128`, or if
+    /// `buf.len() < 1024` and the sender sends an `STX` packet. In the latter
+    /// case, a `CAN` byte is written out to end the session.
+    pub fn read_packet(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if buf.len() < 128 {
+            return Err(Error::InvalidInput(
                 "output buffer must be at least 128 bytes",
             ));
         }
 
-        if !self.started {
-            self.write_byte(NAK)?;
-        }
+        self.receiver.set_max_block_size(if buf.len() < 1024 {
+            BlockSize::Standard
+        } else {
+            BlockSize::OneK
+        });
 
-        match self.read_byte(true)? {
-            b if b == EOT => {
-                self.handle_receive_eot()?;
-                return Ok(0);
-            }
-            b if b == SOH => {
-                if !self.started {
-                    (self.progress)(Progress::Started);
-                    self.started = true;
-                }
-            }
-            _ => {
-                self.write_byte(CAN)?;
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "expected SOH or EOT",
-                ));
-            }
-        };
+        self.start_session();
+        self.receiver.start();
+        let waiting = Instant::now();
+        loop {
+            self.write_receiver_output()?;
+            self.checksum = self.receiver.checksum();
+            self.check_deadline()?;
+            let byte = match self.read_byte() {
+                Ok(byte) => byte,
+                Err(Error::Timeout) if !self.interval_passed() => continue,
+                Err(Error::Timeout) => {
+                    let started = self.receiver.is_started();
+                    let event = self.receiver.timeout();
+                    self.write_receiver_output()?;
+                    if let Some(Event::Timeout(_)) = event? {
+                        if started {
+                            self.retries += 1;
+                        }
+
+                        self.stats.timeouts += 1;
+                    }
 
-        let packet = self.packet;
-        self.expect_byte_or_cancel(packet, "invalid packet number")?;
-        self.expect_byte_or_cancel(255 - packet, "invalid 1s complement of packet number")?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
 
-        let mut packet_buf: [u8; 128] = [0; 128];
-        let n = self.inner.read_max(&mut packet_buf)?;
-        if n != 128 {
-            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "short read"));
-        }
+            let event = self.receiver.feed(byte);
+            self.write_receiver_output()?;
+            match event {
+                Err(Error::Checksum) => {
+                    self.retries += 1;
+                    self.stats.naks += 1;
+                    let number = self.receiver.packet_number();
+                    self.report(Progress::Corrupted(number));
+                }
+                Err(Error::Cancelled) => self.stats.cancels += 1,
+                _ => {}
+            }
 
-        let checksum: u8 = packet_buf.iter().fold(0, |a: u8, b| a.wrapping_add(*b));
-        if checksum == self.read_byte(false)? {
-            self.write_byte(ACK)?;
-            buf.copy_from_slice(&packet_buf);
-            self.packet = self.packet.wrapping_add(1);
-            (self.progress)(Progress::Packet(self.packet));
-            Ok(128)
-        } else {
-            self.write_byte(NAK)?;
-            Err(io::Error::new(
-                io::ErrorKind::Interrupted,
-                "invalid checksum",
-            ))
+            match event? {
+                Some(Event::Started) => {
+                    self.stats.handshake += waiting.elapsed();
+                    self.report(Progress::Started);
+                }
+                Some(Event::Duplicate(n)) => {
+                    self.retries += 1;
+                    self.stats.duplicates += 1;
+                    self.report(Progress::Duplicate(n));
+                }
+                Some(Event::Resync) => self.report(Progress::Resync),
+                Some(Event::Packet(n)) => {
+                    let packet = self.receiver.packet();
+                    let len = packet.len();
+                    buf[..len].copy_from_slice(packet);
+                    self.bytes += len as u64;
+                    self.stats.packets += 1;
+                    self.report(Progress::Packet(n));
+                    return Ok(len);
+                }
+                Some(Event::Done) => return Ok(0),
+                _ => continue,
+            }
         }
     }
 
@@ -344,77 +960,177 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     /// transmission is complete. On success, returns the number of bytes
     /// written.
     ///
+    /// `buf` must hold exactly 128 or 1024 bytes. A 128-byte packet is sent
+    /// with `SOH`, a 1024-byte packet with `STX`.
+    ///
+    /// The first call waits for the receiver to start the session and uses the
+    /// variant it requested: the 8-bit checksum for `NAK`, CRC-16 for `'C'`,
+    /// or streamed CRC-16 packets for `'G'`, which aren't acknowledged. Bytes
+    /// received before the handshake are skipped.
+    ///
     /// The progress callback is called with `Progress::Waiting` before waiting
-    /// for the receiver's `NAK`, `Progress::Start` when transmission of the
-    /// first packet has started and subsequently with `Progress::Packet` when a
-    /// packet is sent successfully.
+    /// for the receiver's handshake, `Progress::Resync` if bytes before it are
+    /// skipped, `Progress::Start` and `Progress::Negotiated` when transmission
+    /// of the first packet has started and subsequently with `Progress::Packet`
+    /// when a packet is sent successfully.
     ///
     /// # Errors
     ///
     /// Returns an error if reading or writing to the inner stream fails at any
     /// point. Also returns an error if the XMODEM protocol indicates an error.
-    /// In particular, an `InvalidData` error is returned when:
-    ///
-    ///   * The receiver's first byte isn't a `NAK`.
-    ///   * The receiver doesn't respond with a `NAK` to the first `EOT`.
-    ///   * The receiver doesn't respond with an `ACK` to the second `EOT`.
-    ///   * The receiver responds to a complete packet with something besides
-    ///     `ACK` or `NAK`.
-    ///
-    /// An error of kind `UnexpectedEof` is returned if `buf.len() < 128 &&
-    /// buf.len() != 0`.
-    ///
-    /// An error of kind `ConnectionAborted` is returned if a `CAN` byte is
-    /// received when not expected.
-    ///
-    /// An error of kind `Interrupted` is returned if a packet checksum fails.
-    pub fn write_packet(&mut self, buf: &[u8]) -> io::Result<usize> {
-        if !self.started {
-            (self.progress)(Progress::Waiting);
-            self.expect_byte_or_cancel(NAK, "expected NAK")?;
-            (self.progress)(Progress::Started);
-            self.started = true
+    /// A packet whose response is lost or garbled is sent again, and
+    /// `Error::Timeout` is returned once the configured number of retries is
+    /// exhausted.
+    ///
+    /// `Error::InvalidInput` is returned if `buf.len()` isn't 0, 128 or 1024.
+    ///
+    /// `Error::Cancelled` is returned if the receiver sends two consecutive
+    /// `CAN` bytes.
+    ///
+    /// `Error::Checksum` is returned if the receiver rejects the packet, or
+    /// the end of the transmission, with a `NAK`. The next call must send it
+    /// again.
+    pub fn write_packet(&mut self, buf: &[u8]) -> Result<usize> {
+        self.wait_for_receiver()?;
+        self.discard_input()?;
+        self.sender.send_packet(buf)?;
+        loop {
+            self.write_sender_output()?;
+            let event = match self.sender.take_event() {
+                Some(event) => self.check_input().map(|_| Some(event)),
+                None => self.wait_for_sender(),
+            };
+            if let Err(Error::Checksum) = event {
+                self.retries += 1;
+                self.stats.naks += 1;
+            }
+
+            match event? {
+                Some(Event::Packet(n)) => {
+                    self.bytes += buf.len() as u64;
+                    self.stats.packets += 1;
+                    self.report(Progress::Packet(n));
+                    return Ok(buf.len());
+                }
+                Some(Event::Done) => return Ok(0),
+                Some(Event::Timeout(_)) => {
+                    self.retries += 1;
+                    self.stats.timeouts += 1;
+                    self.discard_input()?;
+                    self.sender.send_packet(buf)?;
+                }
+                Some(Event::Garbled(_)) => {
+                    self.retries += 1;
+                    self.stats.bad_acks += 1;
+                    self.discard_input()?;
+                    self.sender.send_packet(buf)?;
+                }
+                _ => continue,
+            }
         }
+    }
 
-        if buf.len() == 0 {
-            self.handle_send_eot()?;
-            return Ok(0);
-        } else if buf.len() != 128 {
-            return Err(io::Error::new(
-                io::ErrorKind::UnexpectedEof,
-                "invalid packet length",
-            ));
+    /// Starts the session, if it hasn't started yet, by waiting for the
+    /// receiver's handshake. Bytes before it are skipped. The negotiated mode
+    /// is reported with `Progress::Negotiated`.
+    fn wait_for_receiver(&mut self) -> Result<()> {
+        self.start_session();
+        if self.sender.is_started() {
+            return Ok(());
         }
 
-        self.write_byte(SOH)?;
-        self.flush()?;
-
-        let packet = self.packet;
-        self.write_byte(packet)?;
-        self.write_byte(255 - packet)?;
-
-        let checksum = buf.iter().fold(0, |a: u8, b| a.wrapping_add(*b));
-        self.inner.write_max(&buf[..128])?;
-        self.write_byte(checksum)?;
-        let b = self.read_byte(true)?;
-        match b {
-            b if b == ACK => {
-                (self.progress)(Progress::Packet(self.packet));
-                self.packet = self.packet.wrapping_add(1);
-                Ok(128)
+        self.report(Progress::Waiting);
+        let waiting = Instant::now();
+        loop {
+            match self.wait_for_sender()? {
+                Some(Event::Started) => break,
+                Some(Event::Resync) => self.report(Progress::Resync),
+                _ => continue,
             }
-            b if b == NAK => Err(io::Error::new(
-                io::ErrorKind::Interrupted,
-                "receiver rejected checksum",
-            )),
-            _ => {
-                self.write_byte(CAN)?;
-                Err(io::Error::new(
-                    io::ErrorKind::InvalidData,
-                    "expected ACK or NAK",
-                ))
+        }
+
+        self.stats.handshake += waiting.elapsed();
+        self.checksum = self.sender.checksum();
+        self.report(Progress::Started);
+        self.report(Progress::Negotiated {
+            checksum: self.checksum,
+            block_size: self.negotiated_block_size(),
+            streaming: self.sender.is_streaming(),
+        });
+
+        Ok(())
+    }
+
+    /// Returns the packet size [`Xmodem::send()`] uses with the receiver: the
+    /// configured one, or 128 bytes if the receiver requested the 8-bit
+    /// checksum, as receivers that support XMODEM-1K request CRC-16.
+    fn negotiated_block_size(&self) -> BlockSize {
+        match self.sender.checksum() {
+            Checksum::Standard => BlockSize::Standard,
+            Checksum::Crc16 => self.block_size,
+        }
+    }
+
+    /// Reads a byte from the receiver, passes it, or the read timeout, to the
+    /// sender state machine and writes out the sender's response. Returns the
+    /// resulting event.
+    fn wait_for_sender(&mut self) -> Result<Option<Event>> {
+        self.check_deadline()?;
+        let event = match self.read_byte() {
+            Ok(byte) => self.sender.feed(byte),
+            Err(Error::Timeout) => self.sender.timeout(),
+            Err(e) => return Err(e),
+        };
+
+        self.write_sender_output()?;
+        if let Err(Error::Cancelled) = event {
+            self.stats.cancels += 1;
+        }
+
+        event
+    }
+
+    /// Passes the bytes already received to the sender state machine before a
+    /// packet is queued. The sender discards them, unless they cancel the
+    /// session, so that a late or duplicated response to the previous packet
+    /// isn't taken for the response to the next one.
+    fn discard_input(&mut self) -> Result<()> {
+        while self.check_input()? == INPUT_CHUNK {}
+        Ok(())
+    }
+
+    /// Passes the bytes already received to the sender state machine, if
+    /// they can be read without waiting (see [`Xmodem::set_try_read()`]), so
+    /// that a streaming sender notices a cancellation. Returns the number of
+    /// bytes read, at most `INPUT_CHUNK`.
+    fn check_input(&mut self) -> Result<usize> {
+        let mut buf = [0u8; INPUT_CHUNK];
+        let n = match self.try_read {
+            Some(f) => match f(&mut self.inner, &mut buf) {
+                Ok(n) => n,
+                Err(ref e)
+                    if e.kind() == io::ErrorKind::TimedOut
+                        || e.kind() == io::ErrorKind::WouldBlock =>
+                {
+                    0
+                }
+                Err(e) => return Err(e.into()),
+            },
+            None => return Ok(0),
+        };
+
+        self.trace(Direction::Rx, &buf[..n]);
+        for &byte in &buf[..n] {
+            let event = self.sender.feed(byte);
+            self.write_sender_output()?;
+            if let Err(Error::Cancelled) = event {
+                self.stats.cancels += 1;
             }
+
+            event?;
         }
+
+        Ok(n)
     }
 
     /// Flush this output stream, ensuring that all intermediately buffered
@@ -424,7 +1140,7 @@ impl<T: io::Read + io::Write> Xmodem<T> {
     ///
     /// It is considered an error if not all bytes could be written due to I/O
     /// errors or EOF being reached.
-    pub fn flush(&mut self) -> io::Result<()> {
-        self.inner.flush()
+    pub fn flush(&mut self) -> Result<()> {
+        Ok(self.inner.flush()?)
     }
 }
@@ -0,0 +1,818 @@
+use builder::Config;
+use {BlockSize, Checksum, Error, Result};
+use {ACK, CAN, CRC, CRC_HANDSHAKE_ATTEMPTS, EOT, NAK, SOH, STREAMING, STX};
+
+/// Largest packet on the wire: header, packet number and its complement, a
+/// 1024-byte payload and a CRC-16.
+const MAX_PACKET_SIZE: usize = 3 + 1024 + 2;
+
+/// Number of read timeouts a sender waits for the response to a packet
+/// before sending it again. A purging receiver only rejects a corrupted
+/// packet once a read has timed out, so the sender waits longer.
+const RESEND_TIMEOUTS: usize = 2;
+
+/// Something that happened in a session that the driver of a [`Receiver`] or
+/// [`Sender`] should act on.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// The session started: the receiver received the first packet header, or
+    /// the sender received the receiver's handshake.
+    Started,
+    /// Packet `.0` was received and verified, or sent and acknowledged.
+    Packet(u8),
+    /// Packet `.0`, the one received before, was received again because the
+    /// sender missed its `ACK`. It was acknowledged again and its payload
+    /// discarded.
+    Duplicate(u8),
+    /// The receiver skipped bytes that can't start a packet, such as line
+    /// noise, to find the next packet header, or the sender skipped bytes
+    /// that aren't a handshake.
+    Resync,
+    /// No byte arrived in time. The receiver requested packet `.0` again,
+    /// with its handshake or a `NAK`; the sender must queue packet `.0`, or
+    /// the end of the transmission, again with [`Sender::send_packet()`].
+    Timeout(u8),
+    /// The sender received something other than `ACK` or `NAK` in response
+    /// to packet `.0`, or to the end of the transmission, and must queue it
+    /// again with [`Sender::send_packet()`].
+    Garbled(u8),
+    /// The end of the transmission was acknowledged.
+    Done,
+}
+
+/// Bytes waiting to be written to the other side.
+struct Output {
+    buf: [u8; MAX_PACKET_SIZE],
+    len: usize,
+}
+
+impl Output {
+    fn new() -> Output {
+        Output {
+            buf: [0; MAX_PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.extend(&[byte]);
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        let end = self.len + bytes.len();
+        self.buf[self.len..end].copy_from_slice(bytes);
+        self.len = end;
+    }
+
+    /// Replaces anything pending with `count` `CAN` bytes, as many as fit.
+    fn cancel(&mut self, count: usize) {
+        self.len = count.min(MAX_PACKET_SIZE);
+        self.buf[..self.len].iter_mut().for_each(|b| *b = CAN);
+    }
+
+    fn take(&mut self) -> &[u8] {
+        let len = self.len;
+        self.len = 0;
+        &self.buf[..len]
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum RxState {
+    /// The session hasn't been started.
+    Idle,
+    /// Waiting for `SOH`, `STX` or `EOT`.
+    Header,
+    /// Waiting for the packet number.
+    Number,
+    /// Waiting for the 1s complement of packet number `.0`.
+    Complement(u8),
+    /// Receiving the payload.
+    Data,
+    /// Receiving the checksum or CRC.
+    Trailer,
+    /// Discarding the `.0` bytes left of a corrupted packet, to reject it.
+    Skip(usize),
+    /// Discarding bytes until the line is quiet, to reject a corrupted packet.
+    Purge,
+    /// Waiting for the second `EOT`.
+    Eot,
+    /// The transmission ended.
+    Done,
+}
+
+/// The receiving side of an XMODEM session as a state machine without I/O.
+///
+/// Bytes received from the sender are passed to [`Receiver::feed()`] one at a
+/// time, and read timeouts are reported with [`Receiver::timeout()`]. After
+/// each call, the bytes returned by [`Receiver::take_output()`] must be
+/// written to the sender. The receiver never allocates and works without the
+/// `std` feature.
+///
+/// # Example
+///
+/// ```rust
+/// use xmodem::{Event, Receiver, XmodemBuilder};
+///
+/// let mut receiver = XmodemBuilder::new().receiver();
+/// receiver.start();
+/// assert_eq!(receiver.take_output(), b"C");
+///
+/// assert_eq!(receiver.feed(0x04).unwrap(), None);
+/// assert_eq!(receiver.take_output(), &[0x15]);
+/// assert_eq!(receiver.feed(0x04).unwrap(), Some(Event::Done));
+/// assert_eq!(receiver.take_output(), &[0x06]);
+/// ```
+pub struct Receiver {
+    config: Config,
+    state: RxState,
+    checksum: Checksum,
+    max_block_size: BlockSize,
+    /// Number of the next packet.
+    packet: u8,
+    /// Number of the packet received last, if any.
+    previous: Option<u8>,
+    /// Whether the current packet is a duplicate of the previous one.
+    duplicate: bool,
+    /// Whether bytes are being skipped to find the next packet header.
+    resyncing: bool,
+    /// Whether the last byte was a `CAN` that may start a cancellation.
+    cancelling: bool,
+    started: bool,
+    /// Number of handshake bytes sent that went unanswered.
+    handshakes: usize,
+    /// Number of consecutive packets that failed their checksum.
+    errors: usize,
+    /// Size of the payload of the current packet.
+    size: usize,
+    /// Number of payload or trailer bytes of the current packet received.
+    len: usize,
+    data: [u8; 1024],
+    trailer: [u8; 2],
+    output: Output,
+}
+
+impl Receiver {
+    pub(crate) fn new(config: Config, checksum: Checksum) -> Receiver {
+        Receiver {
+            config,
+            state: RxState::Idle,
+            checksum,
+            max_block_size: BlockSize::OneK,
+            packet: 1,
+            previous: None,
+            duplicate: false,
+            resyncing: false,
+            cancelling: false,
+            started: false,
+            handshakes: 0,
+            errors: 0,
+            size: 0,
+            len: 0,
+            data: [0; 1024],
+            trailer: [0; 2],
+            output: Output::new(),
+        }
+    }
+
+    /// Returns the packet checksum mode: the mode that will be requested
+    /// before the session has started, the negotiated mode afterwards.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Sets the packet checksum mode requested from the sender. Has no effect
+    /// once the session has started.
+    pub fn set_checksum(&mut self, checksum: Checksum) {
+        if !self.started {
+            self.checksum = checksum;
+        }
+    }
+
+    /// Sets the largest packet accepted. A sender that sends a larger packet
+    /// is cancelled. The default is `BlockSize::OneK`.
+    pub fn set_max_block_size(&mut self, block_size: BlockSize) {
+        self.max_block_size = block_size;
+    }
+
+    /// Returns `true` once the first packet header has been received.
+    pub fn is_started(&self) -> bool {
+        self.started
+    }
+
+    /// Returns the number of the next packet expected.
+    pub fn packet_number(&self) -> u8 {
+        self.packet
+    }
+
+    /// Returns the payload of the packet reported by the last
+    /// `Event::Packet`.
+    pub fn packet(&self) -> &[u8] {
+        &self.data[..self.size]
+    }
+
+    /// Returns the bytes that must be written to the sender, and forgets them.
+    pub fn take_output(&mut self) -> &[u8] {
+        self.output.take()
+    }
+
+    /// Starts the session by requesting the first packet: `'C'` for CRC-16 or
+    /// `NAK` for the 8-bit checksum. Has no effect if the session has already
+    /// been started.
+    pub fn start(&mut self) {
+        if self.state == RxState::Idle {
+            self.handshakes = 0;
+            self.state = RxState::Header;
+            self.request();
+        }
+    }
+
+    /// Prepares for a new exchange within the same session, as YMODEM does for
+    /// every file: the next packet is numbered `packet` and the handshake is
+    /// performed again. The negotiated checksum mode is kept.
+    pub fn restart(&mut self, packet: u8) {
+        self.packet = packet;
+        self.started = false;
+        self.state = RxState::Idle;
+    }
+
+    /// Prepares to resume an interrupted session whose packets before
+    /// `packet` were received: the handshake is performed again and the next
+    /// packet is numbered `packet`. A copy of the packet before it, whose
+    /// `ACK` the sender may have missed, is acknowledged again and reported as
+    /// `Event::Duplicate`.
+    pub fn resume(&mut self, packet: u8) {
+        self.restart(packet);
+        self.previous = Some(packet.wrapping_sub(1));
+    }
+
+    /// Reports that no byte arrived in time.
+    ///
+    /// Before the first packet, the request for it is sent again, up to the
+    /// configured number of handshake retries. After `CRC_HANDSHAKE_ATTEMPTS`
+    /// unanswered `'C'` requests, the sender is assumed to only support the
+    /// 8-bit checksum and `NAK` is sent instead.
+    ///
+    /// Once the session has started, the packet expected, or the second
+    /// `EOT`, is requested again with a `NAK`: the packet or its `ACK` was
+    /// lost. Either request returns `Event::Timeout`. A `NAK` sent after a
+    /// timeout counts as a failure of the packet, like a failed checksum.
+    ///
+    /// While the rest of a corrupted packet is being skipped or the line is
+    /// being purged, the timeout means the line is quiet: the packet is
+    /// rejected with a `NAK`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Timeout` once the handshake retries are exhausted or,
+    /// after the configured number of consecutive failures, once the session
+    /// has started. In the latter case, the session is cancelled.
+    pub fn timeout(&mut self) -> Result<Option<Event>> {
+        match self.state {
+            RxState::Idle | RxState::Done => return Err(Error::Timeout),
+            RxState::Skip(_) | RxState::Purge => {
+                self.output.push(NAK);
+                self.state = RxState::Header;
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        if !self.started && self.state == RxState::Header {
+            self.handshakes += 1;
+            if self.handshakes >= self.config.handshake_retries {
+                return Err(Error::Timeout);
+            }
+
+            self.request();
+            return Ok(Some(Event::Timeout(self.packet)));
+        }
+
+        self.errors += 1;
+        if self.errors >= self.config.retries {
+            self.state = RxState::Header;
+            self.output.cancel(self.config.cancel_count);
+            return Err(Error::Timeout);
+        }
+
+        self.output.push(NAK);
+        if self.state != RxState::Eot {
+            self.state = RxState::Header;
+        }
+
+        Ok(Some(Event::Timeout(self.packet)))
+    }
+
+    /// Processes the byte `byte` received from the sender.
+    ///
+    /// Returns `Event::Started` when the first packet header arrives,
+    /// `Event::Packet` when a packet has been verified and acknowledged (its
+    /// payload is available from [`Receiver::packet()`]) and `Event::Done`
+    /// once the end of the transmission has been acknowledged.
+    ///
+    /// A verified copy of the previous packet is acknowledged again and
+    /// reported as `Event::Duplicate`. Bytes that can't start a packet are
+    /// skipped; `Event::Resync` is returned for the first one skipped.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Checksum` is returned when a packet fails its checksum, or its
+    /// packet number doesn't match its 1s complement. The rest of the packet
+    /// is skipped and the packet is rejected with a `NAK`; if purging is
+    /// configured, once the line is quiet (see [`Receiver::timeout()`]). The
+    /// session continues with the sender resending it; after the configured
+    /// number of consecutive failures, the session is cancelled with
+    /// `Error::RetriesExhausted` instead.
+    ///
+    /// Every other error ends the session:
+    ///
+    ///   * `Error::Cancelled` if the sender cancels the session with two
+    ///     consecutive `CAN` bytes.
+    ///   * `Error::PacketNumber` if a packet is neither the expected one nor a
+    ///     duplicate of the previous one.
+    ///   * `Error::InvalidInput` if the sender sends a packet larger than
+    ///     [`Receiver::set_max_block_size()`] allows.
+    ///
+    /// In all but the first case, the session is cancelled.
+    pub fn feed(&mut self, byte: u8) -> Result<Option<Event>> {
+        let cancelling = self.cancelling;
+        self.cancelling = false;
+        match self.state {
+            RxState::Idle | RxState::Done | RxState::Purge => Ok(None),
+            RxState::Header | RxState::Eot => match byte {
+                EOT if self.state == RxState::Eot => {
+                    self.output.push(ACK);
+                    self.state = RxState::Done;
+                    Ok(Some(Event::Done))
+                }
+                EOT => {
+                    self.output.push(NAK);
+                    self.state = RxState::Eot;
+                    Ok(None)
+                }
+                SOH => Ok(self.begin(128)),
+                STX if self.max_block_size == BlockSize::OneK => Ok(self.begin(1024)),
+                STX => {
+                    self.output.cancel(self.config.cancel_count);
+                    Err(Error::InvalidInput("1024-byte packets not accepted"))
+                }
+                CAN if cancelling => Err(Error::Cancelled),
+                CAN => {
+                    self.cancelling = true;
+                    Ok(None)
+                }
+                _ if self.resyncing => Ok(None),
+                _ => {
+                    self.resyncing = true;
+                    Ok(Some(Event::Resync))
+                }
+            },
+            RxState::Number => {
+                self.state = RxState::Complement(byte);
+                Ok(None)
+            }
+            RxState::Complement(CAN) if byte == CAN => Err(Error::Cancelled),
+            RxState::Complement(number) => {
+                if byte != 255 - number {
+                    return self.reject(self.size + self.checksum.size());
+                }
+
+                self.duplicate = Some(number) == self.previous && number != self.packet;
+                if number != self.packet && !self.duplicate {
+                    self.state = RxState::Header;
+                    self.output.cancel(self.config.cancel_count);
+                    return Err(Error::PacketNumber {
+                        expected: self.packet,
+                        received: number,
+                    });
+                }
+
+                self.state = RxState::Data;
+                Ok(None)
+            }
+            RxState::Data => {
+                self.data[self.len] = byte;
+                self.len += 1;
+                if self.len == self.size {
+                    self.len = 0;
+                    self.state = RxState::Trailer;
+                }
+
+                Ok(None)
+            }
+            RxState::Trailer => {
+                self.trailer[self.len] = byte;
+                self.len += 1;
+                if self.len < self.checksum.size() {
+                    return Ok(None);
+                }
+
+                self.state = RxState::Header;
+                self.verify()
+            }
+            RxState::Skip(left) => {
+                self.skip(left - 1);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Queues the handshake byte for the current attempt.
+    fn request(&mut self) {
+        if self.handshakes >= CRC_HANDSHAKE_ATTEMPTS {
+            self.checksum = Checksum::Standard;
+        }
+
+        self.output.push(match self.checksum {
+            Checksum::Crc16 => CRC,
+            Checksum::Standard => NAK,
+        });
+    }
+
+    /// Starts receiving a packet with a payload of `size` bytes.
+    fn begin(&mut self, size: usize) -> Option<Event> {
+        self.resyncing = false;
+        self.size = size;
+        self.len = 0;
+        self.state = RxState::Number;
+        if self.started {
+            return None;
+        }
+
+        self.started = true;
+        Some(Event::Started)
+    }
+
+    /// Checks the trailer of the packet just received and acknowledges or
+    /// rejects the packet.
+    fn verify(&mut self) -> Result<Option<Event>> {
+        let size = self.checksum.size();
+        let mut expected = [0u8; 2];
+        self.checksum
+            .compute(&self.data[..self.size], &mut expected);
+        if expected[..size] != self.trailer[..size] {
+            return self.reject(0);
+        }
+
+        self.output.push(ACK);
+        self.errors = 0;
+        if self.duplicate {
+            return Ok(self.previous.map(Event::Duplicate));
+        }
+
+        let number = self.packet;
+        self.previous = Some(number);
+        self.packet = self.packet.wrapping_add(1);
+        Ok(Some(Event::Packet(number)))
+    }
+
+    /// Rejects the packet being received as corrupted, `left` bytes before
+    /// its end: skips the rest of the packet, or purges the line if
+    /// configured to, and requests the packet again with a `NAK`.
+    fn reject(&mut self, left: usize) -> Result<Option<Event>> {
+        self.errors += 1;
+        if self.errors >= self.config.retries {
+            self.state = RxState::Header;
+            self.output.cancel(self.config.cancel_count);
+            return Err(Error::RetriesExhausted);
+        }
+
+        if self.config.purge {
+            self.state = RxState::Purge;
+        } else {
+            self.skip(left);
+        }
+
+        Err(Error::Checksum)
+    }
+
+    /// Skips the `left` bytes left of a rejected packet, then sends the
+    /// `NAK`.
+    fn skip(&mut self, left: usize) {
+        if left > 0 {
+            self.state = RxState::Skip(left);
+        } else {
+            self.state = RxState::Header;
+            self.output.push(NAK);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum TxState {
+    /// Waiting for the receiver's `NAK`, `'C'` or `'G'`.
+    Handshake,
+    /// Ready to send the next packet.
+    Ready,
+    /// Waiting for the packet just sent to be acknowledged.
+    Ack,
+    /// Waiting for the receiver to reject the first `EOT`.
+    EotNak,
+    /// Waiting for the receiver to acknowledge the second `EOT`.
+    EotAck,
+    /// The transmission ended.
+    Done,
+}
+
+/// The sending side of an XMODEM session as a state machine without I/O.
+///
+/// Bytes received from the receiver are passed to [`Sender::feed()`] one at a
+/// time, and read timeouts are reported with [`Sender::timeout()`]. After
+/// [`Event::Started`], packets are queued with [`Sender::send_packet()`], one
+/// at a time. After each call, the bytes returned by
+/// [`Sender::take_output()`] must be written to the receiver. The sender never
+/// allocates and works without the `std` feature.
+///
+/// The receiver picks the variant of the protocol with its handshake byte:
+/// `NAK` for the 8-bit checksum, `'C'` for CRC-16 and `'G'` for streamed
+/// CRC-16 packets, as in YMODEM-g. Streamed packets aren't acknowledged: each
+/// is reported by [`Sender::take_event()`] as soon as it is queued, and the
+/// receiver cancels the session if one is corrupted: the bytes it sends while
+/// packets are streamed should be passed to [`Sender::feed()`] as they
+/// arrive.
+///
+/// # Example
+///
+/// ```rust
+/// use xmodem::{Event, Sender, XmodemBuilder};
+///
+/// let mut sender = XmodemBuilder::new().sender();
+/// assert_eq!(sender.feed(b'C').unwrap(), Some(Event::Started));
+///
+/// sender.send_packet(&[0; 128]).unwrap();
+/// assert_eq!(sender.take_output().len(), 3 + 128 + 2);
+/// assert_eq!(sender.feed(0x06).unwrap(), Some(Event::Packet(1)));
+/// ```
+pub struct Sender {
+    config: Config,
+    state: TxState,
+    checksum: Checksum,
+    /// Number of the next packet.
+    packet: u8,
+    /// Number of read timeouts while waiting for the handshake, or for the
+    /// response to the packet just sent.
+    timeouts: usize,
+    /// Number of consecutive times a packet was sent again.
+    errors: usize,
+    /// Whether packets are streamed without acknowledgements.
+    streaming: bool,
+    /// Whether bytes are being skipped while waiting for the handshake.
+    resyncing: bool,
+    /// Whether something other than `ACK` or `NAK` was received in response
+    /// to the packet just sent.
+    garbled: bool,
+    /// Whether the last byte was a `CAN` that may start a cancellation.
+    cancelling: bool,
+    /// The event of a streamed packet, not yet taken.
+    event: Option<Event>,
+    output: Output,
+}
+
+impl Sender {
+    pub(crate) fn new(config: Config) -> Sender {
+        Sender {
+            config,
+            state: TxState::Handshake,
+            checksum: Checksum::Crc16,
+            packet: 1,
+            timeouts: 0,
+            errors: 0,
+            streaming: false,
+            resyncing: false,
+            garbled: false,
+            cancelling: false,
+            event: None,
+            output: Output::new(),
+        }
+    }
+
+    /// Returns the packet checksum mode requested by the receiver. Only
+    /// meaningful once the session has started.
+    pub fn checksum(&self) -> Checksum {
+        self.checksum
+    }
+
+    /// Returns `true` once the receiver's handshake has been received.
+    pub fn is_started(&self) -> bool {
+        self.state != TxState::Handshake
+    }
+
+    /// Returns `true` if the receiver requested streamed packets with `'G'`.
+    /// Only meaningful once the session has started.
+    pub fn is_streaming(&self) -> bool {
+        self.streaming
+    }
+
+    /// Returns the bytes that must be written to the receiver, and forgets
+    /// them.
+    pub fn take_output(&mut self) -> &[u8] {
+        self.output.take()
+    }
+
+    /// Returns the event that happened without input from the receiver, and
+    /// forgets it: `Event::Packet` for a packet streamed by
+    /// [`Sender::send_packet()`]. Must be checked after queuing a packet.
+    pub fn take_event(&mut self) -> Option<Event> {
+        self.event.take()
+    }
+
+    /// Prepares for a new exchange within the same session, as YMODEM does for
+    /// every file: the next packet is numbered `packet` and the receiver's
+    /// handshake is waited for again.
+    pub fn restart(&mut self, packet: u8) {
+        self.packet = packet;
+        self.timeouts = 0;
+        self.resyncing = false;
+        self.state = TxState::Handshake;
+    }
+
+    /// Queues the packet `buf` for transmission. `buf` must hold exactly 128
+    /// or 1024 bytes; a 128-byte packet is sent with `SOH`, a 1024-byte packet
+    /// with `STX`. If `buf` is empty, the end of the transmission is sent
+    /// instead.
+    ///
+    /// When streaming, the packet counts as acknowledged once queued: the
+    /// next one can be queued after [`Sender::take_event()`] reports it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if the session hasn't started, if the
+    /// previous packet hasn't been acknowledged or if `buf.len()` isn't 0, 128
+    /// or 1024.
+    pub fn send_packet(&mut self, buf: &[u8]) -> Result<()> {
+        if self.state != TxState::Ready {
+            return Err(Error::InvalidInput("sender is not ready for a packet"));
+        }
+
+        self.timeouts = 0;
+        self.garbled = false;
+        let header = match buf.len() {
+            0 => {
+                self.output.push(EOT);
+                self.state = TxState::EotNak;
+                return Ok(());
+            }
+            128 => SOH,
+            1024 => STX,
+            _ => return Err(Error::InvalidInput("invalid packet length")),
+        };
+
+        let mut trailer = [0u8; 2];
+        self.checksum.compute(buf, &mut trailer);
+        self.output
+            .extend(&[header, self.packet, 255 - self.packet]);
+        self.output.extend(buf);
+        self.output.extend(&trailer[..self.checksum.size()]);
+        if self.streaming {
+            self.event = Some(Event::Packet(self.packet));
+            self.packet = self.packet.wrapping_add(1);
+        } else {
+            self.state = TxState::Ack;
+        }
+
+        Ok(())
+    }
+
+    /// Reports that no byte arrived in time.
+    ///
+    /// Once a packet, or the end of the transmission, has been sent and the
+    /// receiver hasn't responded for `RESEND_TIMEOUTS` read timeouts, it must
+    /// be sent again: `Event::Timeout` is returned, or `Event::Garbled` if
+    /// the response was garbled. Either counts as a rejection of the packet.
+    ///
+    /// # Errors
+    ///
+    /// While waiting for the handshake, returns `Error::Timeout` once the
+    /// configured number of handshake retries is exhausted. Afterwards,
+    /// returns `Error::Timeout` after the configured number of consecutive
+    /// rejections, and cancels the session.
+    pub fn timeout(&mut self) -> Result<Option<Event>> {
+        self.timeouts += 1;
+        match self.state {
+            TxState::Handshake if self.timeouts >= self.config.handshake_retries => {
+                Err(Error::Timeout)
+            }
+            TxState::Ack | TxState::EotNak | TxState::EotAck
+                if self.timeouts >= RESEND_TIMEOUTS =>
+            {
+                match self.resend() {
+                    Err(Error::RetriesExhausted) => Err(Error::Timeout),
+                    Err(Error::Checksum) => Ok(Some(Event::Timeout(self.packet))),
+                    result => result,
+                }
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Processes the byte `byte` received from the receiver.
+    ///
+    /// Returns `Event::Started` when the receiver's handshake arrives and sets
+    /// the checksum mode to the one it requested: `NAK` for `Standard`, or
+    /// `'C'` or `'G'` for `Crc16`. Bytes received before the handshake, such
+    /// as line noise or a boot log, are skipped; `Event::Resync` is returned
+    /// for the first one skipped. Returns `Event::Packet` when a packet is
+    /// acknowledged and `Event::Done` when the end of the transmission is.
+    /// When streaming, or once it has been sent again, the first `EOT` may be
+    /// acknowledged right away.
+    ///
+    /// Other responses to a packet are skipped as noise: the packet is sent
+    /// again when the receiver rejects it or stops responding. Bytes received
+    /// while the sender is ready for a packet are discarded, unless they
+    /// cancel the session: the bytes already received should be passed before
+    /// a packet is queued, so that a response duplicated by the line, or
+    /// arriving late, isn't taken for the response to that packet.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Checksum` is returned when the receiver rejects a packet; it
+    /// must be queued again with [`Sender::send_packet()`]. If the rejection
+    /// followed a garbled response, `Event::Garbled` is returned instead.
+    /// After the configured number of consecutive rejections, the session is
+    /// cancelled with `Error::RetriesExhausted` instead.
+    ///
+    /// `Error::Cancelled` is returned, and the session ends, when the
+    /// receiver cancels the session with two consecutive `CAN` bytes.
+    pub fn feed(&mut self, byte: u8) -> Result<Option<Event>> {
+        let cancelling = self.cancelling;
+        self.cancelling = false;
+        if byte == CAN && self.state != TxState::Done {
+            if cancelling {
+                return Err(Error::Cancelled);
+            }
+
+            self.cancelling = true;
+            self.garbled = self.state != TxState::Handshake && self.state != TxState::Ready;
+            return Ok(None);
+        }
+
+        match (self.state, byte) {
+            (TxState::Ready, _) | (TxState::Done, _) => Ok(None),
+            (TxState::Handshake, NAK)
+            | (TxState::Handshake, CRC)
+            | (TxState::Handshake, STREAMING) => {
+                self.checksum = match byte {
+                    NAK => Checksum::Standard,
+                    _ => Checksum::Crc16,
+                };
+
+                self.streaming = byte == STREAMING;
+                self.resyncing = false;
+                self.state = TxState::Ready;
+                Ok(Some(Event::Started))
+            }
+            (TxState::Handshake, _) if self.resyncing => Ok(None),
+            (TxState::Handshake, _) => {
+                self.resyncing = true;
+                Ok(Some(Event::Resync))
+            }
+            (TxState::Ack, ACK) => {
+                let number = self.packet;
+                self.errors = 0;
+                self.packet = self.packet.wrapping_add(1);
+                self.state = TxState::Ready;
+                Ok(Some(Event::Packet(number)))
+            }
+            (TxState::Ack, NAK) | (TxState::EotAck, NAK) => self.resend(),
+            (TxState::EotNak, NAK) => {
+                self.output.push(EOT);
+                self.timeouts = 0;
+                self.state = TxState::EotAck;
+                Ok(None)
+            }
+            // A receiver that rejected the first `EOT` acknowledges it when
+            // it is sent again.
+            (TxState::EotNak, ACK) if self.streaming || self.errors > 0 => {
+                self.state = TxState::Done;
+                Ok(Some(Event::Done))
+            }
+            (TxState::EotAck, ACK) => {
+                self.state = TxState::Done;
+                Ok(Some(Event::Done))
+            }
+            (TxState::Ack, _) | (TxState::EotNak, _) | (TxState::EotAck, _) => {
+                self.garbled = true;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Prepares to send the packet just sent, or the end of the transmission,
+    /// again: returns `Error::Checksum`, or `Event::Garbled` if the response
+    /// to it was garbled. Cancels the session with `Error::RetriesExhausted`
+    /// after the configured number of consecutive rejections.
+    fn resend(&mut self) -> Result<Option<Event>> {
+        self.state = TxState::Ready;
+        self.errors += 1;
+        if self.errors >= self.config.retries {
+            self.output.cancel(self.config.cancel_count);
+            return Err(Error::RetriesExhausted);
+        }
+
+        if self.garbled {
+            return Ok(Some(Event::Garbled(self.packet)));
+        }
+
+        Err(Error::Checksum)
+    }
+}
@@ -0,0 +1,310 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The faults a [`NoisyPipe`] injects into the bytes written to it.
+///
+/// Rates are probabilities per byte written, between `0.0` and `1.0`. By
+/// default, no faults are injected and the line is perfect.
+///
+/// # Example
+///
+/// ```rust
+/// use std::time::Duration;
+/// use xmodem::Faults;
+///
+/// let faults = Faults::new()
+///     .bit_flips(0.001)
+///     .garbage(0.001)
+///     .delays(0.01, Duration::from_millis(5));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Faults {
+    bit_flips: f64,
+    drops: f64,
+    garbage: f64,
+    duplicates: f64,
+    delays: f64,
+    delay: Duration,
+    stall_after: Option<usize>,
+}
+
+impl Faults {
+    /// Returns a configuration that injects no faults.
+    pub fn new() -> Faults {
+        Faults::default()
+    }
+
+    /// Sets the rate at which a byte has one of its bits flipped.
+    pub fn bit_flips(mut self, rate: f64) -> Self {
+        self.bit_flips = rate;
+        self
+    }
+
+    /// Sets the rate at which a byte is lost.
+    pub fn drops(mut self, rate: f64) -> Self {
+        self.drops = rate;
+        self
+    }
+
+    /// Sets the rate at which a random byte is inserted before a byte.
+    pub fn garbage(mut self, rate: f64) -> Self {
+        self.garbage = rate;
+        self
+    }
+
+    /// Sets the rate at which a byte is delivered twice.
+    pub fn duplicates(mut self, rate: f64) -> Self {
+        self.duplicates = rate;
+        self
+    }
+
+    /// Sets the rate at which a byte, and the bytes written after it, are
+    /// held back for `delay` before being delivered.
+    pub fn delays(mut self, rate: f64, delay: Duration) -> Self {
+        self.delays = rate;
+        self.delay = delay;
+        self
+    }
+
+    /// Makes the peer stall after writing `count` bytes: every byte written
+    /// afterwards is lost.
+    pub fn stall_after(mut self, count: usize) -> Self {
+        self.stall_after = Some(count);
+        self
+    }
+}
+
+/// A small, seeded pseudo-random number generator (SplitMix64), so a failing
+/// run can be reproduced from its seed.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Returns `true` with probability `rate`.
+    fn chance(&mut self, rate: f64) -> bool {
+        rate > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+/// One end of a simulated serial line that injects [`Faults`] into the bytes
+/// written to it, created by [`noisy_pipe()`].
+///
+/// Reads wait for a byte at most for the read timeout, 20 milliseconds by
+/// default, and fail with `io::ErrorKind::TimedOut` if none arrives, like a
+/// serial port with a read timeout. The bytes of a write, faults included,
+/// reach the other end together. Bytes written after the other end was
+/// dropped are lost.
+#[derive(Debug)]
+pub struct NoisyPipe {
+    tx: mpsc::Sender<Vec<(Instant, u8)>>,
+    rx: mpsc::Receiver<Vec<(Instant, u8)>>,
+    /// The bytes received and not read yet, with when they are due.
+    pending: VecDeque<(Instant, u8)>,
+    /// The bytes of the write in progress.
+    written_bytes: Vec<(Instant, u8)>,
+    faults: Faults,
+    rng: Rng,
+    read_timeout: Duration,
+    /// When the last byte written is delivered.
+    due: Instant,
+    written: usize,
+    injected: usize,
+}
+
+/// Returns the two ends of a simulated serial line. The bytes written to
+/// either end are subject to `faults`, drawn from a generator seeded with
+/// `seed`: the same seed and the same writes inject the same faults.
+///
+/// # Example
+///
+/// ```rust
+/// use std::thread;
+/// use xmodem::{noisy_pipe, Faults, Xmodem};
+///
+/// let (tx, rx) = noisy_pipe(7, Faults::new().bit_flips(0.0001));
+/// let sender = thread::spawn(move || Xmodem::transmit(&[0u8; 512][..], rx));
+///
+/// let mut output = vec![];
+/// if Xmodem::receive(tx, &mut output).is_ok() {
+///     assert_eq!(output, &[0u8; 512][..]);
+/// }
+/// # let _ = sender.join();
+/// ```
+pub fn noisy_pipe(seed: u64, faults: Faults) -> (NoisyPipe, NoisyPipe) {
+    let ((tx1, rx1), (tx2, rx2)) = (mpsc::channel(), mpsc::channel());
+    let mut rng = Rng(seed);
+    let (seed1, seed2) = (rng.next(), rng.next());
+    (
+        NoisyPipe::new(tx1, rx2, faults, seed1),
+        NoisyPipe::new(tx2, rx1, faults, seed2),
+    )
+}
+
+impl NoisyPipe {
+    fn new(
+        tx: mpsc::Sender<Vec<(Instant, u8)>>,
+        rx: mpsc::Receiver<Vec<(Instant, u8)>>,
+        faults: Faults,
+        seed: u64,
+    ) -> NoisyPipe {
+        NoisyPipe {
+            tx,
+            rx,
+            pending: VecDeque::new(),
+            written_bytes: vec![],
+            faults,
+            rng: Rng(seed),
+            read_timeout: Duration::from_millis(20),
+            due: Instant::now(),
+            written: 0,
+            injected: 0,
+        }
+    }
+
+    /// Sets how long a read waits for a byte.
+    pub fn set_read_timeout(&mut self, timeout: Duration) {
+        self.read_timeout = timeout;
+    }
+
+    /// Returns the number of faults injected into the bytes written to this
+    /// end so far.
+    pub fn injected(&self) -> usize {
+        self.injected
+    }
+
+    /// Reads the bytes that are already due into `buf`, without waiting for
+    /// more, and returns their number. Suitable for
+    /// [`Xmodem::set_try_read()`](::Xmodem::set_try_read).
+    pub fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.get(Instant::now()) {
+                Ok(Some(byte)) => buf[n] = byte,
+                _ => break,
+            }
+
+            n += 1;
+        }
+
+        Ok(n)
+    }
+
+    /// Delivers `byte` to the other end with the rest of the write, possibly
+    /// delayed.
+    fn deliver(&mut self, byte: u8) {
+        let now = Instant::now();
+        if self.rng.chance(self.faults.delays) {
+            self.injected += 1;
+            self.due = cmp::max(self.due, now + self.faults.delay);
+        }
+
+        self.due = cmp::max(self.due, now);
+        self.written_bytes.push((self.due, byte));
+    }
+
+    /// Writes `byte` to the line, injecting faults.
+    fn put(&mut self, byte: u8) {
+        self.written += 1;
+        if self.faults.stall_after.is_some_and(|n| self.written > n) {
+            self.injected += 1;
+            return;
+        }
+
+        let faults = self.faults;
+        if self.rng.chance(faults.drops) {
+            self.injected += 1;
+            return;
+        }
+
+        if self.rng.chance(faults.garbage) {
+            self.injected += 1;
+            let garbage = self.rng.next() as u8;
+            self.deliver(garbage);
+        }
+
+        let mut byte = byte;
+        if self.rng.chance(faults.bit_flips) {
+            self.injected += 1;
+            byte ^= 1 << (self.rng.next() % 8);
+        }
+
+        self.deliver(byte);
+        if self.rng.chance(faults.duplicates) {
+            self.injected += 1;
+            self.deliver(byte);
+        }
+    }
+
+    /// Returns the next byte once it is due, waiting until `deadline` at most.
+    fn get(&mut self, deadline: Instant) -> io::Result<Option<u8>> {
+        if self.pending.is_empty() {
+            let wait = deadline.saturating_duration_since(Instant::now());
+            match self.rx.recv_timeout(wait) {
+                Ok(received) => self.pending.extend(received),
+                Err(RecvTimeoutError::Timeout) => return Ok(None),
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+
+        let (due, byte) = match self.pending.front() {
+            Some(&pending) => pending,
+            None => return Ok(None),
+        };
+
+        if due > deadline {
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+            return Ok(None);
+        }
+
+        self.pending.pop_front();
+        thread::sleep(due.saturating_duration_since(Instant::now()));
+        Ok(Some(byte))
+    }
+}
+
+impl io::Read for NoisyPipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        match self.get(Instant::now() + self.read_timeout)? {
+            Some(byte) => buf[0] = byte,
+            None => return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+        }
+
+        // Return the bytes that are already due without waiting for more.
+        Ok(1 + self.read_available(&mut buf[1..])?)
+    }
+}
+
+impl io::Write for NoisyPipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        buf.iter().for_each(|&byte| self.put(byte));
+        let written = mem::take(&mut self.written_bytes);
+        if !written.is_empty() {
+            let _ = self.tx.send(written);
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
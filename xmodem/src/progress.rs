@@ -1,3 +1,7 @@
+use core::time::Duration;
+
+use {BlockSize, Checksum};
+
 /// Enum representing how much progress has been made transmitting/receiving.
 ///
 /// A value of this type is passed in to the progress callback supplied to
@@ -10,12 +14,77 @@ pub enum Progress {
     Waiting,
     /// Download/upload has started.
     Started,
+    /// The sender picked the variant of the protocol matching the receiver's
+    /// handshake.
+    Negotiated {
+        /// The packet checksum mode the receiver requested.
+        checksum: Checksum,
+        /// The size of the packets sent, except at the end of the data.
+        block_size: BlockSize,
+        /// Whether packets are streamed without acknowledgements.
+        streaming: bool,
+    },
     /// Packet `.0` was transmitted/received.
     Packet(u8),
+    /// Packet `.0` was received again and discarded because the sender missed
+    /// its acknowledgement.
+    Duplicate(u8),
+    /// Packet `.0` was received corrupted and requested again.
+    Corrupted(u8),
+    /// Line noise was skipped while waiting for a packet.
+    Resync,
+}
+
+/// The state of a transfer at the time of a [`Progress`] event.
+///
+/// A value of this type is passed to progress closures set with
+/// [`XmodemBuilder::progress()`](::XmodemBuilder::progress) or
+/// [`Xmodem::set_progress()`]. Unlike `Progress::Packet`, whose packet number
+/// wraps around after 255, the byte count keeps growing for the whole
+/// transfer.
+#[derive(Debug, Copy, Clone)]
+pub struct Status {
+    /// The event being reported.
+    pub progress: Progress,
+    /// Number of payload bytes transferred so far, including padding.
+    pub bytes: u64,
+    /// Number of bytes expected in total, when known.
+    pub total: Option<u64>,
+    /// Number of packets sent or received again so far.
+    pub retries: usize,
+    /// Time since the session started.
+    pub elapsed: Duration,
+}
+
+impl Status {
+    /// Returns the fraction of the expected bytes transferred, between `0.0`
+    /// and `1.0`, if the total is known.
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| match total {
+            0 => 1.0,
+            total => (self.bytes as f64 / total as f64).min(1.0),
+        })
+    }
+
+    /// Returns the average throughput so far in bytes per second, if any time
+    /// has passed.
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            Some(self.bytes as f64 / secs)
+        } else {
+            None
+        }
+    }
 }
 
 /// Type for progress callbacks.
 pub type ProgressFn = fn(Progress);
 
+/// Type for progress closures.
+#[cfg(feature = "std")]
+pub(crate) type ProgressCallback = Box<dyn FnMut(&Status) + Send>;
+
 /// Noop progress callback.
+#[cfg(feature = "std")]
 pub fn noop(_: Progress) {  }
@@ -0,0 +1,55 @@
+use std::time::Duration;
+
+/// Statistics of an XMODEM session, returned by
+/// [`Xmodem::transmit_with_stats()`](::Xmodem::transmit_with_stats) and
+/// [`Xmodem::receive_with_stats()`](::Xmodem::receive_with_stats), or taken
+/// with [`Xmodem::stats()`](::Xmodem::stats) at any point, including after a
+/// session failed.
+///
+/// Retransmissions are counted by cause.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct TransferStats {
+    /// Number of payload bytes transferred, excluding padding.
+    pub bytes: u64,
+    /// Number of packets sent and acknowledged, or received and written out.
+    pub packets: usize,
+    /// Number of packets rejected with a `NAK` and sent again: the `NAK`s a
+    /// sender received or a receiver sent for corrupted packets.
+    pub naks: usize,
+    /// Number of retransmissions after a read timeout: the handshakes and
+    /// `NAK`s a receiver sent because nothing arrived in time, or the packets
+    /// a sender sent again because no response arrived.
+    pub timeouts: usize,
+    /// Number of packets a sender sent again because the response to them was
+    /// garbled.
+    pub bad_acks: usize,
+    /// Number of packets a receiver got twice, because the sender didn't get
+    /// their `ACK`, and acknowledged again and discarded.
+    pub duplicates: usize,
+    /// Number of times the session was cancelled with `CAN`, by either side.
+    pub cancels: usize,
+    /// Time spent waiting for the session to start: for the receiver's
+    /// handshake when sending, or for the first packet when receiving.
+    pub handshake: Duration,
+    /// Time since the session started.
+    pub duration: Duration,
+}
+
+impl TransferStats {
+    /// Returns the total number of retransmissions, of any cause.
+    pub fn retransmissions(&self) -> usize {
+        self.naks + self.timeouts + self.bad_acks + self.duplicates
+    }
+
+    /// Returns the effective throughput of the session in payload bytes per
+    /// second, handshake and retransmissions included, if any time has
+    /// passed.
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            Some(self.bytes as f64 / secs)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::cmp;
+use std::io;
+use std::mem;
+
+use {PaddingPolicy, Result, Xmodem};
+
+/// An `io::Write` adapter that sends everything written to it using the
+/// XMODEM protocol.
+///
+/// The first write waits for the receiver to start the transmission. Written
+/// bytes are then buffered into blocks of the configured
+/// [`BlockSize`](::BlockSize), or of 128 bytes if the receiver requests the
+/// 8-bit checksum, and sent as whole packets. The last, partial
+/// block and the end of the transmission are sent by
+/// [`XmodemWriter::finish()`], or when the writer is dropped, in which case
+/// errors are ignored.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::XmodemWriter;
+///
+/// let mut writer = XmodemWriter::new(port);
+/// io::copy(&mut File::open("kernel.img")?, &mut writer)?;
+/// writer.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct XmodemWriter<T: io::Read + io::Write> {
+    /// `None` once the writer has been finished.
+    xmodem: Option<Xmodem<T>>,
+    block: [u8; 1024],
+    len: usize,
+}
+
+impl<T: io::Read + io::Write> XmodemWriter<T> {
+    /// Returns a writer sending to `inner` with the default configuration.
+    pub fn new(inner: T) -> XmodemWriter<T> {
+        XmodemWriter::from_xmodem(Xmodem::new(inner))
+    }
+
+    /// Returns a writer sending with `xmodem`, which must not have sent any
+    /// packets yet.
+    pub fn from_xmodem(xmodem: Xmodem<T>) -> XmodemWriter<T> {
+        XmodemWriter {
+            xmodem: Some(xmodem),
+            block: [0; 1024],
+            len: 0,
+        }
+    }
+
+    /// Sends the bytes still buffered, padded to whole packets, and ends the
+    /// transmission. Returns the inner reader/writer.
+    ///
+    /// # Errors
+    ///
+    /// Returns any error returned by [`Xmodem::write_packet()`].
+    pub fn finish(mut self) -> Result<T> {
+        self.end()?;
+        Ok(self.xmodem.take().expect("unfinished writer").into_inner())
+    }
+
+    /// Sends the buffered bytes, if any, and the end of the transmission.
+    fn end(&mut self) -> Result<()> {
+        let xmodem = self.xmodem.as_mut().expect("unfinished writer");
+        xmodem.wait_for_receiver()?;
+        if self.len > 0 {
+            xmodem.send_block(&mut self.block, self.len)?;
+            self.len = 0;
+        }
+
+        xmodem.send_packet(&[])
+    }
+}
+
+impl<T: io::Read + io::Write> io::Write for XmodemWriter<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let xmodem = self.xmodem.as_mut().expect("unfinished writer");
+        xmodem.wait_for_receiver()?;
+        let block_size = xmodem.negotiated_block_size().size();
+        let n = cmp::min(buf.len(), block_size - self.len);
+        self.block[self.len..self.len + n].copy_from_slice(&buf[..n]);
+        self.len += n;
+        if self.len == block_size {
+            xmodem.send_block(&mut self.block, block_size)?;
+            self.len = 0;
+        }
+
+        Ok(n)
+    }
+
+    /// Flushes the inner stream. Bytes that don't fill a block yet stay
+    /// buffered, as sending them would add padding in the middle of the data.
+    fn flush(&mut self) -> io::Result<()> {
+        let xmodem = self.xmodem.as_mut().expect("unfinished writer");
+        Ok(xmodem.flush()?)
+    }
+}
+
+impl<T: io::Read + io::Write> Drop for XmodemWriter<T> {
+    fn drop(&mut self) {
+        if self.xmodem.is_some() {
+            let _ = self.end();
+        }
+    }
+}
+
+/// An `io::Read` adapter that receives data using the XMODEM protocol.
+///
+/// Reads yield payload bytes across packet boundaries and return `0` once the
+/// sender has ended the transmission. The configured
+/// [`PaddingPolicy`] is applied to the end of the data, so packets are
+/// yielded one packet late, as with [`Xmodem::recv()`].
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::{PaddingPolicy, Xmodem, XmodemReader};
+///
+/// let xmodem = Xmodem::builder()
+///     .padding_policy(PaddingPolicy::StripSub)
+///     .build(port);
+/// let mut reader = XmodemReader::from_xmodem(xmodem);
+/// io::copy(&mut reader, &mut File::create("kernel.img")?)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct XmodemReader<T> {
+    xmodem: Xmodem<T>,
+    /// The packet being read from.
+    buf: [u8; 1024],
+    pos: usize,
+    len: usize,
+    /// The packet received last, which is read once the next one arrives.
+    next: [u8; 1024],
+    next_len: usize,
+    /// Number of bytes yielded, to apply `PaddingPolicy::Truncate`.
+    yielded: u64,
+    done: bool,
+}
+
+impl<T: io::Read + io::Write> XmodemReader<T> {
+    /// Returns a reader receiving from `inner` with the default
+    /// configuration.
+    pub fn new(inner: T) -> XmodemReader<T> {
+        XmodemReader::from_xmodem(Xmodem::new(inner))
+    }
+
+    /// Returns a reader receiving with `xmodem`, which must not have received
+    /// any packets yet.
+    pub fn from_xmodem(xmodem: Xmodem<T>) -> XmodemReader<T> {
+        XmodemReader {
+            xmodem,
+            buf: [0; 1024],
+            pos: 0,
+            len: 0,
+            next: [0; 1024],
+            next_len: 0,
+            yielded: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the inner reader/writer. Any data not read yet is lost.
+    pub fn into_inner(self) -> T {
+        self.xmodem.into_inner()
+    }
+
+    /// Receives packets until one can be read from or the transmission ends.
+    fn fill(&mut self) -> Result<()> {
+        while self.pos == self.len && !self.done {
+            let n = self.xmodem.recv_packet(&mut self.buf)?;
+            mem::swap(&mut self.buf, &mut self.next);
+            self.pos = 0;
+            self.len = match n {
+                0 => self
+                    .xmodem
+                    .padding_policy()
+                    .strip(&self.buf[..self.next_len]),
+                _ => self.next_len,
+            };
+
+            self.next_len = n;
+            self.done = n == 0;
+        }
+
+        Ok(())
+    }
+}
+
+impl<T: io::Read + io::Write> io::Read for XmodemReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.fill()?;
+        let limit = match self.xmodem.padding_policy() {
+            PaddingPolicy::Truncate(len) => len - cmp::min(len, self.yielded),
+            _ => u64::MAX,
+        };
+
+        if limit == 0 {
+            // Receive the rest of the transmission so the sender can finish.
+            while !self.done {
+                self.pos = self.len;
+                self.fill()?;
+            }
+
+            return Ok(0);
+        }
+
+        let available = cmp::min((self.len - self.pos) as u64, limit) as usize;
+        let n = cmp::min(buf.len(), available);
+        buf[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+        self.pos += n;
+        self.yielded += n as u64;
+        Ok(n)
+    }
+}
@@ -1,19 +1,19 @@
 use super::*;
 use std::io::Cursor;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{self, channel};
 
-struct Pipe(Sender<u8>, Receiver<u8>, Vec<u8>);
+pub(crate) struct Pipe(mpsc::Sender<u8>, mpsc::Receiver<u8>, pub(crate) Vec<u8>);
 
-fn pipe() -> (Pipe, Pipe) {
+pub(crate) fn pipe() -> (Pipe, Pipe) {
     let ((tx1, rx1), (tx2, rx2)) = (channel(), channel());
     (Pipe(tx1, rx2, vec![]), Pipe(tx2, rx1, vec![]))
 }
 
 impl io::Read for Pipe {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        for i in 0..buf.len() {
+        for (i, slot) in buf.iter_mut().enumerate() {
             match self.1.recv() {
-                Ok(byte) => buf[i] = byte,
+                Ok(byte) => *slot = byte,
                 Err(_) => return Ok(i),
             }
         }
@@ -62,73 +62,103 @@ fn test_loop() {
     assert_eq!(&input[..], &output[..]);
 }
 
-#[test]
-fn read_byte() {
-    let byte = Xmodem::new(Cursor::new(vec![CAN]))
-        .read_byte(false)
-        .expect("read a byte");
-
-    assert_eq!(byte, CAN);
+/// Returns packet number `number` carrying `data`, with a CRC-16 trailer.
+fn crc_packet(number: u8, data: &[u8]) -> Vec<u8> {
+    let crc = checksum::crc16(data);
+    let mut packet = vec![SOH, number, 255 - number];
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(&[(crc >> 8) as u8, crc as u8]);
+    packet
+}
 
-    let e = Xmodem::new(Cursor::new(vec![CAN]))
-        .read_byte(true)
-        .expect_err("abort on CAN");
+/// Feeds `bytes` to `receiver`, returning the last result.
+fn feed_all(receiver: &mut Receiver, bytes: &[u8]) -> Result<Option<Event>> {
+    let mut result = Ok(None);
+    for &byte in bytes {
+        result = receiver.feed(byte);
+    }
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    result
 }
 
 #[test]
-fn test_expect_byte() {
-    let mut xmodem = Xmodem::new(Cursor::new(vec![1, 1]));
-    assert_eq!(xmodem.expect_byte(1, "1").expect("expected"), 1);
-    let e = xmodem
-        .expect_byte(2, "1, please")
-        .expect_err("expect the unexpected");
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+fn test_receiver_treats_can_in_packet_as_data() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    assert_eq!(receiver.take_output(), &[CRC]);
+
+    let packet = crc_packet(1, &[CAN; 128]);
+    let event = feed_all(&mut receiver, &packet).expect("CAN is data");
+    assert_eq!(event, Some(Event::Packet(1)));
+    assert_eq!(receiver.packet(), &[CAN; 128][..]);
+    assert_eq!(receiver.take_output(), &[ACK]);
+
+    assert_eq!(receiver.feed(CAN).expect("a single CAN"), None);
+    let e = receiver.feed(CAN).expect_err("abort on CAN");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
 }
 
 #[test]
-fn test_expect_byte_or_cancel() {
-    let mut buffer = vec![2, 0];
-    let b = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(2, "it's a 2")
-        .expect("got a 2");
+fn test_sender_ignores_noise_and_resends_on_nak() {
+    let mut sender = XmodemBuilder::new().sender();
+    assert_eq!(sender.feed(NAK).expect("handshake"), Some(Event::Started));
+    sender.send_packet(&[1; 128]).expect("send packet");
+    assert_eq!(sender.take_output().len(), 3 + 128 + 1);
 
-    assert_eq!(b, 2);
+    assert_eq!(sender.feed(1).expect("noise"), None);
+    assert_eq!(sender.feed(NAK).expect("a NAK"), Some(Event::Garbled(1)));
+    sender.send_packet(&[1; 128]).expect("send packet again");
+    assert_eq!(sender.take_output().len(), 3 + 128 + 1);
+    assert_eq!(sender.feed(ACK).expect("an ACK"), Some(Event::Packet(1)));
 }
 
 #[test]
-fn test_expect_can() {
-    let mut xmodem = Xmodem::new(Cursor::new(vec![CAN]));
-    assert_eq!(xmodem.expect_byte(CAN, "hi").expect("CAN"), CAN);
+fn test_packet_number_can() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.restart(CAN);
+    receiver.start();
+    let event = feed_all(&mut receiver, &crc_packet(CAN, &[0; 128])).expect("packet CAN");
+    assert_eq!(event, Some(Event::Packet(CAN)));
 }
 
 #[test]
-fn test_unexpected_can() {
-    let e = Xmodem::new(Cursor::new(vec![CAN]))
-        .expect_byte(SOH, "want SOH")
-        .expect_err("have CAN");
+fn test_sender_cancelled_while_waiting_for_ack() {
+    let mut sender = XmodemBuilder::new().sender();
+    sender.feed(CRC).expect("handshake");
+    sender.send_packet(&[0; 128]).expect("send packet");
+    sender.take_output();
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert_eq!(sender.feed(CAN).expect("a single CAN"), None);
+    let e = sender.feed(CAN).expect_err("have CAN");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+    assert_eq!(sender.take_output(), &[]);
 }
 
 #[test]
-fn test_cancel_on_unexpected() {
-    let mut buffer = vec![CAN, 0];
-    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(SOH, "want SOH")
-        .expect_err("have CAN");
+fn test_receiver_cancel_during_eot_handshake() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    receiver.feed(EOT).expect("first EOT");
+    assert_eq!(receiver.take_output(), &[CRC, NAK]);
 
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
-    assert_eq!(buffer[1], CAN);
+    assert_eq!(receiver.feed(CAN).expect("a single CAN"), None);
+    let e = receiver.feed(CAN).expect_err("have CAN");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+    assert_eq!(receiver.take_output(), &[]);
 
-    let mut buffer = vec![0, 0];
-    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
-        .expect_byte_or_cancel(SOH, "want SOH")
-        .expect_err("have 0");
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    receiver.feed(EOT).expect("first EOT");
+    receiver.take_output();
 
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
-    assert_eq!(buffer[1], CAN);
+    assert_eq!(receiver.feed(0).expect("noise"), Some(Event::Resync));
+    assert_eq!(receiver.feed(CAN).expect("a single CAN"), None);
+    assert_eq!(receiver.take_output(), &[]);
+    let event = receiver.timeout().expect("second EOT lost");
+    assert_eq!(event, Some(Event::Timeout(1)));
+    assert_eq!(receiver.take_output(), &[NAK]);
+    assert_eq!(receiver.feed(EOT).expect("second EOT"), Some(Event::Done));
+    assert_eq!(receiver.take_output(), &[ACK]);
 }
 
 #[test]
@@ -172,7 +202,6 @@ fn test_raw_transmission() {
     let mut input = [0u8; 256];
     let mut output = [0u8; 256];
     (0..256usize)
-        .into_iter()
         .enumerate()
         .for_each(|(i, b)| input[i] = b as u8);
 
@@ -190,6 +219,51 @@ fn test_raw_transmission() {
     let rx_buf = tx_thread.join().expect("tx join okay");
     let tx_buf = rx_thread.join().expect("rx join okay");
 
+    // check packet 1
+    let crc = checksum::crc16(&input[..128]);
+    assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
+    assert_eq!(&rx_buf[3..(3 + 128)], &input[..128]);
+    assert_eq!(&rx_buf[131..133], &[(crc >> 8) as u8, crc as u8]);
+
+    // check packet 2
+    let crc = checksum::crc16(&input[128..]);
+    assert_eq!(&rx_buf[133..136], &[SOH, 2, 255 - 2]);
+    assert_eq!(&rx_buf[136..(136 + 128)], &input[128..]);
+    assert_eq!(&rx_buf[264..266], &[(crc >> 8) as u8, crc as u8]);
+
+    // check EOT
+    assert_eq!(&rx_buf[266..], &[EOT, EOT]);
+
+    // check receiver responses
+    assert_eq!(&tx_buf, &[CRC, ACK, ACK, NAK, ACK]);
+}
+
+#[test]
+fn test_raw_checksum_transmission() {
+    let mut input = [0u8; 256];
+    (0..256usize)
+        .enumerate()
+        .for_each(|(i, b)| input[i] = b as u8);
+
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::transmit(&input[..], &mut rx).expect("transmit okay");
+        rx.2
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = [0u8; 128];
+        {
+            let mut receiver = Xmodem::new(&mut tx);
+            receiver.set_checksum(Checksum::Standard);
+            while receiver.read_packet(&mut output).expect("read okay") != 0 {}
+        }
+        tx.2
+    });
+
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    let tx_buf = rx_thread.join().expect("rx join okay");
+
     // check packet 1
     assert_eq!(&rx_buf[0..3], &[SOH, 1, 255 - 1]);
     assert_eq!(&rx_buf[3..(3 + 128)], &input[..128]);
@@ -213,33 +287,227 @@ fn test_raw_transmission() {
     assert_eq!(&tx_buf, &[NAK, ACK, ACK, NAK, ACK]);
 }
 
+#[test]
+fn test_crc16() {
+    assert_eq!(checksum::crc16(b""), 0);
+    assert_eq!(checksum::crc16(b"123456789"), 0x31C3);
+}
+
+#[test]
+fn test_crc32() {
+    assert_eq!(!checksum::crc32_update(!0, b""), 0);
+    assert_eq!(!checksum::crc32_update(!0, b"123456789"), 0xCBF4_3926);
+}
+
+/// A stream whose first `timeouts` reads time out before it yields the bytes
+/// of `input`. Written bytes are collected in `output`.
+struct Stalled {
+    timeouts: usize,
+    input: Cursor<Vec<u8>>,
+    output: Vec<u8>,
+}
+
+impl io::Read for Stalled {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.timeouts > 0 {
+            self.timeouts -= 1;
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+        }
+
+        self.input.read(buf)
+    }
+}
+
+impl io::Write for Stalled {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_crc_fallback_to_checksum() {
+    let data = [7u8; 128];
+    let mut input = vec![SOH, 1, 255 - 1];
+    input.extend_from_slice(&data);
+    input.push(checksum::checksum(&data));
+
+    let mut xmodem = Xmodem::new(Stalled {
+        timeouts: CRC_HANDSHAKE_ATTEMPTS,
+        input: Cursor::new(input),
+        output: vec![],
+    });
+
+    let mut buffer = [0u8; 128];
+    assert_eq!(xmodem.read_packet(&mut buffer).expect("read packet"), 128);
+    assert_eq!(&buffer[..], &data[..]);
+    assert_eq!(xmodem.checksum(), Checksum::Standard);
+    assert_eq!(&xmodem.inner.output, &[CRC, CRC, CRC, NAK, ACK]);
+}
+
+#[test]
+fn test_crc_handshake_after_timeout() {
+    let data = [7u8; 128];
+    let crc = checksum::crc16(&data);
+    let mut input = vec![SOH, 1, 255 - 1];
+    input.extend_from_slice(&data);
+    input.extend_from_slice(&[(crc >> 8) as u8, crc as u8]);
+
+    let mut xmodem = Xmodem::new(Stalled {
+        timeouts: CRC_HANDSHAKE_ATTEMPTS - 1,
+        input: Cursor::new(input),
+        output: vec![],
+    });
+
+    let mut buffer = [0u8; 128];
+    assert_eq!(xmodem.read_packet(&mut buffer).expect("read packet"), 128);
+    assert_eq!(&buffer[..], &data[..]);
+    assert_eq!(xmodem.checksum(), Checksum::Crc16);
+    assert_eq!(&xmodem.inner.output, &[CRC, CRC, CRC, ACK]);
+}
+
+#[test]
+fn test_bad_crc() {
+    let data = [7u8; 128];
+    let crc = checksum::crc16(&data) ^ 1;
+    let mut input = vec![SOH, 1, 255 - 1];
+    input.extend_from_slice(&data);
+    input.extend_from_slice(&[(crc >> 8) as u8, crc as u8]);
+
+    let mut buffer = vec![0u8; input.len() + 2];
+    buffer[1..(input.len() + 1)].copy_from_slice(&input);
+
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet)
+        .expect_err("bad CRC");
+
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+    assert_eq!(buffer[0], CRC);
+    assert_eq!(buffer[buffer.len() - 1], NAK);
+}
+
+#[test]
+fn test_sender_detects_mode() {
+    let mut buffer = vec![NAK, 0, NAK, 0, ACK];
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.checksum(), Checksum::Standard);
+
+    let mut buffer = vec![CRC, 0, NAK, 0, ACK];
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    xmodem.set_checksum(Checksum::Standard);
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.checksum(), Checksum::Crc16);
+
+    // Anything before the handshake, like a boot log, is skipped.
+    let mut buffer = b"boot\r\n\x06C\0\x15\0\x06".to_vec();
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(xmodem.checksum(), Checksum::Crc16);
+    assert_eq!(buffer[8], EOT);
+}
+
+#[test]
+fn test_sender_streams() {
+    let mut sender = XmodemBuilder::new().sender();
+    assert_eq!(sender.feed(b'x').expect("noise"), Some(Event::Resync));
+    assert_eq!(sender.feed(b'y').expect("noise"), None);
+    assert_eq!(
+        sender.feed(STREAMING).expect("handshake"),
+        Some(Event::Started)
+    );
+    assert!(sender.is_streaming());
+    for number in 1..3 {
+        sender.send_packet(&[0; 128]).expect("send packet");
+        assert_eq!(sender.take_event(), Some(Event::Packet(number)));
+        assert_eq!(sender.take_output().len(), 3 + 128 + 2);
+    }
+
+    sender.send_packet(&[]).expect("send EOT");
+    assert_eq!(sender.take_event(), None);
+    assert_eq!(sender.feed(ACK).expect("an ACK"), Some(Event::Done));
+
+    let mut buffer = vec![STREAMING];
+    buffer.extend_from_slice(&[0; 3 + 1024 + 2]);
+    buffer.extend_from_slice(&[0, NAK, 0, ACK]);
+    let (tx, rx) = mpsc::channel();
+    let mut xmodem = Xmodem::builder()
+        .block_size(BlockSize::OneK)
+        .progress(move |status| {
+            if let Progress::Negotiated { .. } = status.progress {
+                tx.send(status.progress).expect("send mode");
+            }
+        })
+        .build(Cursor::new(buffer.as_mut_slice()));
+    assert_eq!(xmodem.send(&[5; 1024][..]).expect("send okay"), 1024);
+    drop(xmodem);
+    let modes: Vec<_> = rx.try_iter().collect();
+
+    assert_eq!(buffer[1], STX);
+    assert_eq!(buffer[3 + 1024 + 3], EOT);
+    assert!(matches!(
+        modes[..],
+        [Progress::Negotiated {
+            checksum: Checksum::Crc16,
+            block_size: BlockSize::OneK,
+            streaming: true,
+        }]
+    ));
+}
+
+#[test]
+fn test_checksum_receiver_gets_standard_packets() {
+    let input = vec![0x55; 2000];
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::builder().block_size(BlockSize::OneK).build(rx);
+        xmodem.send(&input[..]).map(|_| xmodem.into_inner().2)
+    });
+
+    let mut output = vec![];
+    Xmodem::builder()
+        .checksum(Checksum::Standard)
+        .padding_policy(PaddingPolicy::Truncate(2000))
+        .receive(tx, &mut output)
+        .expect("receive okay");
+
+    let sent = tx_thread.join().expect("tx join okay").expect("tx okay");
+    assert_eq!(output, vec![0x55; 2000]);
+    assert_eq!(sent.len(), 16 * (3 + 128 + 1) + 2);
+    assert!(sent.chunks(3 + 128 + 1).take(16).all(|p| p[0] == SOH));
+}
+
 #[test]
 fn test_small_packet_eof_error() {
     let mut xmodem = Xmodem::new(Cursor::new(vec![NAK, NAK, NAK]));
 
     let mut buffer = [1, 2, 3];
     let e = xmodem.read_packet(&mut buffer[..]).expect_err("read EOF");
-    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
 
     let e = xmodem.write_packet(&buffer).expect_err("write EOF");
-    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
 }
 
 #[test]
 fn test_bad_control() {
     let mut packet = [0; 128];
-    let e = Xmodem::new(Cursor::new(vec![0, CAN]))
+    let e = Xmodem::new(Cursor::new(vec![0, CAN, CAN]))
         .read_packet(&mut packet[..])
         .expect_err("CAN");
 
     println!("e: {:?}", e);
-    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
 
-    let e = Xmodem::new(Cursor::new(vec![0, 0xFF]))
+    let e = Xmodem::new(Cursor::new(vec![0, 0xFF, CAN, 0x7F, CAN, CAN]))
         .read_packet(&mut packet[..])
-        .expect_err("bad contorl");
+        .expect_err("CAN after noise");
 
-    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
 }
 
 #[test]
@@ -251,3 +519,1500 @@ fn test_eot() {
 
     assert_eq!(&buffer[..], &[NAK, EOT, NAK, EOT, ACK]);
 }
+
+/// Transmits `input` with `block_size` packets and returns the bytes received
+/// alongside the packet headers the sender wrote.
+fn transmit_with_block_size(input: Vec<u8>, block_size: BlockSize) -> (Vec<u8>, Vec<u8>) {
+    let (mut tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let written = {
+            let mut xmodem = Xmodem::new(&mut rx);
+            xmodem.set_block_size(block_size);
+            xmodem.send(&input[..]).expect("transmit okay")
+        };
+        (written, input.len(), rx.2)
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut output = vec![];
+        Xmodem::receive(&mut tx, &mut output).expect("receive okay");
+        output
+    });
+
+    let (written, len, rx_buf) = tx_thread.join().expect("tx join okay");
+    assert_eq!(written, len);
+
+    let mut headers = vec![];
+    let mut i = 0;
+    while i < rx_buf.len() {
+        headers.push(rx_buf[i]);
+        i += match rx_buf[i] {
+            SOH => 3 + 128 + 2,
+            STX => 3 + 1024 + 2,
+            _ => 1,
+        };
+    }
+
+    (rx_thread.join().expect("rx join okay"), headers)
+}
+
+#[test]
+fn test_1k_transmission() {
+    let input: Vec<u8> = (0..(2 * 1024 + 300)).map(|i| (i % 251) as u8).collect();
+    let (output, headers) = transmit_with_block_size(input.clone(), BlockSize::OneK);
+    assert_eq!(&headers, &[STX, STX, SOH, SOH, SOH, EOT, EOT]);
+    assert_eq!(output.len(), 2 * 1024 + 384);
+    assert_eq!(&output[..input.len()], &input[..]);
+    assert!(output[input.len()..].iter().all(|&b| b == 0));
+
+    let input: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let (output, headers) = transmit_with_block_size(input.clone(), BlockSize::OneK);
+    assert_eq!(&headers, &[STX, EOT, EOT]);
+    assert_eq!(&output[..input.len()], &input[..]);
+    assert_eq!(output.len(), 1024);
+
+    let input = vec![1u8; 1024];
+    let (output, headers) = transmit_with_block_size(input.clone(), BlockSize::Standard);
+    assert_eq!(
+        &headers,
+        &[SOH, SOH, SOH, SOH, SOH, SOH, SOH, SOH, EOT, EOT]
+    );
+    assert_eq!(output, input);
+}
+
+#[test]
+fn test_mixed_block_sizes() {
+    let (one, two) = ([1u8; 1024], [2u8; 128]);
+    let mut input = vec![STX, 1, 255 - 1];
+    input.extend_from_slice(&one);
+    input.push(checksum::checksum(&one));
+    input.extend_from_slice(&[SOH, 2, 255 - 2]);
+    input.extend_from_slice(&two);
+    input.push(checksum::checksum(&two));
+
+    let mut xmodem = Xmodem::new(Stalled {
+        timeouts: 0,
+        input: Cursor::new(input),
+        output: vec![],
+    });
+    xmodem.set_checksum(Checksum::Standard);
+
+    let mut buffer = [0u8; 1024];
+    assert_eq!(xmodem.read_packet(&mut buffer).expect("read 1K"), 1024);
+    assert_eq!(&buffer[..], &one[..]);
+    assert_eq!(xmodem.read_packet(&mut buffer).expect("read 128"), 128);
+    assert_eq!(&buffer[..128], &two[..]);
+    assert_eq!(&xmodem.inner.output, &[NAK, ACK, ACK]);
+}
+
+#[test]
+fn test_1k_packet_small_buffer() {
+    let mut buffer = vec![0, STX, 0, 0];
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet[..])
+        .expect_err("buffer too small");
+
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
+    assert_eq!(&buffer[..], &[CRC, STX, CAN, CAN]);
+}
+
+#[test]
+fn test_ymodem_header() {
+    let mut info = FileInfo::new("kernel8.img", 4242);
+    let mut buf = [0xFFu8; 128];
+    let used = info.encode(&mut buf);
+    assert_eq!(&buf[..used], &b"kernel8.img\x004242"[..]);
+    assert!(buf[used..].iter().all(|&b| b == 0));
+    assert_eq!(FileInfo::decode(&buf).expect("decode"), Some(info.clone()));
+
+    info.mtime = Some(0o13351441232);
+    info.mode = Some(0o100644);
+    let used = info.encode(&mut buf);
+    assert_eq!(&buf[..used], &b"kernel8.img\x004242 13351441232 100644"[..]);
+    assert_eq!(FileInfo::decode(&buf).expect("decode"), Some(info.clone()));
+
+    assert_eq!(FileInfo::decode(&[0; 128]).expect("decode"), None);
+    assert_eq!(info.encode(&mut buf[..16]), 0);
+    FileInfo::decode(b"name\0x12").expect_err("bad length");
+}
+
+#[test]
+fn test_ymodem_header_strips_directories() {
+    let decode = |name: &[u8]| {
+        let mut buf = [0u8; 128];
+        buf[..name.len()].copy_from_slice(name);
+        FileInfo::decode(&buf).map(|info| info.map(|info| info.name))
+    };
+
+    assert_eq!(
+        decode(b"boot/kernel8.img").unwrap(),
+        Some("kernel8.img".into())
+    );
+    assert_eq!(decode(b"/etc/passwd").unwrap(), Some("passwd".into()));
+    assert_eq!(decode(b"../../.bashrc").unwrap(), Some(".bashrc".into()));
+    assert_eq!(
+        decode(b"C:\\boot\\..\\x.txt").unwrap(),
+        Some("x.txt".into())
+    );
+    for name in &[&b".."[..], b"a/..", b"a\\.", b"dir/"] {
+        let e = decode(name).expect_err("no file name");
+        assert!(matches!(e, Error::Protocol(_)), "{:?}", e);
+    }
+}
+
+#[test]
+fn test_ymodem_batch() {
+    let files = vec![
+        (FileInfo::new("kernel8.img", 3000), vec![0xAB; 3000]),
+        (FileInfo::new("config.txt", 13), b"arm_64bit=1\n\n".to_vec()),
+        (FileInfo::new("empty", 0), vec![]),
+        (
+            FileInfo {
+                name: "x".repeat(200),
+                len: Some(128),
+                mtime: Some(1),
+                mode: None,
+            },
+            vec![7; 128],
+        ),
+    ];
+
+    let (tx, rx) = pipe();
+    let sent = files.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(rx);
+        for (info, data) in sent.iter() {
+            let n = ymodem.send_file(info, &data[..]).expect("send file");
+            assert_eq!(n, data.len() as u64);
+        }
+
+        ymodem.finish().expect("finish batch");
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut ymodem = Ymodem::new(tx);
+        let mut received = vec![];
+        while let Some(info) = ymodem.recv_header().expect("header") {
+            let mut data = vec![];
+            let n = ymodem.recv_data(&info, &mut data).expect("data");
+            assert_eq!(n, data.len() as u64);
+            received.push((info, data));
+        }
+
+        received
+    });
+
+    tx_thread.join().expect("tx join okay");
+    let received = rx_thread.join().expect("rx join okay");
+    assert_eq!(received, files);
+}
+
+#[test]
+fn test_bad_packet_number() {
+    let mut buffer = vec![0, SOH, 2, 255 - 2, 0, 0];
+    let mut packet = [0u8; 128];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet)
+        .expect_err("bad packet number");
+
+    assert!(
+        matches!(
+            e,
+            Error::PacketNumber {
+                expected: 1,
+                received: 2
+            }
+        ),
+        "{:?}",
+        e
+    );
+    assert_eq!(&buffer[4..], &[CAN, CAN]);
+
+    let mut buffer = vec![0, SOH, 0, 255, 0, 0];
+    let e = Xmodem::new(Cursor::new(buffer.as_mut_slice()))
+        .read_packet(&mut packet)
+        .expect_err("no previous packet");
+
+    assert!(
+        matches!(
+            e,
+            Error::PacketNumber {
+                expected: 1,
+                received: 0
+            }
+        ),
+        "{:?}",
+        e
+    );
+}
+
+#[test]
+fn test_error_conversion() {
+    let e = io::Error::from(Error::Cancelled);
+    assert_eq!(e.kind(), io::ErrorKind::ConnectionAborted);
+    assert!(matches!(Error::from(e), Error::Cancelled));
+
+    let e = io::Error::from(Error::Checksum);
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert!(matches!(Error::from(e), Error::Checksum));
+
+    let e = Error::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"));
+    assert!(matches!(e, Error::Timeout));
+
+    let e = Error::from(io::Error::new(io::ErrorKind::PermissionDenied, "denied"));
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(io::Error::from(e).kind(), io::ErrorKind::PermissionDenied);
+}
+
+#[test]
+fn test_receiver_resends_handshake() {
+    let data = [7u8; 128];
+    let mut input = vec![SOH, 1, 255 - 1];
+    input.extend_from_slice(&data);
+    input.push(checksum::checksum(&data));
+
+    let mut xmodem = Xmodem::new(Stalled {
+        timeouts: CRC_HANDSHAKE_ATTEMPTS + 2,
+        input: Cursor::new(input),
+        output: vec![],
+    });
+
+    let mut buffer = [0u8; 128];
+    assert_eq!(xmodem.read_packet(&mut buffer).expect("read packet"), 128);
+    assert_eq!(&xmodem.inner.output, &[CRC, CRC, CRC, NAK, NAK, NAK, ACK]);
+
+    let mut xmodem = Xmodem::builder().handshake_retries(4).build(Stalled {
+        timeouts: 4,
+        input: Cursor::new(vec![]),
+        output: vec![],
+    });
+
+    let e = xmodem.read_packet(&mut buffer).expect_err("late sender");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+    assert_eq!(&xmodem.inner.output, &[CRC, CRC, CRC, NAK]);
+}
+
+#[test]
+fn test_sender_waits_for_handshake() {
+    let mut xmodem = Xmodem::new(Stalled {
+        timeouts: 5,
+        input: Cursor::new(vec![NAK, NAK, ACK]),
+        output: vec![],
+    });
+
+    xmodem.write_packet(&[]).expect("write EOT");
+    assert_eq!(&xmodem.inner.output, &[EOT, EOT]);
+
+    let mut xmodem = Xmodem::builder().handshake_retries(5).build(Stalled {
+        timeouts: 5,
+        input: Cursor::new(vec![NAK]),
+        output: vec![],
+    });
+
+    let e = xmodem.write_packet(&[]).expect_err("late receiver");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+}
+
+#[test]
+fn test_session_timeout() {
+    let mut xmodem = Xmodem::builder()
+        .handshake_retries(usize::MAX)
+        .session_timeout(std::time::Duration::from_millis(10))
+        .cancel_count(2)
+        .build(Stalled {
+            timeouts: usize::MAX,
+            input: Cursor::new(vec![]),
+            output: vec![],
+        });
+
+    let mut buffer = [0u8; 128];
+    let e = xmodem
+        .read_packet(&mut buffer)
+        .expect_err("session timeout");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+    assert!(xmodem.inner.output.ends_with(&[NAK, CAN, CAN]));
+}
+
+#[test]
+fn test_cancel_count() {
+    let mut xmodem = Xmodem::builder().retries(1).cancel_count(5).build(Stalled {
+        timeouts: 0,
+        input: Cursor::new(vec![NAK, NAK, NAK]),
+        output: vec![],
+    });
+
+    let e = xmodem.write_packet(&[]).expect_err("EOT rejected");
+    assert!(matches!(e, Error::RetriesExhausted), "{:?}", e);
+    assert_eq!(&xmodem.inner.output, &[EOT, EOT, CAN, CAN, CAN, CAN, CAN]);
+}
+
+#[test]
+fn test_padding() {
+    let (tx, rx) = pipe();
+    let tx_thread =
+        std::thread::spawn(move || Xmodem::builder().padding(0x1A).transmit(&b"hello"[..], rx));
+
+    let mut output = vec![];
+    let n = Xmodem::receive(tx, &mut output).expect("rx okay");
+    assert_eq!(tx_thread.join().expect("tx join okay").expect("tx okay"), 5);
+    assert_eq!(n, 128);
+    assert_eq!(&output[..5], b"hello");
+    assert!(output[5..].iter().all(|&b| b == 0x1A));
+}
+
+#[test]
+fn test_ring_tracer() {
+    use std::sync::{Arc, Mutex};
+
+    let input = [7u8; 128];
+    let ring = Arc::new(Mutex::new(RingTracer::new(1024)));
+    let (tx, rx) = pipe();
+    let tracer = ring.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(rx);
+        xmodem.set_tracer(tracer);
+        xmodem.send(&input[..])
+    });
+
+    let mut output = [0u8; 128];
+    Xmodem::receive(tx, &mut output[..]).expect("receive okay");
+    tx_thread.join().expect("tx join okay").expect("tx okay");
+
+    let ring = ring.lock().expect("tracer lock");
+    assert_eq!(ring.bytes(Direction::Rx), &[CRC, ACK, NAK, ACK]);
+    let mut sent = crc_packet(1, &input);
+    sent.extend_from_slice(&[EOT, EOT]);
+    assert_eq!(ring.bytes(Direction::Tx), sent);
+
+    let frames: Vec<_> = ring
+        .records()
+        .filter_map(|record| match *record {
+            Record::Frame(direction, frame) => Some((direction, frame)),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        frames,
+        &[
+            (Direction::Rx, Frame::Crc),
+            (
+                Direction::Tx,
+                Frame::Header {
+                    size: 128,
+                    number: 1,
+                    complement: 254
+                }
+            ),
+            (
+                Direction::Tx,
+                Frame::Checksum {
+                    number: 1,
+                    ok: true
+                }
+            ),
+            (Direction::Rx, Frame::Ack),
+            (Direction::Tx, Frame::Eot),
+            (Direction::Rx, Frame::Nak),
+            (Direction::Tx, Frame::Eot),
+            (Direction::Rx, Frame::Ack),
+        ][..]
+    );
+}
+
+#[test]
+fn test_ring_tracer_capacity() {
+    let mut ring = RingTracer::new(2);
+    ring.byte(Direction::Rx, 1);
+    ring.byte(Direction::Tx, 2);
+    ring.byte(Direction::Rx, 3);
+    assert_eq!(ring.bytes(Direction::Rx), &[3]);
+    assert_eq!(ring.bytes(Direction::Tx), &[2]);
+}
+
+#[test]
+fn test_frame_dumper() {
+    let mut decoder = trace::Decoder::new();
+    let mut dumper = FrameDumper::new(vec![]);
+    let mut rx = vec![CRC, NAK, b'x'];
+    rx.extend(crc_packet(1, &[0; 128]));
+    *rx.last_mut().unwrap() ^= 1;
+    for byte in rx {
+        if let Some(frame) = decoder.decode(byte, Checksum::Crc16) {
+            dumper.frame(Direction::Rx, frame);
+        }
+    }
+
+    let dump = String::from_utf8(dumper.into_inner()).expect("utf-8 dump");
+    assert_eq!(
+        dump,
+        "<- 'C'\n<- NAK\n<- unexpected 0x78\n<- SOH 1/254\n<- packet 1 checksum bad\n"
+    );
+}
+
+#[test]
+fn test_duplicate_packet() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    receiver.take_output();
+
+    let first = crc_packet(1, &[1; 128]);
+    assert_eq!(
+        feed_all(&mut receiver, &first).unwrap(),
+        Some(Event::Packet(1))
+    );
+    assert_eq!(receiver.take_output(), &[ACK]);
+
+    // The sender missed the ACK and resends packet 1.
+    let event = feed_all(&mut receiver, &first).expect("duplicate");
+    assert_eq!(event, Some(Event::Duplicate(1)));
+    assert_eq!(receiver.take_output(), &[ACK]);
+
+    let second = crc_packet(2, &[2; 128]);
+    assert_eq!(
+        feed_all(&mut receiver, &second).unwrap(),
+        Some(Event::Packet(2))
+    );
+    assert_eq!(receiver.packet(), &[2; 128][..]);
+}
+
+#[test]
+fn test_duplicate_packet_discarded() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static DUPLICATES: AtomicUsize = AtomicUsize::new(0);
+    fn progress(progress: Progress) {
+        if let Progress::Duplicate(1) = progress {
+            DUPLICATES.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    // Every response overwrites the byte before the next packet.
+    let mut buffer = vec![0];
+    for packet in &[crc_packet(1, &[1; 128]), crc_packet(1, &[1; 128])] {
+        buffer.extend_from_slice(packet);
+        buffer.push(0);
+    }
+    buffer.extend(crc_packet(2, &[2; 128]));
+    buffer.extend_from_slice(&[0, EOT, 0, EOT, 0]);
+
+    let mut output = vec![];
+    let n =
+        Xmodem::receive_with_progress(Cursor::new(buffer.as_mut_slice()), &mut output, progress)
+            .expect("receive okay");
+
+    assert_eq!(n, 256);
+    assert_eq!(&output[..128], &[1; 128][..]);
+    assert_eq!(&output[128..], &[2; 128][..]);
+    assert_eq!(DUPLICATES.load(Ordering::SeqCst), 1);
+    assert_eq!(buffer[2 * 134], ACK);
+}
+
+#[test]
+fn test_resync_on_noise() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    receiver.take_output();
+
+    assert_eq!(receiver.feed(0x7F).unwrap(), Some(Event::Resync));
+    assert_eq!(receiver.feed(0x55).unwrap(), None);
+    assert_eq!(receiver.take_output(), &[]);
+
+    // A noise SOH is followed by a number that doesn't match its complement.
+    // The packet it seems to start is skipped until the line is quiet.
+    let e = feed_all(&mut receiver, &[SOH, 1, 0]).expect_err("bad complement");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+    assert_eq!(receiver.take_output(), &[]);
+    assert_eq!(receiver.timeout().expect("line is quiet"), None);
+    assert_eq!(receiver.take_output(), &[NAK]);
+
+    let event = feed_all(&mut receiver, &crc_packet(1, &[0; 128])).expect("packet");
+    assert_eq!(event, Some(Event::Packet(1)));
+}
+
+#[test]
+fn test_purge_before_nak() {
+    let mut receiver = XmodemBuilder::new().purge(true).receiver();
+    receiver.start();
+    receiver.take_output();
+
+    let mut packet = crc_packet(1, &[0; 128]);
+    *packet.last_mut().unwrap() ^= 1;
+    packet.extend_from_slice(&[EOT, CAN, SOH]);
+
+    let e = feed_all(&mut receiver, &packet[..133]).expect_err("bad CRC");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+    assert_eq!(feed_all(&mut receiver, &packet[133..]).unwrap(), None);
+    assert_eq!(receiver.take_output(), &[]);
+
+    receiver.timeout().expect("line is quiet");
+    assert_eq!(receiver.take_output(), &[NAK]);
+
+    let event = feed_all(&mut receiver, &crc_packet(1, &[0; 128])).expect("packet");
+    assert_eq!(event, Some(Event::Packet(1)));
+}
+
+#[test]
+fn test_skip_rejected_packet() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.start();
+    receiver.take_output();
+
+    // Nothing in the payload of a packet with a bad complement starts a
+    // packet or cancels the session.
+    let mut data = [0; 128];
+    data[..7].copy_from_slice(&[SOH, 2, 253, EOT, CAN, CAN, STX]);
+    let mut packet = crc_packet(1, &data);
+    packet[2] ^= 1;
+    let e = feed_all(&mut receiver, &packet[..3]).expect_err("bad complement");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+    assert_eq!(feed_all(&mut receiver, &packet[3..]).unwrap(), None);
+    assert_eq!(receiver.take_output(), &[NAK]);
+
+    let event = feed_all(&mut receiver, &crc_packet(1, &[0; 128])).expect("packet");
+    assert_eq!(event, Some(Event::Packet(1)));
+}
+
+#[test]
+fn test_receiver_naks_on_timeout() {
+    let mut receiver = XmodemBuilder::new().retries(2).receiver();
+    receiver.start();
+    feed_all(&mut receiver, &crc_packet(1, &[0; 128])).expect("packet");
+    assert_eq!(receiver.take_output(), &[CRC, ACK]);
+
+    // Packet 2 is cut short.
+    feed_all(&mut receiver, &crc_packet(2, &[0; 128])[..50]).expect("partial packet");
+    assert_eq!(receiver.timeout().expect("NAK"), Some(Event::Timeout(2)));
+    assert_eq!(receiver.take_output(), &[NAK]);
+
+    let e = receiver.timeout().expect_err("retries exhausted");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+    assert_eq!(receiver.take_output(), &[CAN, CAN]);
+}
+
+#[test]
+fn test_sender_resends_on_timeout() {
+    let mut sender = XmodemBuilder::new().retries(2).sender();
+    sender.feed(CRC).expect("handshake");
+    sender.send_packet(&[0; 128]).expect("send packet");
+    let packet = sender.take_output().to_vec();
+
+    // The receiver purges the line for a read timeout before it responds.
+    assert_eq!(sender.timeout().expect("wait"), None);
+    assert_eq!(sender.timeout().expect("resend"), Some(Event::Timeout(1)));
+    sender.send_packet(&[0; 128]).expect("send packet again");
+    assert_eq!(sender.take_output(), &packet[..]);
+
+    sender.timeout().expect("wait");
+    let e = sender.timeout().expect_err("retries exhausted");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+    assert_eq!(sender.take_output(), &[CAN, CAN]);
+}
+
+#[test]
+fn test_streaming_sender_notices_cancel() {
+    let mut sender = XmodemBuilder::new().sender();
+    sender.feed(STREAMING).expect("handshake");
+    sender.send_packet(&[0; 128]).expect("send packet");
+    assert_eq!(sender.take_event(), Some(Event::Packet(1)));
+    assert_eq!(sender.feed(CAN).expect("a first CAN"), None);
+    let e = sender.feed(CAN).expect_err("cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+}
+
+#[test]
+fn test_streaming_cancel_between_packets() {
+    let mut xmodem = Xmodem::new(Scripted {
+        input: vec![Some(STREAMING), None, Some(CAN), Some(CAN)],
+        output: vec![],
+    });
+    xmodem.set_try_read(Scripted::read_available);
+
+    let e = xmodem.write_packet(&[0; 128]).expect_err("cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+    assert_eq!(xmodem.stats().cancels, 1);
+    assert_eq!(xmodem.into_inner().output.len(), 3 + 128 + 2);
+}
+
+#[test]
+fn test_sender_resends_eot() {
+    let mut sender = XmodemBuilder::new().sender();
+    sender.feed(NAK).expect("handshake");
+    sender.send_packet(&[]).expect("send EOT");
+    assert_eq!(sender.take_output(), &[EOT]);
+
+    // The receiver's NAK is lost; it acknowledges the EOT sent again.
+    sender.timeout().expect("wait");
+    assert_eq!(sender.timeout().expect("resend"), Some(Event::Timeout(1)));
+    sender.send_packet(&[]).expect("send EOT again");
+    assert_eq!(sender.take_output(), &[EOT]);
+    assert_eq!(sender.feed(ACK).expect("an ACK"), Some(Event::Done));
+}
+
+#[test]
+fn test_progress_closure() {
+    use std::sync::{Arc, Mutex};
+
+    let input = [3u8; 300];
+    let statuses = Arc::new(Mutex::new(vec![]));
+    let (tx, rx) = pipe();
+    let record = statuses.clone();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::builder()
+            .total(300)
+            .progress(move |status| record.lock().unwrap().push(*status))
+            .transmit(&input[..], rx)
+    });
+
+    let mut output = [0u8; 384];
+    Xmodem::receive(tx, &mut output[..]).expect("receive okay");
+    assert_eq!(
+        tx_thread.join().expect("tx join okay").expect("tx okay"),
+        300
+    );
+
+    let statuses = statuses.lock().unwrap();
+    let packets: Vec<_> = statuses
+        .iter()
+        .filter(|status| matches!(status.progress, Progress::Packet(_)))
+        .map(|status| status.bytes)
+        .collect();
+    assert_eq!(packets, &[128, 256, 384]);
+    assert!(matches!(statuses[0].progress, Progress::Waiting));
+
+    let last = statuses.last().expect("a status");
+    assert_eq!(last.total, Some(300));
+    assert_eq!(last.retries, 0);
+    assert_eq!(last.fraction(), Some(1.0));
+    assert!(statuses.windows(2).all(|w| w[0].elapsed <= w[1].elapsed));
+}
+
+#[test]
+fn test_progress_counts_retries() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let mut buffer = vec![0];
+    let mut bad = crc_packet(1, &[1; 128]);
+    *bad.last_mut().unwrap() ^= 1;
+    buffer.extend(bad);
+    buffer.push(0);
+    buffer.extend(crc_packet(1, &[1; 128]));
+    buffer.push(0);
+
+    let retries = Arc::new(AtomicUsize::new(0));
+    let seen = retries.clone();
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    xmodem.set_progress(move |status| seen.store(status.retries, Ordering::SeqCst));
+
+    let mut packet = [0u8; 128];
+    let e = xmodem.read_packet(&mut packet).expect_err("bad CRC");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+    assert_eq!(xmodem.read_packet(&mut packet).expect("packet"), 128);
+    assert_eq!(retries.load(Ordering::SeqCst), 1);
+}
+
+/// Transmits `input` padded with `padding` and receives it with `policy`.
+fn transmit_with_padding(input: Vec<u8>, padding: u8, policy: PaddingPolicy) -> Vec<u8> {
+    let (tx, rx) = pipe();
+    let tx_thread =
+        std::thread::spawn(move || Xmodem::builder().padding(padding).transmit(&input[..], rx));
+
+    let mut output = vec![];
+    let n = Xmodem::builder()
+        .padding_policy(policy)
+        .receive(tx, &mut output)
+        .expect("receive okay");
+
+    tx_thread.join().expect("tx join okay").expect("tx okay");
+    assert_eq!(n, output.len());
+    output
+}
+
+#[test]
+fn test_padding_policy() {
+    let input: Vec<u8> = (1..=200u8).collect();
+
+    let output = transmit_with_padding(input.clone(), 0x1A, PaddingPolicy::Keep);
+    assert_eq!(output.len(), 256);
+    assert_eq!(&output[..200], &input[..]);
+    assert!(output[200..].iter().all(|&b| b == 0x1A));
+
+    let output = transmit_with_padding(input.clone(), 0x1A, PaddingPolicy::StripSub);
+    assert_eq!(output, input);
+
+    let output = transmit_with_padding(input.clone(), 0, PaddingPolicy::StripNul);
+    assert_eq!(output, input);
+
+    // Only the padding of the last packet is stripped.
+    let mut zeroes = vec![0u8; 128];
+    zeroes.push(1);
+    let output = transmit_with_padding(zeroes.clone(), 0, PaddingPolicy::StripNul);
+    assert_eq!(output, zeroes);
+
+    let output = transmit_with_padding(input.clone(), 0, PaddingPolicy::Truncate(150));
+    assert_eq!(output, &input[..150]);
+
+    let output = transmit_with_padding(input.clone(), 0, PaddingPolicy::Truncate(1000));
+    assert_eq!(output.len(), 256);
+}
+
+#[test]
+fn test_reader_writer_adapters() {
+    let input: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+    let expected = input.clone();
+
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut writer = XmodemWriter::new(rx);
+        for chunk in input.chunks(100) {
+            io::Write::write_all(&mut writer, chunk).expect("write okay");
+        }
+
+        writer.finish().map(|_| ())
+    });
+
+    let xmodem = Xmodem::builder()
+        .padding_policy(PaddingPolicy::Truncate(1000))
+        .build(tx);
+    let mut reader = XmodemReader::from_xmodem(xmodem);
+    let mut output = vec![];
+    let mut buf = [0u8; 77];
+    loop {
+        match io::Read::read(&mut reader, &mut buf).expect("read okay") {
+            0 => break,
+            n => output.extend_from_slice(&buf[..n]),
+        }
+    }
+
+    tx_thread.join().expect("tx join okay").expect("tx okay");
+    assert_eq!(output, expected);
+}
+
+#[test]
+fn test_writer_waits_for_checksum_receiver() {
+    let input: Vec<u8> = (0..1000).map(|i| (i * 7) as u8).collect();
+    let expected = input.clone();
+
+    let (tx, mut rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        {
+            let xmodem = Xmodem::builder().block_size(BlockSize::OneK).build(&mut rx);
+            let mut writer = XmodemWriter::from_xmodem(xmodem);
+            io::Write::write_all(&mut writer, &input).expect("write okay");
+            writer.finish().expect("finish okay");
+        }
+        rx.2
+    });
+
+    let xmodem = Xmodem::builder()
+        .checksum(Checksum::Standard)
+        .padding_policy(PaddingPolicy::Truncate(1000))
+        .build(tx);
+    let mut output = vec![];
+    io::copy(&mut XmodemReader::from_xmodem(xmodem), &mut output).expect("copy okay");
+    assert_eq!(output, expected);
+
+    // The receiver asked for the 8-bit checksum, so the writer falls back to
+    // 128-byte packets.
+    let rx_buf = tx_thread.join().expect("tx join okay");
+    assert_eq!(rx_buf.len(), 8 * 132 + 2);
+    assert_eq!(&rx_buf[8 * 132..], &[EOT, EOT]);
+    for (i, packet) in rx_buf.chunks(132).take(8).enumerate() {
+        assert_eq!(&packet[..3], &[SOH, i as u8 + 1, 254 - i as u8]);
+    }
+}
+
+#[test]
+fn test_writer_finishes_on_drop() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        let mut writer = XmodemWriter::from_xmodem(Xmodem::builder().padding(0x1A).build(rx));
+        io::Write::write_all(&mut writer, b"hello").expect("write okay");
+    });
+
+    let mut reader = XmodemReader::from_xmodem(
+        Xmodem::builder()
+            .padding_policy(PaddingPolicy::StripSub)
+            .build(tx),
+    );
+    let mut output = vec![];
+    io::copy(&mut reader, &mut output).expect("copy okay");
+
+    tx_thread.join().expect("tx join okay");
+    assert_eq!(output, b"hello");
+}
+
+#[cfg(feature = "async")]
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .expect("runtime okay")
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_transmission() {
+    let input: Vec<u8> = (0..3000usize).map(|i| i as u8).collect();
+    let (tx, rx) = tokio::io::duplex(64);
+    let sender = Xmodem::builder()
+        .block_size(BlockSize::OneK)
+        .padding(0x1A)
+        .build_async(rx);
+    let receiver = Xmodem::builder()
+        .padding_policy(PaddingPolicy::StripSub)
+        .build_async(tx);
+
+    let runtime = runtime();
+    let transmit = runtime.spawn(sender.send(Cursor::new(input.clone())));
+    let mut output = vec![];
+    let received = runtime
+        .block_on(receiver.recv(&mut output))
+        .expect("receive okay");
+    let sent = runtime
+        .block_on(transmit)
+        .expect("join okay")
+        .expect("transmit okay");
+
+    assert_eq!(sent, 3000);
+    assert_eq!(received, 3000);
+    assert_eq!(output, input);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_checksum_receiver_gets_standard_packets() {
+    let (tx, rx) = tokio::io::duplex(64);
+    let sender = Xmodem::builder()
+        .block_size(BlockSize::OneK)
+        .build_async(rx);
+    let mut receiver = Xmodem::builder()
+        .checksum(Checksum::Standard)
+        .build_async(tx);
+
+    let runtime = runtime();
+    let transmit = runtime.spawn(sender.send(Cursor::new(vec![7u8; 2000])));
+    let mut sizes = vec![];
+    let mut packet = [0u8; 1024];
+    loop {
+        match runtime.block_on(receiver.read_packet(&mut packet)) {
+            Ok(0) => break,
+            Ok(n) => sizes.push(n),
+            Err(e) => panic!("read failed: {:?}", e),
+        }
+    }
+
+    let sent = runtime
+        .block_on(transmit)
+        .expect("join okay")
+        .expect("transmit okay");
+
+    assert_eq!(sent, 2000);
+    assert_eq!(sizes, vec![128; 16]);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_streaming_cancel() {
+    use tokio::io::AsyncWriteExt;
+
+    let (tx, mut rx) = tokio::io::duplex(4096);
+    let mut sender = Xmodem::builder().build_async(tx);
+
+    let runtime = runtime();
+    runtime
+        .block_on(rx.write_all(&[STREAMING, CAN, CAN]))
+        .expect("write okay");
+    let e = runtime
+        .block_on(sender.write_packet(&[0; 128]))
+        .expect_err("cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_read_timeout() {
+    use tokio::io::AsyncReadExt;
+
+    let (tx, mut rx) = tokio::io::duplex(64);
+    let mut receiver = Xmodem::builder()
+        .handshake_retries(2)
+        .read_timeout(std::time::Duration::from_millis(10))
+        .build_async(tx);
+
+    let runtime = runtime();
+    let mut buffer = [0u8; 128];
+    let e = runtime
+        .block_on(receiver.read_packet(&mut buffer))
+        .expect_err("timeout");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+
+    drop(receiver);
+    let mut sent = vec![];
+    runtime
+        .block_on(rx.read_to_end(&mut sent))
+        .expect("read okay");
+    assert_eq!(sent, &[CRC, CRC]);
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_session_timeout() {
+    use tokio::io::AsyncReadExt;
+
+    let (tx, mut rx) = tokio::io::duplex(64);
+    let mut receiver = Xmodem::builder()
+        .handshake_retries(usize::MAX)
+        .read_timeout(std::time::Duration::from_millis(5))
+        .session_timeout(std::time::Duration::from_millis(50))
+        .cancel_count(2)
+        .build_async(tx);
+
+    let runtime = runtime();
+    let mut buffer = [0u8; 128];
+    let e = runtime
+        .block_on(receiver.read_packet(&mut buffer))
+        .expect_err("timeout");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+
+    drop(receiver);
+    let mut sent = vec![];
+    runtime
+        .block_on(rx.read_to_end(&mut sent))
+        .expect("read okay");
+    assert!(sent.ends_with(&[CAN, CAN]), "{:?}", sent);
+}
+
+/// Transfers `input` over a noisy line. Returns what the sender and the
+/// receiver returned.
+fn noisy_transfer(seed: u64, faults: Faults, input: Vec<u8>) -> (Result<usize>, Result<Vec<u8>>) {
+    let len = input.len() as u64;
+    let (mut tx, rx) = noisy_pipe(seed, faults);
+    let tx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::builder()
+            .handshake_retries(5)
+            .block_size(BlockSize::OneK)
+            .build(rx);
+        xmodem.set_try_read(NoisyPipe::read_available);
+        xmodem.send(&input[..])
+    });
+
+    // The receiver's end stays open until the sender is done, so that a
+    // sender outliving the receiver times out rather than reading EOF.
+    let mut output = vec![];
+    let received = Xmodem::builder()
+        .handshake_retries(5)
+        .purge(true)
+        .padding_policy(PaddingPolicy::Truncate(len))
+        .receive(&mut tx, &mut output)
+        .map(|_| output);
+
+    (tx_thread.join().expect("tx join okay"), received)
+}
+
+/// Returns `true` if `e` tells the user why a transfer over a noisy line
+/// failed, rather than leaking a condition the session should have handled.
+fn is_clear_error(e: &Error) -> bool {
+    matches!(
+        *e,
+        Error::Timeout | Error::Cancelled | Error::RetriesExhausted
+    )
+}
+
+/// Returns a line with one kind of fault, at a rate a session should
+/// usually recover from.
+fn fault_model(model: usize, len: usize) -> Faults {
+    match model {
+        0 => Faults::new().bit_flips(0.0005),
+        1 => Faults::new().drops(0.0005),
+        2 => Faults::new().garbage(0.001),
+        3 => Faults::new().duplicates(0.001),
+        4 => Faults::new().delays(0.01, std::time::Duration::from_millis(5)),
+        _ => Faults::new().stall_after(len / 2),
+    }
+}
+
+#[test]
+fn test_noisy_pipe_is_seeded() {
+    use std::io::{Read, Write};
+
+    let faults = Faults::new().bit_flips(0.1).drops(0.1).garbage(0.1);
+    let mut received = vec![];
+    for _ in 0..2 {
+        let (mut a, mut b) = noisy_pipe(42, faults);
+        a.write_all(&[0x55; 256]).expect("write okay");
+        assert!(a.injected() > 0);
+        drop(a);
+
+        let mut bytes = vec![];
+        b.read_to_end(&mut bytes).expect_err("eof");
+        received.push(bytes);
+    }
+
+    assert_ne!(received[0], &[0x55; 256][..]);
+    assert_eq!(received[0], received[1]);
+}
+
+#[test]
+fn test_noisy_pipe_perfect_line() {
+    let input: Vec<u8> = (0..1500usize).map(|i| (i * 7) as u8).collect();
+    let (sent, received) = noisy_transfer(1, Faults::new(), input.clone());
+    assert_eq!(sent.expect("transmit okay"), input.len());
+    assert_eq!(received.expect("receive okay"), input);
+}
+
+#[test]
+fn test_noisy_pipe_stalled_peer() {
+    let (sent, received) = noisy_transfer(1, Faults::new().stall_after(300), vec![7; 1024]);
+    let e = received.expect_err("stalled");
+    assert!(matches!(e, Error::Timeout), "{:?}", e);
+    let e = sent.expect_err("stalled");
+    assert!(matches!(e, Error::Cancelled | Error::Timeout), "{:?}", e);
+}
+
+proptest! {
+    // A fixed seed makes every run draw the same cases, so that a failure
+    // can be reproduced.
+    #![proptest_config(proptest::test_runner::Config {
+        rng_seed: proptest::test_runner::RngSeed::Fixed(0x5EED),
+        ..proptest::test_runner::Config::with_cases(48)
+    })]
+
+    #[test]
+    fn prop_noisy_transfer_is_exact_or_fails_clearly(
+        seed in proptest::num::u64::ANY,
+        input in proptest::collection::vec(proptest::num::u8::ANY, 0..2048),
+        model in 0..6usize,
+    ) {
+        let len = input.len();
+        let (sent, received) = noisy_transfer(seed, fault_model(model, len), input.clone());
+        match received {
+            Ok(output) => prop_assert_eq!(output, input),
+            Err(e) => prop_assert!(is_clear_error(&e), "receiver: {:?}", e),
+        }
+
+        match sent {
+            Ok(n) => prop_assert_eq!(n, len),
+            Err(e) => prop_assert!(is_clear_error(&e), "sender: {:?}", e),
+        }
+    }
+}
+
+#[test]
+fn test_checkpoint_round_trip() {
+    let mut checkpoint = Checkpoint::new();
+    checkpoint.commit(b"hello");
+    checkpoint.commit(b"world");
+
+    let mut saved = vec![];
+    checkpoint.write_to(&mut saved).expect("write okay");
+    assert_eq!(
+        std::str::from_utf8(&saved).unwrap(),
+        "xmodem checkpoint\npackets 2\nbytes 10\ncrc32 f9eb20ad\n"
+    );
+
+    let loaded = Checkpoint::read_from(&saved[..]).expect("read okay");
+    assert_eq!(loaded, checkpoint);
+    assert_eq!(loaded.next_packet(), 3);
+
+    let e = Checkpoint::read_from(&b"xmodem checkpoint\npackets 2\n"[..]).expect_err("short");
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
+}
+
+#[test]
+fn test_checkpoint_verify() {
+    let mut checkpoint = Checkpoint::new();
+    checkpoint.commit(&[1; 128]);
+
+    let mut data = Cursor::new([[1u8; 128], [2u8; 128]].concat());
+    checkpoint.verify(&mut data).expect("verify okay");
+    assert_eq!(data.position(), 128);
+
+    let e = checkpoint.verify(&[1u8; 100][..]).expect_err("short");
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
+    let e = checkpoint.verify(&[3u8; 128][..]).expect_err("mismatch");
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
+}
+
+/// A reader that yields `data`, then fails.
+struct Interrupted(Cursor<Vec<u8>>);
+
+impl io::Read for Interrupted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf)? {
+            0 => Err(io::Error::new(io::ErrorKind::BrokenPipe, "interrupted")),
+            n => Ok(n),
+        }
+    }
+}
+
+#[test]
+fn test_resume_transfer() {
+    let input: Vec<u8> = (0..1024usize).map(|i| (i / 3) as u8).collect();
+
+    // The first session fails after three packets.
+    let (tx, rx) = pipe();
+    let first = input[..384].to_vec();
+    let tx_thread = std::thread::spawn(move || {
+        let mut xmodem = Xmodem::new(rx);
+        xmodem
+            .send(Interrupted(Cursor::new(first)))
+            .expect_err("interrupted");
+        xmodem.checkpoint()
+    });
+
+    let mut output = vec![];
+    let mut xmodem = Xmodem::new(tx);
+    xmodem.recv(&mut output).expect_err("interrupted");
+    let received = xmodem.checkpoint();
+    let sent = tx_thread.join().expect("tx join okay");
+    assert_eq!(sent, received);
+    assert_eq!(received.packets(), 3);
+    assert_eq!(output, &input[..384]);
+
+    // The second session resumes with the fourth packet.
+    let (tx, rx) = pipe();
+    let data = input.clone();
+    let tx_thread = std::thread::spawn(move || {
+        let mut data = Cursor::new(data);
+        sent.verify(&mut data).expect("verify okay");
+        let mut xmodem = Xmodem::new(rx);
+        xmodem.resume(sent);
+        let n = xmodem.send(data).expect("send okay");
+        (n, rx_bytes(xmodem))
+    });
+
+    let mut xmodem = Xmodem::new(tx);
+    xmodem.resume(received);
+    let n = xmodem.recv(&mut output).expect("receive okay");
+    let (sent_n, sent_bytes) = tx_thread.join().expect("tx join okay");
+    assert_eq!(n, 640);
+    assert_eq!(sent_n, 640);
+    assert_eq!(&sent_bytes[..3], &[SOH, 4, 255 - 4]);
+    assert_eq!(output, input);
+    assert_eq!(xmodem.checkpoint().packets(), 8);
+}
+
+/// Returns the bytes written by an `Xmodem` session over a `Pipe`.
+fn rx_bytes(xmodem: Xmodem<Pipe>) -> Vec<u8> {
+    xmodem.into_inner().2
+}
+
+#[test]
+fn test_resume_after_lost_ack() {
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.resume(4);
+    receiver.start();
+    assert_eq!(receiver.take_output(), &[CRC]);
+
+    let event = feed_all(&mut receiver, &crc_packet(3, &[3; 128])).expect("duplicate");
+    assert_eq!(event, Some(Event::Duplicate(3)));
+    assert_eq!(receiver.take_output(), &[ACK]);
+
+    let event = feed_all(&mut receiver, &crc_packet(4, &[4; 128])).expect("packet");
+    assert_eq!(event, Some(Event::Packet(4)));
+
+    let mut receiver = XmodemBuilder::new().receiver();
+    receiver.resume(4);
+    receiver.start();
+    let e = feed_all(&mut receiver, &[SOH, 2, 255 - 2]).expect_err("too far back");
+    assert!(matches!(e, Error::PacketNumber { .. }), "{:?}", e);
+}
+
+#[test]
+fn test_sha256() {
+    let hex =
+        |digest: [u8; 32]| -> String { digest.iter().map(|b| format!("{:02x}", b)).collect() };
+
+    let mut sha256 = digest::Sha256::new();
+    sha256.update(b"abc");
+    assert_eq!(
+        hex(sha256.finish()),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+
+    let mut sha256 = digest::Sha256::new();
+    for chunk in b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".chunks(5) {
+        sha256.update(chunk);
+    }
+
+    assert_eq!(
+        hex(sha256.finish()),
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+    );
+}
+
+#[test]
+fn test_verification_block() {
+    let mut checkpoint = Checkpoint::new();
+    let mut hasher = digest::Hasher::new(Digest::Sha256);
+    checkpoint.commit(b"hello");
+    hasher.update(b"hello");
+    let mut whole = checkpoint;
+    whole.commit(b"world");
+    let mut announced = hasher.clone();
+    announced.update(b"world");
+    let block = announced.block(&whole);
+
+    let len = hasher
+        .check(&block, &checkpoint, b"world\x1a\x1a")
+        .expect("match");
+    assert_eq!(len, 5);
+
+    let e = hasher
+        .check(&block, &checkpoint, b"wor")
+        .expect_err("short");
+    assert!(matches!(e, Error::Verification), "{:?}", e);
+    let e = hasher
+        .check(&block, &checkpoint, b"w0rld")
+        .expect_err("mismatch");
+    assert!(matches!(e, Error::Verification), "{:?}", e);
+    let e = hasher
+        .check(&[0; 128], &checkpoint, b"world")
+        .expect_err("not a block");
+    assert!(matches!(e, Error::Protocol(_)), "{:?}", e);
+}
+
+#[test]
+fn test_verified_transfer() {
+    for &digest in &[Digest::Crc32, Digest::Sha256] {
+        let input: Vec<u8> = (0..300usize).map(|i| i as u8).collect();
+        let (tx, rx) = pipe();
+        let data = input.clone();
+        let tx_thread =
+            std::thread::spawn(move || Xmodem::builder().verify(digest).transmit(&data[..], rx));
+
+        let mut output = vec![];
+        let n = Xmodem::builder()
+            .verify(digest)
+            .receive(tx, &mut output)
+            .expect("receive okay");
+        let sent = tx_thread.join().expect("tx join okay").expect("send okay");
+        assert_eq!(n, 300);
+        assert_eq!(sent, 300);
+        assert_eq!(output, input);
+    }
+}
+
+#[test]
+fn test_verified_transfer_mismatch() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || {
+        Xmodem::builder()
+            .verify(Digest::Crc32)
+            .transmit(&[1u8; 200][..], rx)
+    });
+
+    // The receiver is kept alive until the sender has read the `CAN`s.
+    let mut xmodem = Xmodem::builder().verify(Digest::Sha256).build(tx);
+    let e = xmodem.recv(io::sink()).expect_err("mismatch");
+    assert!(matches!(e, Error::Verification), "{:?}", e);
+    let e = tx_thread
+        .join()
+        .expect("tx join okay")
+        .expect_err("cancelled");
+    assert!(matches!(e, Error::Verification), "{:?}", e);
+    assert!(rx_bytes(xmodem).ends_with(&[ACK, CAN, CAN]));
+}
+
+#[test]
+fn test_verify_resumed_transfer() {
+    let mut checkpoint = Checkpoint::new();
+    checkpoint.commit(&[0; 128]);
+    let (tx, _rx) = pipe();
+    let mut xmodem = Xmodem::builder().verify(Digest::Sha256).build(tx);
+    xmodem.resume(checkpoint);
+    let e = xmodem.recv(io::sink()).expect_err("can't resume");
+    assert!(matches!(e, Error::InvalidInput(_)), "{:?}", e);
+}
+
+#[test]
+fn test_transfer_stats() {
+    let (tx, rx) = pipe();
+    let tx_thread = std::thread::spawn(move || Xmodem::transmit_with_stats(&[7u8; 300][..], rx));
+
+    let mut output = vec![];
+    let received = Xmodem::receive_with_stats(tx, &mut output).expect("receive okay");
+    let sent = tx_thread.join().expect("tx join okay").expect("tx okay");
+
+    assert_eq!(sent.bytes, 300);
+    assert_eq!(received.bytes, 384);
+    for stats in &[sent, received] {
+        assert_eq!(stats.packets, 3);
+        assert_eq!(stats.retransmissions(), 0);
+        assert_eq!(stats.cancels, 0);
+        assert!(stats.handshake <= stats.duration);
+    }
+}
+
+#[test]
+fn test_stats_count_retransmissions() {
+    let mut buffer = vec![0];
+    let mut bad = crc_packet(1, &[1; 128]);
+    *bad.last_mut().unwrap() ^= 1;
+    for packet in &[bad, crc_packet(1, &[1; 128]), crc_packet(1, &[1; 128])] {
+        buffer.extend_from_slice(packet);
+        buffer.push(0);
+    }
+    buffer.extend(crc_packet(2, &[2; 128]));
+    buffer.extend_from_slice(&[0, EOT, 0, EOT, 0]);
+
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    assert_eq!(xmodem.recv(&mut vec![]).expect("receive okay"), 256);
+    let stats = xmodem.stats();
+    assert_eq!(stats.bytes, 256);
+    assert_eq!(stats.packets, 2);
+    assert_eq!(stats.naks, 1);
+    assert_eq!(stats.bad_acks, 0);
+    assert_eq!(stats.duplicates, 1);
+    assert_eq!(stats.retransmissions(), 2);
+
+    let mut buffer = vec![0, CAN, CAN];
+    let mut xmodem = Xmodem::new(Cursor::new(buffer.as_mut_slice()));
+    let e = xmodem.recv(&mut vec![]).expect_err("cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+    assert_eq!(xmodem.stats().cancels, 1);
+}
+
+/// A stream reading the bytes of `input` one at a time, with `None` standing
+/// for a read timeout.
+struct Scripted {
+    input: Vec<Option<u8>>,
+    output: Vec<u8>,
+}
+
+impl io::Read for Scripted {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.input.is_empty() {
+            return Ok(0);
+        }
+
+        match self.input.remove(0) {
+            Some(byte) => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            None => Err(io::Error::new(io::ErrorKind::TimedOut, "timed out")),
+        }
+    }
+}
+
+impl Scripted {
+    /// Reads the bytes of `input` up to the next timeout, which is consumed,
+    /// as the bytes already available.
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < buf.len() && !self.input.is_empty() {
+            match self.input.remove(0) {
+                Some(byte) => buf[n] = byte,
+                None => break,
+            }
+
+            n += 1;
+        }
+
+        Ok(n)
+    }
+}
+
+impl io::Write for Scripted {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.output.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_sender_stats_count_retransmissions() {
+    let input = vec![
+        // Packet 1 is rejected.
+        Some(CRC),
+        Some(NAK),
+        Some(ACK),
+        // The response to packet 2 is garbled.
+        Some(0x55),
+        Some(NAK),
+        Some(ACK),
+        // The response to packet 3 is lost.
+        None,
+        None,
+        Some(ACK),
+        Some(NAK),
+        Some(ACK),
+    ];
+
+    let mut xmodem = Xmodem::builder()
+        .block_size(BlockSize::Standard)
+        .build(Scripted {
+            input,
+            output: vec![],
+        });
+    let sent = xmodem.send(&mut &[7u8; 384][..]).expect("send okay");
+    assert_eq!(sent, 384);
+
+    let stats = xmodem.stats();
+    assert_eq!(stats.packets, 3);
+    assert_eq!(stats.naks, 1);
+    assert_eq!(stats.bad_acks, 1);
+    assert_eq!(stats.timeouts, 1);
+    assert_eq!(stats.retransmissions(), 3);
+}
+
+#[test]
+fn test_sender_discards_duplicated_ack() {
+    let input = vec![
+        // `None` ends the bytes available before a packet is sent.
+        Some(CRC),
+        None,
+        // The ACK of packet 1 is duplicated by the line.
+        Some(ACK),
+        Some(ACK),
+        None,
+        Some(ACK),
+        None,
+        Some(NAK),
+        Some(ACK),
+    ];
+
+    let mut xmodem = Xmodem::builder()
+        .block_size(BlockSize::Standard)
+        .build(Scripted {
+            input,
+            output: vec![],
+        });
+    xmodem.set_try_read(Scripted::read_available);
+    assert_eq!(xmodem.send(&[7u8; 256][..]).expect("send okay"), 256);
+    assert_eq!(xmodem.stats().retransmissions(), 0);
+
+    let scripted = xmodem.into_inner();
+    assert!(scripted.input.is_empty());
+    let packet = 3 + 128 + 2;
+    assert_eq!(scripted.output.len(), 2 * packet + 2);
+    assert_eq!(scripted.output[packet..packet + 3], [SOH, 2, 253]);
+}
+
+#[test]
+fn test_receiver_stats_count_timeouts() {
+    let packet = crc_packet(1, &[1; 128]);
+    let mut input: Vec<_> = packet[..50].iter().cloned().map(Some).collect();
+    input.push(None);
+    input.extend(packet.iter().cloned().map(Some));
+    input.extend(&[Some(EOT), Some(EOT)]);
+
+    let mut xmodem = Xmodem::builder()
+        .handshake_interval(std::time::Duration::from_millis(0))
+        .build(Scripted {
+            input,
+            output: vec![],
+        });
+    assert_eq!(xmodem.recv(&mut vec![]).expect("receive okay"), 128);
+
+    let stats = xmodem.stats();
+    assert_eq!(stats.packets, 1);
+    assert_eq!(stats.timeouts, 1);
+    assert_eq!(stats.retransmissions(), 1);
+}
@@ -0,0 +1,396 @@
+use core::fmt;
+#[cfg(feature = "std")]
+use std::collections::VecDeque;
+#[cfg(feature = "std")]
+use std::io;
+#[cfg(feature = "std")]
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "std")]
+use checksum;
+#[cfg(feature = "std")]
+use Checksum;
+#[cfg(feature = "std")]
+use {ACK, CAN, CRC, EOT, NAK, SOH, STREAMING, STX};
+
+/// The direction a traced byte travelled in.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Direction {
+    /// Received from the other side.
+    Rx,
+    /// Sent to the other side.
+    Tx,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Rx => write!(f, "<-"),
+            Direction::Tx => write!(f, "->"),
+        }
+    }
+}
+
+/// A protocol element decoded from the bytes on the wire.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Frame {
+    /// A packet header: `SOH` for a 128-byte or `STX` for a 1024-byte
+    /// payload, followed by the packet number and its 1s complement.
+    Header {
+        /// Payload size announced by the header byte.
+        size: usize,
+        /// The packet number.
+        number: u8,
+        /// The 1s complement of the packet number, as sent.
+        complement: u8,
+    },
+    /// The trailer of packet `number` arrived; `ok` is `true` if it matches
+    /// the payload.
+    Checksum {
+        /// Number of the packet the trailer belongs to.
+        number: u8,
+        /// Whether the checksum or CRC matches the payload.
+        ok: bool,
+    },
+    /// End of transmission.
+    Eot,
+    /// Positive acknowledgement.
+    Ack,
+    /// Negative acknowledgement, or a request for 8-bit checksum packets.
+    Nak,
+    /// Cancellation.
+    Can,
+    /// A request for CRC-16 packets.
+    Crc,
+    /// A request for streamed CRC-16 packets.
+    Streaming,
+    /// A byte outside of a packet that isn't a control byte.
+    Unexpected(u8),
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Frame::Header {
+                size,
+                number,
+                complement,
+            } => {
+                let header = if size == 1024 { "STX" } else { "SOH" };
+                write!(f, "{} {}/{}", header, number, complement)
+            }
+            Frame::Checksum { number, ok: true } => write!(f, "packet {} checksum ok", number),
+            Frame::Checksum { number, ok: false } => write!(f, "packet {} checksum bad", number),
+            Frame::Eot => write!(f, "EOT"),
+            Frame::Ack => write!(f, "ACK"),
+            Frame::Nak => write!(f, "NAK"),
+            Frame::Can => write!(f, "CAN"),
+            Frame::Crc => write!(f, "'C'"),
+            Frame::Streaming => write!(f, "'G'"),
+            Frame::Unexpected(byte) => write!(f, "unexpected {:#04x}", byte),
+        }
+    }
+}
+
+/// An observer of the bytes an [`Xmodem`](::Xmodem) session sends and
+/// receives.
+///
+/// Every byte is passed to [`Tracer::byte()`] in the order it was read or
+/// written. Whenever the bytes in one direction complete a protocol element,
+/// it is passed to [`Tracer::frame()`] right after the byte completing it.
+pub trait Tracer {
+    /// Called for every byte sent or received.
+    fn byte(&mut self, direction: Direction, byte: u8);
+
+    /// Called for every protocol element decoded. Does nothing by default.
+    fn frame(&mut self, direction: Direction, frame: Frame) {
+        let _ = (direction, frame);
+    }
+}
+
+/// Traces into a tracer shared with another thread, or kept by the caller to
+/// inspect once the session has ended.
+#[cfg(feature = "std")]
+impl<T: Tracer> Tracer for Arc<Mutex<T>> {
+    fn byte(&mut self, direction: Direction, byte: u8) {
+        if let Ok(mut tracer) = self.lock() {
+            tracer.byte(direction, byte);
+        }
+    }
+
+    fn frame(&mut self, direction: Direction, frame: Frame) {
+        if let Ok(mut tracer) = self.lock() {
+            tracer.frame(direction, frame);
+        }
+    }
+}
+
+/// An event recorded by a [`RingTracer`].
+#[cfg(feature = "std")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Record {
+    /// A byte was sent or received.
+    Byte(Direction, u8),
+    /// A protocol element was decoded.
+    Frame(Direction, Frame),
+}
+
+/// A tracer that keeps the most recent bytes and frames of a session.
+///
+/// Once `capacity` records are kept, every new record replaces the oldest one,
+/// so the end of a long session, where it usually failed, is always
+/// available.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::io;
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use std::sync::{Arc, Mutex};
+/// use xmodem::{FrameDumper, RingTracer, Xmodem};
+///
+/// let ring = Arc::new(Mutex::new(RingTracer::new(4096)));
+/// let mut xmodem = Xmodem::new(port);
+/// xmodem.set_tracer(ring.clone());
+/// if let Err(e) = xmodem.send(&b"hello"[..]) {
+///     ring.lock().unwrap().replay(&mut FrameDumper::new(io::stderr()));
+///     return Err(e);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct RingTracer {
+    records: VecDeque<Record>,
+    capacity: usize,
+}
+
+#[cfg(feature = "std")]
+impl RingTracer {
+    /// Returns a tracer that keeps the last `capacity` records.
+    pub fn new(capacity: usize) -> RingTracer {
+        RingTracer {
+            records: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the records kept, oldest first.
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.records.iter()
+    }
+
+    /// Returns the bytes kept that travelled in `direction`, oldest first.
+    pub fn bytes(&self, direction: Direction) -> Vec<u8> {
+        self.records
+            .iter()
+            .filter_map(|record| match *record {
+                Record::Byte(d, byte) if d == direction => Some(byte),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Passes the records kept, oldest first, to `tracer`.
+    pub fn replay<T: Tracer>(&self, tracer: &mut T) {
+        for record in &self.records {
+            match *record {
+                Record::Byte(direction, byte) => tracer.byte(direction, byte),
+                Record::Frame(direction, frame) => tracer.frame(direction, frame),
+            }
+        }
+    }
+
+    /// Forgets all records.
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    fn push(&mut self, record: Record) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.records.len() == self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(record);
+    }
+}
+
+#[cfg(feature = "std")]
+impl Tracer for RingTracer {
+    fn byte(&mut self, direction: Direction, byte: u8) {
+        self.push(Record::Byte(direction, byte));
+    }
+
+    fn frame(&mut self, direction: Direction, frame: Frame) {
+        self.push(Record::Frame(direction, frame));
+    }
+}
+
+/// A tracer that writes one human-readable line per decoded frame, such as
+/// `-> SOH 1/254` or `<- NAK`. Payload bytes aren't written.
+///
+/// Errors writing to the inner writer are ignored.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub struct FrameDumper<W> {
+    inner: W,
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> FrameDumper<W> {
+    /// Returns a dumper writing to `inner`.
+    pub fn new(inner: W) -> FrameDumper<W> {
+        FrameDumper { inner }
+    }
+
+    /// Returns the inner writer.
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+#[cfg(feature = "std")]
+impl<W: io::Write> Tracer for FrameDumper<W> {
+    fn byte(&mut self, _: Direction, _: u8) {}
+
+    fn frame(&mut self, direction: Direction, frame: Frame) {
+        let _ = writeln!(self.inner, "{} {}", direction, frame);
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Between packets.
+    Idle,
+    /// Waiting for the packet number of a packet with a `.0`-byte payload.
+    Number(usize),
+    /// Waiting for the complement of packet number `.1`.
+    Complement(usize, u8),
+    /// Receiving `.0` more payload bytes.
+    Data(usize),
+    /// Receiving `.0` more trailer bytes.
+    Trailer(usize),
+}
+
+/// Decodes the bytes travelling in one direction into frames.
+#[cfg(feature = "std")]
+#[derive(Debug)]
+pub(crate) struct Decoder {
+    state: State,
+    number: u8,
+    sum: u8,
+    crc: u16,
+    trailer: [u8; 2],
+}
+
+#[cfg(feature = "std")]
+impl Decoder {
+    pub(crate) fn new() -> Decoder {
+        Decoder {
+            state: State::Idle,
+            number: 0,
+            sum: 0,
+            crc: 0,
+            trailer: [0; 2],
+        }
+    }
+
+    /// Processes `byte`, using `mode` for packet trailers. Returns the frame
+    /// `byte` completes, if any.
+    pub(crate) fn decode(&mut self, byte: u8, mode: Checksum) -> Option<Frame> {
+        match self.state {
+            State::Idle => match byte {
+                SOH => self.state = State::Number(128),
+                STX => self.state = State::Number(1024),
+                EOT => return Some(Frame::Eot),
+                ACK => return Some(Frame::Ack),
+                NAK => return Some(Frame::Nak),
+                CAN => return Some(Frame::Can),
+                CRC => return Some(Frame::Crc),
+                STREAMING => return Some(Frame::Streaming),
+                byte => return Some(Frame::Unexpected(byte)),
+            },
+            State::Number(size) => self.state = State::Complement(size, byte),
+            State::Complement(size, number) => {
+                self.number = number;
+                self.sum = 0;
+                self.crc = 0;
+                self.state = State::Data(size);
+                return Some(Frame::Header {
+                    size,
+                    number,
+                    complement: byte,
+                });
+            }
+            State::Data(left) => {
+                self.sum = self.sum.wrapping_add(byte);
+                self.crc = checksum::crc16_update(self.crc, &[byte]);
+                self.state = match left {
+                    1 => State::Trailer(mode.size()),
+                    left => State::Data(left - 1),
+                };
+            }
+            State::Trailer(left) => {
+                self.trailer[mode.size() - left] = byte;
+                if left > 1 {
+                    self.state = State::Trailer(left - 1);
+                    return None;
+                }
+
+                self.state = State::Idle;
+                let ok = match mode {
+                    Checksum::Standard => self.trailer[0] == self.sum,
+                    Checksum::Crc16 => self.trailer == [(self.crc >> 8) as u8, self.crc as u8],
+                };
+
+                return Some(Frame::Checksum {
+                    number: self.number,
+                    ok,
+                });
+            }
+        }
+
+        None
+    }
+}
+
+/// A tracer attached to an [`Xmodem`](::Xmodem) session, with a decoder for
+/// each direction.
+#[cfg(feature = "std")]
+pub(crate) struct Tap {
+    tracer: Box<dyn Tracer + Send>,
+    rx: Decoder,
+    tx: Decoder,
+}
+
+#[cfg(feature = "std")]
+impl Tap {
+    pub(crate) fn new(tracer: Box<dyn Tracer + Send>) -> Tap {
+        Tap {
+            tracer,
+            rx: Decoder::new(),
+            tx: Decoder::new(),
+        }
+    }
+
+    /// Traces `bytes`, decoding packet trailers with `mode`.
+    pub(crate) fn record(&mut self, direction: Direction, bytes: &[u8], mode: Checksum) {
+        let decoder = match direction {
+            Direction::Rx => &mut self.rx,
+            Direction::Tx => &mut self.tx,
+        };
+
+        for &byte in bytes {
+            self.tracer.byte(direction, byte);
+            if let Some(frame) = decoder.decode(byte, mode) {
+                self.tracer.frame(direction, frame);
+            }
+        }
+    }
+}
@@ -0,0 +1,259 @@
+use std::cmp;
+use std::io;
+use std::str;
+
+use {BlockSize, Error, ProgressFn, Result, Status, Xmodem};
+
+/// Metadata sent ahead of every file in a YMODEM batch (block 0).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileInfo {
+    /// File name, without any directory components.
+    pub name: String,
+    /// Exact length of the file in bytes, if known. Receivers use it to strip
+    /// the padding of the last packet.
+    pub len: Option<u64>,
+    /// Modification time in seconds since the Unix epoch, if known.
+    pub mtime: Option<u64>,
+    /// Unix file mode, if known.
+    pub mode: Option<u32>,
+}
+
+impl FileInfo {
+    /// Returns metadata for a file named `name` that is `len` bytes long.
+    pub fn new<S: Into<String>>(name: S, len: u64) -> FileInfo {
+        FileInfo {
+            name: name.into(),
+            len: Some(len),
+            mtime: None,
+            mode: None,
+        }
+    }
+
+    /// Encodes the metadata into a block 0 payload: the file name and a NUL,
+    /// followed by the decimal length and the octal modification time and
+    /// mode separated by spaces, padded with NULs to `buf.len()`.
+    ///
+    /// Returns the number of bytes of `buf` that are used, 0 if `buf` is too
+    /// small.
+    pub(crate) fn encode(&self, buf: &mut [u8]) -> usize {
+        let mut fields = String::new();
+        if let Some(len) = self.len {
+            fields.push_str(&len.to_string());
+            if self.mtime.is_some() || self.mode.is_some() {
+                fields.push_str(&format!(" {:o}", self.mtime.unwrap_or(0)));
+            }
+
+            if let Some(mode) = self.mode {
+                fields.push_str(&format!(" {:o}", mode));
+            }
+        }
+
+        let name = self.name.as_bytes();
+        let used = name.len() + 1 + fields.len();
+        if used >= buf.len() {
+            return 0;
+        }
+
+        buf.iter_mut().for_each(|b| *b = 0);
+        buf[..name.len()].copy_from_slice(name);
+        buf[(name.len() + 1)..used].copy_from_slice(fields.as_bytes());
+        used
+    }
+
+    /// Decodes a block 0 payload. Returns `None` for the null file name that
+    /// ends a batch.
+    ///
+    /// Senders may send a path: only its last component, after any `/` or
+    /// `\`, is kept as the file name, so that a received name can't point
+    /// outside of the directory files are received in.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if the payload is malformed, or if the file
+    /// name is empty, `.` or `..` once its directories are stripped.
+    pub(crate) fn decode(buf: &[u8]) -> Result<Option<FileInfo>> {
+        fn invalid() -> Error {
+            Error::Protocol("invalid YMODEM header")
+        }
+
+        let mut parts = buf.splitn(3, |&b| b == 0);
+        let name = parts.next().unwrap_or(&[]);
+        if name.is_empty() {
+            return Ok(None);
+        }
+
+        let name = str::from_utf8(name).map_err(|_| invalid())?;
+        let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(Error::Protocol("invalid file name in YMODEM header"));
+        }
+
+        let fields = str::from_utf8(parts.next().unwrap_or(&[])).map_err(|_| invalid())?;
+        let mut fields = fields.split_whitespace();
+        let len = match fields.next() {
+            Some(len) => Some(len.parse().map_err(|_| invalid())?),
+            None => None,
+        };
+
+        let mtime = match fields.next() {
+            Some(mtime) => Some(u64::from_str_radix(mtime, 8).map_err(|_| invalid())?),
+            None => None,
+        };
+
+        let mode = match fields.next() {
+            Some(mode) => Some(u32::from_str_radix(mode, 8).map_err(|_| invalid())?),
+            None => None,
+        };
+
+        Ok(Some(FileInfo {
+            name: name.to_string(),
+            len,
+            mtime,
+            mode,
+        }))
+    }
+}
+
+/// Implementation of the YMODEM batch protocol on top of [`Xmodem`].
+///
+/// Every file is preceded by a header packet numbered 0 carrying its
+/// [`FileInfo`] and transferred as an XMODEM transmission of its own. A header
+/// with an empty file name ends the batch.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::Ymodem;
+///
+/// let mut ymodem = Ymodem::new(port);
+/// while let Some(info) = ymodem.recv_header()? {
+///     let file = File::create(&info.name)?;
+///     ymodem.recv_data(&info, file)?;
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct Ymodem<T> {
+    xmodem: Xmodem<T>,
+}
+
+impl<T: io::Read + io::Write> Ymodem<T> {
+    /// Returns a new `Ymodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving
+    /// (downloading) and sending (uploading). Files are sent in 1024-byte
+    /// packets.
+    pub fn new(inner: T) -> Self {
+        let mut xmodem = Xmodem::new(inner);
+        xmodem.set_block_size(BlockSize::OneK);
+        Ymodem { xmodem }
+    }
+
+    /// Returns a new `Ymodem` instance with the internal reader/writer set to
+    /// `inner`. The function `f` is used as a callback to indicate progress
+    /// throughout the transfer. See the [`Progress`](::Progress) enum for more
+    /// information.
+    pub fn new_with_progress(inner: T, f: ProgressFn) -> Self {
+        let mut xmodem = Xmodem::new_with_progress(inner, f);
+        xmodem.set_block_size(BlockSize::OneK);
+        Ymodem { xmodem }
+    }
+
+    /// Sets the progress closure. It is called with a [`Status`](::Status) for
+    /// every [`Progress`](::Progress) event; byte counts start over for every
+    /// file, and the total is the length in the file's header.
+    pub fn set_progress<F: FnMut(&Status) + Send + 'static>(&mut self, f: F) {
+        self.xmodem.set_progress(f);
+    }
+
+    /// Sets the packet size used to send file contents. The default is
+    /// `BlockSize::OneK`.
+    pub fn set_block_size(&mut self, block_size: BlockSize) {
+        self.xmodem.set_block_size(block_size);
+    }
+
+    /// Sends the header `info` followed by the contents of the file, read from
+    /// `data`. `info.len` should match the number of bytes `data` yields, as
+    /// the receiver truncates the file to that length.
+    ///
+    /// Returns the number of bytes sent, excluding padding.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if the file name is empty or if the
+    /// encoded header doesn't fit in a 1024-byte packet. Otherwise, returns
+    /// any error returned by [`Xmodem::send()`].
+    pub fn send_file<R: io::Read>(&mut self, info: &FileInfo, data: R) -> Result<u64> {
+        if info.name.is_empty() {
+            return Err(Error::InvalidInput("YMODEM file name must not be empty"));
+        }
+
+        let mut header = [0u8; 1024];
+        let size = match info.encode(&mut header[..128]) {
+            0 => 1024,
+            _ => 128,
+        };
+
+        if info.encode(&mut header[..size]) == 0 {
+            return Err(Error::InvalidInput("file name too long for YMODEM header"));
+        }
+
+        self.xmodem.restart(0);
+        self.xmodem.send_packet(&header[..size])?;
+        self.xmodem.restart(1);
+        self.xmodem.reset_progress(info.len);
+        self.xmodem.send(data).map(|n| n as u64)
+    }
+
+    /// Ends the batch by sending a header with an empty file name. This must
+    /// be called after the last file has been sent.
+    pub fn finish(&mut self) -> Result<()> {
+        self.xmodem.restart(0);
+        self.xmodem.send_packet(&[0; 128])
+    }
+
+    /// Receives the header of the next file in the batch. Returns `None` when
+    /// the sender has ended the batch. Otherwise, the file's contents must be
+    /// received with [`Ymodem::recv_data()`] before the next header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if the sender sends `EOT` instead of a header
+    /// or if the header is malformed. Otherwise, returns any error
+    /// returned by [`Xmodem::read_packet()`].
+    pub fn recv_header(&mut self) -> Result<Option<FileInfo>> {
+        let mut header = [0u8; 1024];
+        self.xmodem.restart(0);
+        match self.xmodem.recv_packet(&mut header)? {
+            0 => Err(Error::Protocol("expected YMODEM header")),
+            n => FileInfo::decode(&header[..n]),
+        }
+    }
+
+    /// Receives the contents of the file described by `info` and writes them
+    /// into `into`. If `info.len` is known, the padding after the last byte of
+    /// the file is discarded.
+    ///
+    /// Returns the number of bytes written to `into`.
+    pub fn recv_data<W: io::Write>(&mut self, info: &FileInfo, mut into: W) -> Result<u64> {
+        let mut packet = [0u8; 1024];
+        let mut written = 0;
+        self.xmodem.restart(1);
+        self.xmodem.reset_progress(info.len);
+        loop {
+            let n = match self.xmodem.recv_packet(&mut packet)? {
+                0 => return Ok(written),
+                n => n as u64,
+            };
+
+            let keep = match info.len {
+                Some(len) => cmp::min(n, len.saturating_sub(written)),
+                None => n,
+            };
+
+            into.write_all(&packet[..keep as usize])?;
+            written += keep;
+        }
+    }
+}
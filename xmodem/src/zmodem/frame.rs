@@ -0,0 +1,237 @@
+use checksum::{crc16, crc16_update, crc32_update};
+
+pub const ZPAD: u8 = b'*';
+pub const ZDLE: u8 = 0x18;
+pub const ZBIN: u8 = b'A';
+pub const ZHEX: u8 = b'B';
+pub const ZBIN32: u8 = b'C';
+
+pub const ZRQINIT: u8 = 0;
+pub const ZRINIT: u8 = 1;
+pub const ZSINIT: u8 = 2;
+pub const ZACK: u8 = 3;
+pub const ZFILE: u8 = 4;
+pub const ZSKIP: u8 = 5;
+pub const ZNAK: u8 = 6;
+pub const ZABORT: u8 = 7;
+pub const ZFIN: u8 = 8;
+pub const ZRPOS: u8 = 9;
+pub const ZDATA: u8 = 10;
+pub const ZEOF: u8 = 11;
+pub const ZFERR: u8 = 12;
+pub const ZCRC: u8 = 13;
+pub const ZCHALLENGE: u8 = 14;
+pub const ZCAN: u8 = 16;
+
+/// Data subpacket ends; a header follows.
+pub const ZCRCE: u8 = b'h';
+/// Data subpacket ends; the frame continues without acknowledgement.
+pub const ZCRCG: u8 = b'i';
+/// Data subpacket ends; the frame continues and a `ZACK` is expected.
+pub const ZCRCQ: u8 = b'j';
+/// Data subpacket ends; a `ZACK` is expected before the sender continues.
+pub const ZCRCW: u8 = b'k';
+/// Escaped `0x7F`.
+pub const ZRUB0: u8 = b'l';
+/// Escaped `0xFF`.
+pub const ZRUB1: u8 = b'm';
+
+/// `ZRINIT` flag: the receiver can send and receive at the same time.
+pub const CANFDX: u8 = 0x01;
+/// `ZRINIT` flag: the receiver can receive data while writing to disk.
+pub const CANOVIO: u8 = 0x02;
+/// `ZRINIT` flag: the receiver can check 32-bit CRCs.
+pub const CANFC32: u8 = 0x20;
+/// `ZRINIT`/`ZSINIT` flag: all control characters must be escaped.
+pub const ESCCTL: u8 = 0x40;
+
+/// `ZFILE` conversion option: binary transfer.
+pub const ZCBIN: u8 = 1;
+/// `ZFILE` conversion option: resume an interrupted transfer.
+pub const ZCRESUM: u8 = 3;
+
+const DLE: u8 = 0x10;
+pub const XON: u8 = 0x11;
+pub const XOFF: u8 = 0x13;
+
+/// A ZMODEM frame header: a frame type and four bytes that hold either flags
+/// or a little-endian file position.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Header {
+    pub kind: u8,
+    pub data: [u8; 4],
+}
+
+impl Header {
+    /// Returns a header of type `kind` carrying the file position `position`.
+    pub fn with_position(kind: u8, position: u32) -> Header {
+        Header {
+            kind,
+            data: position.to_le_bytes(),
+        }
+    }
+
+    /// Returns a header of type `kind` carrying the flag `zf0`.
+    pub fn with_flags(kind: u8, zf0: u8) -> Header {
+        Header {
+            kind,
+            data: [0, 0, 0, zf0],
+        }
+    }
+
+    /// Returns the file position carried by this header.
+    pub fn position(&self) -> u32 {
+        u32::from_le_bytes(self.data)
+    }
+
+    /// Returns the first (least significant) flag byte, `ZF0`.
+    pub fn zf0(&self) -> u8 {
+        self.data[3]
+    }
+
+    fn bytes(&self) -> [u8; 5] {
+        [
+            self.kind,
+            self.data[0],
+            self.data[1],
+            self.data[2],
+            self.data[3],
+        ]
+    }
+}
+
+/// The CRC protecting binary headers and data subpackets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Encoding {
+    /// `ZBIN` headers and 16-bit CRCs.
+    Crc16,
+    /// `ZBIN32` headers and 32-bit CRCs.
+    Crc32,
+}
+
+impl Encoding {
+    /// Returns the number of CRC bytes trailing a header or subpacket.
+    pub fn crc_size(&self) -> usize {
+        match *self {
+            Encoding::Crc16 => 2,
+            Encoding::Crc32 => 4,
+        }
+    }
+
+    /// Computes the CRC of `data` followed by `end` into `out`, in the byte
+    /// order it is transmitted in. Returns the number of bytes used.
+    pub fn compute(&self, data: &[u8], end: &[u8], out: &mut [u8; 4]) -> usize {
+        match *self {
+            Encoding::Crc16 => {
+                let crc = crc16_update(crc16(data), end);
+                out[..2].copy_from_slice(&crc.to_be_bytes());
+            }
+            Encoding::Crc32 => {
+                let crc = !crc32_update(crc32_update(!0, data), end);
+                out.copy_from_slice(&crc.to_le_bytes());
+            }
+        }
+
+        self.crc_size()
+    }
+}
+
+/// ZDLE-escapes bytes of binary headers and data subpackets.
+#[derive(Debug, Default)]
+pub struct Escaper {
+    /// Escape every control character, as requested with `ESCCTL`.
+    pub escape_ctl: bool,
+    last: u8,
+}
+
+impl Escaper {
+    /// Appends `byte` to `out`, escaping it if it could be mistaken for flow
+    /// control or ZMODEM framing. A carriage return is escaped after `@` so
+    /// that `@<CR>` never reaches a Telenet command processor.
+    pub fn push(&mut self, out: &mut Vec<u8>, byte: u8) {
+        let escape = match byte {
+            ZDLE | DLE | XON | XOFF | 0x90 | 0x91 | 0x93 => true,
+            b'\r' | 0x8D => self.escape_ctl || self.last & 0x7F == b'@',
+            b => self.escape_ctl && b & 0x60 == 0,
+        };
+
+        self.last = if escape {
+            out.push(ZDLE);
+            byte ^ 0x40
+        } else {
+            byte
+        };
+
+        out.push(self.last);
+    }
+
+    /// Appends every byte of `data` to `out`, escaping as needed.
+    pub fn extend(&mut self, out: &mut Vec<u8>, data: &[u8]) {
+        data.iter().for_each(|&b| self.push(out, b));
+    }
+}
+
+/// Appends `header` encoded as a hex header to `out`. Hex headers contain only
+/// printable characters and are used for frames that carry no data.
+pub fn encode_hex_header(header: &Header, out: &mut Vec<u8>) {
+    const DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+    let bytes = header.bytes();
+    let crc = crc16(&bytes);
+    out.extend_from_slice(&[ZPAD, ZPAD, ZDLE, ZHEX]);
+    for &b in bytes.iter().chain(crc.to_be_bytes().iter()) {
+        out.push(DIGITS[(b >> 4) as usize]);
+        out.push(DIGITS[(b & 0xF) as usize]);
+    }
+
+    out.extend_from_slice(&[b'\r', b'\n' | 0x80]);
+    if header.kind != ZFIN && header.kind != ZACK {
+        out.push(XON);
+    }
+}
+
+/// Appends `header` encoded as a binary header to `out`.
+pub fn encode_bin_header(
+    header: &Header,
+    encoding: Encoding,
+    escaper: &mut Escaper,
+    out: &mut Vec<u8>,
+) {
+    let bytes = header.bytes();
+    let mut crc = [0u8; 4];
+    let n = encoding.compute(&bytes, &[], &mut crc);
+    let format = match encoding {
+        Encoding::Crc16 => ZBIN,
+        Encoding::Crc32 => ZBIN32,
+    };
+
+    out.extend_from_slice(&[ZPAD, ZDLE, format]);
+    escaper.extend(out, &bytes);
+    escaper.extend(out, &crc[..n]);
+}
+
+/// Appends a data subpacket carrying `data` and ending with `end` (one of
+/// `ZCRCE`, `ZCRCG`, `ZCRCQ` or `ZCRCW`) to `out`.
+pub fn encode_subpacket(
+    data: &[u8],
+    end: u8,
+    encoding: Encoding,
+    escaper: &mut Escaper,
+    out: &mut Vec<u8>,
+) {
+    let mut crc = [0u8; 4];
+    let n = encoding.compute(data, &[end], &mut crc);
+    escaper.extend(out, data);
+    out.extend_from_slice(&[ZDLE, end]);
+    escaper.extend(out, &crc[..n]);
+}
+
+/// Returns the value of the hex digit `c`, ignoring parity.
+pub fn hex_value(c: u8) -> Option<u8> {
+    match c & 0x7F {
+        c @ b'0'..=b'9' => Some(c - b'0'),
+        c @ b'a'..=b'f' => Some(c - b'a' + 10),
+        c @ b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
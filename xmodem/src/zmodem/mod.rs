@@ -0,0 +1,719 @@
+use std::io;
+use std::io::SeekFrom;
+
+use checksum::{crc16, crc32_update};
+use read_ext::ReadExt;
+use ymodem::FileInfo;
+use {Error, Result};
+
+mod frame;
+#[cfg(test)]
+mod tests;
+
+use self::frame::*;
+
+/// Number of payload bytes in each data subpacket sent.
+const SUBPACKET_SIZE: usize = 1024;
+
+/// Largest data subpacket accepted from a sender.
+const MAX_SUBPACKET_SIZE: usize = 8192;
+
+/// Number of bytes sent before waiting for an acknowledgement when the
+/// receiver can stream. Blocking I/O can't watch for the receiver's `ZRPOS`
+/// while sending, so this bounds the data sent after an error.
+const STREAM_WINDOW: usize = 32 * 1024;
+
+/// Number of bytes skipped while looking for a header before giving up. A
+/// receiver that saw an error skips the rest of the sender's window.
+const MAX_GARBAGE: usize = 2 * STREAM_WINDOW + 1024;
+
+/// Number of consecutive timeouts or corrupted frames tolerated.
+const MAX_RETRIES: usize = 10;
+
+/// Flags advertised in the receiver's `ZRINIT`.
+const RECEIVER_FLAGS: u8 = CANFDX | CANOVIO | CANFC32;
+
+/// A byte or a frame end read from a ZDLE-escaped stream.
+enum Escaped {
+    Byte(u8),
+    End(u8),
+}
+
+/// Outcome of sending a `ZDATA` frame.
+enum Sent {
+    /// The file was sent up to its end, at the given position.
+    End(u32),
+    /// The receiver asked for the data from the given position to be resent.
+    Restart(u32),
+}
+
+/// Implementation of the ZMODEM protocol.
+///
+/// Unlike XMODEM, ZMODEM streams file contents without waiting for an
+/// acknowledgement of each packet. Data is sent in subpackets protected by a
+/// 32-bit CRC (or a 16-bit one for receivers that don't support CRC-32). When
+/// the receiver detects corruption, it asks the sender to resume from the last
+/// good file position, which is also how interrupted transfers are resumed.
+///
+/// File headers use the same [`FileInfo`] as YMODEM.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use std::{fs::File, io};
+/// # fn f<T: io::Read + io::Write>(port: T) -> xmodem::Result<()> {
+/// use xmodem::{FileInfo, Zmodem};
+///
+/// let file = File::open("kernel8.img")?;
+/// let info = FileInfo::new("kernel8.img", file.metadata()?.len());
+///
+/// let mut zmodem = Zmodem::new(port);
+/// zmodem.send_file(&info, file)?;
+/// zmodem.finish()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Zmodem<T> {
+    inner: T,
+    /// The sender has received `ZRINIT`.
+    started: bool,
+    /// The receiver must (re)send `ZRINIT` before waiting for a header.
+    send_zrinit: bool,
+    /// Request that the receiver resumes interrupted transfers.
+    resume: bool,
+    /// Encoding of outgoing binary headers and subpackets.
+    encoding: Encoding,
+    /// Encoding of the last binary header received; subpackets that follow
+    /// it use the same CRC.
+    rx_encoding: Encoding,
+    /// Number of bytes sent before waiting for the receiver's `ZACK`.
+    window: usize,
+    escaper: Escaper,
+    packet: Vec<u8>,
+    out: Vec<u8>,
+}
+
+impl<T: io::Read + io::Write> Zmodem<T> {
+    /// Returns a new `Zmodem` instance with the internal reader/writer set to
+    /// `inner`. The returned instance can be used for both receiving
+    /// (downloading) and sending (uploading).
+    pub fn new(inner: T) -> Self {
+        Zmodem {
+            inner,
+            started: false,
+            send_zrinit: true,
+            resume: false,
+            encoding: Encoding::Crc16,
+            rx_encoding: Encoding::Crc16,
+            window: STREAM_WINDOW,
+            escaper: Escaper::default(),
+            packet: Vec::with_capacity(MAX_SUBPACKET_SIZE),
+            out: Vec::with_capacity(2 * SUBPACKET_SIZE + 16),
+        }
+    }
+
+    /// Sets whether the receiver is asked to resume an interrupted transfer of
+    /// the same file (`ZCRESUM`). The receiver then requests the data from
+    /// the length of its partial copy onwards. The default is `false`.
+    pub fn set_resume(&mut self, resume: bool) {
+        self.resume = resume;
+    }
+
+    /// Sends the header `info` followed by the contents of the file, read from
+    /// `data`. `data` is seeked to the position the receiver requests, which
+    /// is non-zero when the receiver resumes an interrupted transfer, and
+    /// whenever the receiver asks for corrupted data to be resent.
+    ///
+    /// Returns the length of the file as acknowledged by the receiver, or 0 if
+    /// the receiver skipped the file.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if the file name is empty, too long, or if
+    /// the file is larger than 4 GiB. `Error::Cancelled` is returned if the
+    /// receiver cancels the session and `Error::RetriesExhausted` if it
+    /// repeatedly doesn't respond. Otherwise, returns any error from reading
+    /// `data` or reading and writing the inner stream.
+    pub fn send_file<R>(&mut self, info: &FileInfo, mut data: R) -> Result<u64>
+    where
+        R: io::Read + io::Seek,
+    {
+        let mut header = [0u8; 1024];
+        let used = match info.encode(&mut header) {
+            _ if info.name.is_empty() => 0,
+            used => used,
+        };
+
+        if used == 0 {
+            return Err(Error::InvalidInput("invalid file name for ZMODEM header"));
+        }
+
+        if !self.started {
+            self.start_send()?;
+        }
+
+        let zf0 = if self.resume { ZCRESUM } else { ZCBIN };
+        let mut offset = None;
+        for _ in 0..MAX_RETRIES {
+            self.send_bin_header(Header::with_flags(ZFILE, zf0))?;
+            self.send_subpacket(&header[..(used + 1)], ZCRCW)?;
+            // The receiver may have answered our ZRQINIT after we saw its
+            // first ZRINIT; that ZRINIT crosses the ZFILE and is ignored once.
+            let mut crossed = true;
+            offset = loop {
+                match self.read_header() {
+                    Ok(h) => match h.kind {
+                        ZRPOS => break Some(h.position()),
+                        ZSKIP => return Ok(0),
+                        ZRINIT if crossed => crossed = false,
+                        ZRINIT | ZNAK => break None,
+                        ZCRC => {
+                            let crc = file_crc(&mut data)?;
+                            self.send_hex_header(Header::with_position(ZCRC, crc))?;
+                        }
+                        ZABORT | ZFERR | ZCAN => return Err(Error::Cancelled),
+                        _ => continue,
+                    },
+                    Err(ref e) if is_retryable(e) => break None,
+                    Err(e) => return Err(e),
+                }
+            };
+
+            if offset.is_some() {
+                break;
+            }
+        }
+
+        let mut offset = offset.ok_or(Error::RetriesExhausted)?;
+        let mut retries = 0;
+        'resend: while retries < MAX_RETRIES {
+            let end = match self.send_data(&mut data, offset)? {
+                Sent::End(end) => end,
+                Sent::Restart(position) => {
+                    retries = if position > offset { 0 } else { retries + 1 };
+                    offset = position;
+                    continue;
+                }
+            };
+
+            self.send_bin_header(Header::with_position(ZEOF, end))?;
+            while retries < MAX_RETRIES {
+                match self.read_header() {
+                    Ok(h) => match h.kind {
+                        ZRINIT | ZSKIP => return Ok(end as u64),
+                        ZRPOS => {
+                            retries = if h.position() > offset {
+                                0
+                            } else {
+                                retries + 1
+                            };
+                            offset = h.position();
+                            continue 'resend;
+                        }
+                        ZABORT | ZFERR | ZCAN => return Err(Error::Cancelled),
+                        _ => continue,
+                    },
+                    Err(ref e) if is_retryable(e) => {
+                        retries += 1;
+                        self.send_bin_header(Header::with_position(ZEOF, end))?;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Ends the session. This must be called after the last file has been
+    /// sent.
+    pub fn finish(&mut self) -> Result<()> {
+        if !self.started {
+            self.start_send()?;
+        }
+
+        for _ in 0..MAX_RETRIES {
+            self.send_hex_header(Header::with_position(ZFIN, 0))?;
+            match self.read_header() {
+                Ok(ref h) if h.kind == ZFIN => return self.write_all(b"OO"),
+                Ok(ref h) if h.kind == ZCAN || h.kind == ZABORT => return Err(Error::Cancelled),
+                Ok(_) => continue,
+                Err(ref e) if is_retryable(e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Receives the header of the next file. Returns `None` when the sender
+    /// has ended the session. Otherwise, the file must be received with
+    /// [`Zmodem::recv_data()`] or skipped with [`Zmodem::skip_file()`] before
+    /// the next header.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Protocol` if the header is malformed, `Error::Cancelled`
+    /// if the sender cancels the session and `Error::RetriesExhausted` if it
+    /// repeatedly doesn't respond. Otherwise, returns any error from reading
+    /// or writing the inner stream.
+    pub fn recv_header(&mut self) -> Result<Option<FileInfo>> {
+        let mut retries = 0;
+        while retries < MAX_RETRIES {
+            if self.send_zrinit {
+                let flags = [0, 0, 0, RECEIVER_FLAGS];
+                self.send_hex_header(Header {
+                    kind: ZRINIT,
+                    data: flags,
+                })?;
+                self.send_zrinit = false;
+            }
+
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ref e) if is_retryable(e) => {
+                    retries += 1;
+                    self.send_zrinit = true;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match header.kind {
+                ZFILE => match self.read_subpacket() {
+                    Ok(_) => {
+                        return FileInfo::decode(&self.packet)?
+                            .map(Some)
+                            .ok_or(Error::Protocol("empty ZMODEM file name"));
+                    }
+                    Err(ref e) if is_retryable(e) => {
+                        retries += 1;
+                        self.send_hex_header(Header::with_position(ZNAK, 0))?;
+                    }
+                    Err(e) => return Err(e),
+                },
+                ZSINIT => match self.read_subpacket() {
+                    Ok(_) => {
+                        self.escaper.escape_ctl |= header.zf0() & ESCCTL != 0;
+                        self.send_hex_header(Header::with_position(ZACK, 0))?;
+                    }
+                    Err(ref e) if is_retryable(e) => {
+                        retries += 1;
+                        self.send_hex_header(Header::with_position(ZNAK, 0))?;
+                    }
+                    Err(e) => return Err(e),
+                },
+                ZFIN => {
+                    self.send_hex_header(Header::with_position(ZFIN, 0))?;
+                    // The sender's "OO" is a courtesy; it may never arrive.
+                    let mut over_and_out = [0u8; 2];
+                    let _ = self.inner.read_max(&mut over_and_out);
+                    return Ok(None);
+                }
+                ZCAN | ZABORT => return Err(Error::Cancelled),
+                _ => self.send_zrinit = true,
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Receives the contents of the file described by the last header
+    /// returned from [`Zmodem::recv_header()`] and writes them into `into`.
+    ///
+    /// The sender is asked to start at byte `offset` of the file. To resume an
+    /// interrupted transfer, pass the length of the partial copy and append
+    /// to it. Otherwise, pass 0.
+    ///
+    /// Returns the number of bytes written to `into`.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidInput` if `offset` doesn't fit in 32 bits,
+    /// `Error::Cancelled` if the sender cancels the session and
+    /// `Error::RetriesExhausted` if it repeatedly doesn't respond or sends
+    /// corrupted data. Otherwise, returns any error from writing to `into` or
+    /// reading and writing the inner stream.
+    pub fn recv_data<W: io::Write>(&mut self, offset: u64, mut into: W) -> Result<u64> {
+        let start = position(offset)?;
+        let mut position = start;
+        let mut retries = 0;
+        self.send_hex_header(Header::with_position(ZRPOS, position))?;
+        while retries < MAX_RETRIES {
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ref e) if is_retryable(e) => {
+                    retries += 1;
+                    self.send_hex_header(Header::with_position(ZRPOS, position))?;
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            match header.kind {
+                ZDATA if header.position() == position => loop {
+                    let end = match self.read_subpacket() {
+                        Ok(end) => end,
+                        Err(ref e) if is_retryable(e) => {
+                            retries += 1;
+                            self.send_hex_header(Header::with_position(ZRPOS, position))?;
+                            break;
+                        }
+                        Err(e) => return Err(e),
+                    };
+
+                    into.write_all(&self.packet)?;
+                    position = position.wrapping_add(self.packet.len() as u32);
+                    retries = 0;
+                    if end == ZCRCQ || end == ZCRCW {
+                        self.send_hex_header(Header::with_position(ZACK, position))?;
+                    }
+
+                    if end == ZCRCE || end == ZCRCW {
+                        break;
+                    }
+                },
+                ZDATA => {
+                    retries += 1;
+                    self.send_hex_header(Header::with_position(ZRPOS, position))?;
+                }
+                ZEOF if header.position() == position => {
+                    self.send_zrinit = true;
+                    return Ok((position - start) as u64);
+                }
+                ZFILE | ZSINIT => {
+                    // A duplicate sent before our ZRPOS arrived.
+                    let _ = self.read_subpacket();
+                }
+                ZCAN | ZABORT => return Err(Error::Cancelled),
+                _ => continue,
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Skips the file described by the last header returned from
+    /// [`Zmodem::recv_header()`]. The sender moves on to its next file.
+    pub fn skip_file(&mut self) -> Result<()> {
+        self.send_hex_header(Header::with_position(ZSKIP, 0))
+    }
+
+    /// Starts a session as a sender: announces the session with `ZRQINIT` and
+    /// waits for the receiver's `ZRINIT`, which determines the CRC, escaping
+    /// and window size used for the rest of the session.
+    fn start_send(&mut self) -> Result<()> {
+        // Starts `rz` if the other side is a shell.
+        self.write_all(b"rz\r")?;
+        for _ in 0..MAX_RETRIES {
+            self.send_hex_header(Header::with_position(ZRQINIT, 0))?;
+            let header = match self.read_header() {
+                Ok(header) => header,
+                Err(ref e) if is_retryable(e) => continue,
+                Err(e) => return Err(e),
+            };
+
+            match header.kind {
+                ZRINIT => {
+                    let flags = header.zf0();
+                    if flags & CANFC32 != 0 {
+                        self.encoding = Encoding::Crc32;
+                    }
+
+                    self.escaper.escape_ctl |= flags & ESCCTL != 0;
+                    let buffer = header.data[0] as usize | (header.data[1] as usize) << 8;
+                    if buffer != 0 && buffer < self.window {
+                        self.window = buffer;
+                    }
+
+                    self.started = true;
+                    return Ok(());
+                }
+                ZCHALLENGE => {
+                    let response = Header {
+                        kind: ZACK,
+                        data: header.data,
+                    };
+
+                    self.send_hex_header(response)?;
+                }
+                ZCAN | ZABORT => return Err(Error::Cancelled),
+                _ => continue,
+            }
+        }
+
+        Err(Error::RetriesExhausted)
+    }
+
+    /// Sends the contents of `data` from `offset` to its end in a `ZDATA`
+    /// frame, waiting for an acknowledgement after every window.
+    fn send_data<R>(&mut self, data: &mut R, offset: u32) -> Result<Sent>
+    where
+        R: io::Read + io::Seek,
+    {
+        data.seek(SeekFrom::Start(offset as u64))?;
+        self.send_bin_header(Header::with_position(ZDATA, offset))?;
+
+        let mut buf = [0u8; SUBPACKET_SIZE];
+        let (mut position, mut acknowledged) = (offset, offset);
+        loop {
+            let n = data.read_max(&mut buf)?;
+            let next = (position as u64) + (n as u64);
+            if next > u32::MAX as u64 {
+                return Err(Error::InvalidInput("file too large for ZMODEM"));
+            }
+
+            let end = if n < SUBPACKET_SIZE {
+                ZCRCE
+            } else if (next - acknowledged as u64) as usize >= self.window {
+                ZCRCW
+            } else {
+                ZCRCG
+            };
+
+            self.send_subpacket(&buf[..n], end)?;
+            position = next as u32;
+            if end == ZCRCE {
+                return Ok(Sent::End(position));
+            } else if end == ZCRCW {
+                loop {
+                    match self.read_header() {
+                        Ok(ref h) if h.kind == ZACK => break,
+                        Ok(ref h) if h.kind == ZRPOS => return Ok(Sent::Restart(h.position())),
+                        Ok(ref h) if h.kind == ZCAN || h.kind == ZABORT => {
+                            return Err(Error::Cancelled)
+                        }
+                        Ok(_) => continue,
+                        Err(ref e) if is_retryable(e) => return Ok(Sent::Restart(acknowledged)),
+                        Err(e) => return Err(e),
+                    }
+                }
+
+                // The receiver waits for a new header after ZCRCW.
+                acknowledged = position;
+                self.send_bin_header(Header::with_position(ZDATA, position))?;
+            }
+        }
+    }
+
+    /// Reads the next header, skipping anything that precedes it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Checksum` if the header is corrupted, `Error::Protocol`
+    /// if no header is found within `MAX_GARBAGE` bytes and `Error::Cancelled`
+    /// if five `CAN` bytes are received in a row.
+    fn read_header(&mut self) -> Result<Header> {
+        let mut cancels = 0;
+        for _ in 0..MAX_GARBAGE {
+            let mut byte = self.read_byte()?;
+            cancels = if byte == ZDLE { cancels + 1 } else { 0 };
+            if cancels >= 5 {
+                return Err(Error::Cancelled);
+            }
+
+            if byte & 0x7F != ZPAD {
+                continue;
+            }
+
+            while byte & 0x7F == ZPAD {
+                byte = self.read_byte()?;
+            }
+
+            if byte != ZDLE {
+                continue;
+            }
+
+            match self.read_byte()? & 0x7F {
+                ZBIN => return self.read_bin_header(Encoding::Crc16),
+                ZBIN32 => return self.read_bin_header(Encoding::Crc32),
+                ZHEX => return self.read_hex_header(),
+                _ => continue,
+            }
+        }
+
+        Err(Error::Protocol("no ZMODEM header found"))
+    }
+
+    fn read_bin_header(&mut self, encoding: Encoding) -> Result<Header> {
+        let mut bytes = [0u8; 5];
+        for byte in bytes.iter_mut() {
+            *byte = self.read_escaped_byte()?;
+        }
+
+        let (mut expected, mut actual) = ([0u8; 4], [0u8; 4]);
+        let n = encoding.compute(&bytes, &[], &mut expected);
+        for byte in actual[..n].iter_mut() {
+            *byte = self.read_escaped_byte()?;
+        }
+
+        if expected != actual {
+            return Err(Error::Checksum);
+        }
+
+        self.rx_encoding = encoding;
+        Ok(Header {
+            kind: bytes[0],
+            data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+        })
+    }
+
+    fn read_hex_header(&mut self) -> Result<Header> {
+        let mut bytes = [0u8; 7];
+        for byte in bytes.iter_mut() {
+            let high = hex_value(self.read_byte()?);
+            let low = hex_value(self.read_byte()?);
+            *byte = match (high, low) {
+                (Some(high), Some(low)) => high << 4 | low,
+                _ => return Err(Error::Checksum),
+            };
+        }
+
+        if crc16(&bytes[..5]).to_be_bytes() != bytes[5..] {
+            return Err(Error::Checksum);
+        }
+
+        // Consume the CR LF that ends the header. The XON after it, if any, is
+        // skipped like any other flow control character.
+        if self.read_byte()? & 0x7F == b'\r' {
+            self.read_byte()?;
+        }
+
+        Ok(Header {
+            kind: bytes[0],
+            data: [bytes[1], bytes[2], bytes[3], bytes[4]],
+        })
+    }
+
+    /// Reads a data subpacket into `self.packet` and returns the frame end
+    /// that terminated it.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Checksum` if the subpacket is corrupted or too long.
+    fn read_subpacket(&mut self) -> Result<u8> {
+        self.packet.clear();
+        let end = loop {
+            match self.read_escaped()? {
+                Escaped::Byte(_) if self.packet.len() >= MAX_SUBPACKET_SIZE => {
+                    return Err(Error::Checksum);
+                }
+                Escaped::Byte(byte) => self.packet.push(byte),
+                Escaped::End(end) => break end,
+            }
+        };
+
+        let encoding = self.rx_encoding;
+        let (mut expected, mut actual) = ([0u8; 4], [0u8; 4]);
+        let n = encoding.compute(&self.packet, &[end], &mut expected);
+        for byte in actual[..n].iter_mut() {
+            *byte = self.read_escaped_byte()?;
+        }
+
+        if expected != actual {
+            return Err(Error::Checksum);
+        }
+
+        Ok(end)
+    }
+
+    /// Reads a ZDLE-escaped byte, treating a frame end as corruption.
+    fn read_escaped_byte(&mut self) -> Result<u8> {
+        match self.read_escaped()? {
+            Escaped::Byte(byte) => Ok(byte),
+            Escaped::End(_) => Err(Error::Checksum),
+        }
+    }
+
+    /// Reads a ZDLE-escaped byte or frame end, skipping flow control
+    /// characters.
+    fn read_escaped(&mut self) -> Result<Escaped> {
+        loop {
+            match self.read_byte()? {
+                XON | XOFF | 0x91 | 0x93 => continue,
+                ZDLE => break,
+                byte => return Ok(Escaped::Byte(byte)),
+            }
+        }
+
+        let mut cancels = 1;
+        loop {
+            match self.read_byte()? {
+                ZDLE => {
+                    cancels += 1;
+                    if cancels >= 5 {
+                        return Err(Error::Cancelled);
+                    }
+                }
+                XON | XOFF | 0x91 | 0x93 => continue,
+                end @ ZCRCE..=ZCRCW => return Ok(Escaped::End(end)),
+                ZRUB0 => return Ok(Escaped::Byte(0x7F)),
+                ZRUB1 => return Ok(Escaped::Byte(0xFF)),
+                byte if byte & 0x60 == 0x40 => return Ok(Escaped::Byte(byte ^ 0x40)),
+                _ => return Err(Error::Checksum),
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        let mut buf = [0u8; 1];
+        self.inner.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn send_hex_header(&mut self, header: Header) -> Result<()> {
+        self.out.clear();
+        encode_hex_header(&header, &mut self.out);
+        self.flush_out()
+    }
+
+    fn send_bin_header(&mut self, header: Header) -> Result<()> {
+        self.out.clear();
+        encode_bin_header(&header, self.encoding, &mut self.escaper, &mut self.out);
+        self.flush_out()
+    }
+
+    fn send_subpacket(&mut self, data: &[u8], end: u8) -> Result<()> {
+        self.out.clear();
+        encode_subpacket(data, end, self.encoding, &mut self.escaper, &mut self.out);
+        self.flush_out()
+    }
+
+    fn flush_out(&mut self) -> Result<()> {
+        self.inner.write_all(&self.out)?;
+        Ok(self.inner.flush()?)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.inner.write_all(buf)?;
+        Ok(self.inner.flush()?)
+    }
+}
+
+/// Returns the CRC-32 of all of `data`, as requested by a receiver with `ZCRC`.
+fn file_crc<R: io::Read + io::Seek>(data: &mut R) -> Result<u32> {
+    let mut buf = [0u8; SUBPACKET_SIZE];
+    let mut crc = !0;
+    data.seek(SeekFrom::Start(0))?;
+    loop {
+        match data.read_max(&mut buf)? {
+            0 => return Ok(!crc),
+            n => crc = crc32_update(crc, &buf[..n]),
+        }
+    }
+}
+
+/// Converts a file offset to a ZMODEM position.
+fn position(offset: u64) -> Result<u32> {
+    if offset > u32::MAX as u64 {
+        return Err(Error::InvalidInput("offset too large for ZMODEM"));
+    }
+
+    Ok(offset as u32)
+}
+
+/// Returns `true` if `e` indicates a timeout or a corrupted frame, after which
+/// the frame can be requested again.
+fn is_retryable(e: &Error) -> bool {
+    matches!(*e, Error::Timeout | Error::Checksum)
+}
@@ -0,0 +1,410 @@
+use super::*;
+use std::io::Cursor;
+use tests::{pipe, Pipe};
+
+/// A pipe that flips the low bit of the bytes written at the given offsets.
+struct Noisy {
+    pipe: Pipe,
+    written: usize,
+    corrupt: Vec<usize>,
+}
+
+impl io::Read for Noisy {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.pipe.read(buf)
+    }
+}
+
+impl io::Write for Noisy {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            let byte = if self.corrupt.contains(&self.written) {
+                byte ^ 1
+            } else {
+                byte
+            };
+
+            self.written += 1;
+            self.pipe.write_all(&[byte])?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn tricky_data(len: usize) -> Vec<u8> {
+    let pattern = [
+        ZDLE, ZPAD, 0x10, XON, XOFF, 0x7F, 0xFF, b'@', b'\r', 0x8D, 0x91,
+    ];
+    (0..len)
+        .map(|i| match i % 3 {
+            0 => pattern[i % pattern.len()],
+            _ => (i % 251) as u8,
+        })
+        .collect()
+}
+
+/// Sends `files` from one thread and receives them on another, resuming each
+/// file at the offset returned by `offset`. Returns what the receiver got.
+fn transfer<W>(
+    files: Vec<(FileInfo, Vec<u8>)>,
+    sender: W,
+    receiver: Pipe,
+    offset: fn(&FileInfo) -> Option<u64>,
+) -> Vec<(FileInfo, Vec<u8>)>
+where
+    W: io::Read + io::Write + Send + 'static,
+{
+    let tx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(sender);
+        for (info, data) in files.iter() {
+            let n = zmodem
+                .send_file(info, Cursor::new(&data[..]))
+                .expect("send file");
+            if offset(info).is_some() {
+                assert_eq!(n, data.len() as u64);
+            } else {
+                assert_eq!(n, 0);
+            }
+        }
+
+        zmodem.finish().expect("finish session");
+    });
+
+    let rx_thread = std::thread::spawn(move || {
+        let mut zmodem = Zmodem::new(receiver);
+        let mut received = vec![];
+        while let Some(info) = zmodem.recv_header().expect("header") {
+            match offset(&info) {
+                Some(offset) => {
+                    let mut data = vec![];
+                    let n = zmodem.recv_data(offset, &mut data).expect("data");
+                    assert_eq!(n, data.len() as u64);
+                    received.push((info, data));
+                }
+                None => zmodem.skip_file().expect("skip"),
+            }
+        }
+
+        received
+    });
+
+    tx_thread.join().expect("tx join okay");
+    rx_thread.join().expect("rx join okay")
+}
+
+#[test]
+fn test_hex_header() {
+    let mut out = vec![];
+    let zrinit = Header {
+        kind: ZRINIT,
+        data: [0, 0, 0, CANFDX | CANOVIO | CANFC32],
+    };
+
+    encode_hex_header(&zrinit, &mut out);
+    assert_eq!(&out[..], &b"**\x18B0100000023be50\r\x8a\x11"[..]);
+
+    let mut zmodem = Zmodem::new(Cursor::new(out));
+    assert_eq!(zmodem.read_header().expect("header"), zrinit);
+
+    let mut out = vec![];
+    encode_hex_header(&Header::with_position(ZFIN, 0), &mut out);
+    assert_eq!(&out[..], &b"**\x18B0800000000022d\r\x8a"[..]);
+}
+
+#[test]
+fn test_bin_header() {
+    for &encoding in [Encoding::Crc16, Encoding::Crc32].iter() {
+        let header = Header::with_position(ZDATA, 0x1118_1310);
+        let mut out = b"rz\r".to_vec();
+        encode_bin_header(&header, encoding, &mut Escaper::default(), &mut out);
+        assert!(!out[3..].contains(&XON) && !out[3..].contains(&XOFF));
+
+        let mut zmodem = Zmodem::new(Cursor::new(out));
+        assert_eq!(zmodem.read_header().expect("header"), header);
+        assert_eq!(zmodem.rx_encoding, encoding);
+    }
+}
+
+#[test]
+fn test_subpacket_escaping() {
+    let data: Vec<u8> = (0..=255u8).chain(b"@\r@\x8d".iter().cloned()).collect();
+    for &encoding in [Encoding::Crc16, Encoding::Crc32].iter() {
+        for &escape_ctl in [false, true].iter() {
+            let mut escaper = Escaper::default();
+            escaper.escape_ctl = escape_ctl;
+
+            let mut out = vec![];
+            encode_subpacket(&data, ZCRCW, encoding, &mut escaper, &mut out);
+            for &b in [0x10, XON, XOFF, 0x90, 0x91, 0x93].iter() {
+                assert!(!out.contains(&b));
+            }
+
+            if escape_ctl {
+                assert!(out.iter().all(|&b| b == ZDLE || b & 0x60 != 0));
+            }
+
+            let mut zmodem = Zmodem::new(Cursor::new(out));
+            zmodem.rx_encoding = encoding;
+            assert_eq!(zmodem.read_subpacket().expect("subpacket"), ZCRCW);
+            assert_eq!(zmodem.packet, data);
+        }
+    }
+}
+
+#[test]
+fn test_rubout_escapes() {
+    let data = [0x7F, 0xFF];
+    let mut crc = [0u8; 4];
+    let n = Encoding::Crc16.compute(&data, &[ZCRCE], &mut crc);
+    let mut input = vec![ZDLE, ZRUB0, ZDLE, ZRUB1, ZDLE, ZCRCE];
+    input.extend_from_slice(&crc[..n]);
+
+    let mut zmodem = Zmodem::new(Cursor::new(input));
+    assert_eq!(zmodem.read_subpacket().expect("subpacket"), ZCRCE);
+    assert_eq!(&zmodem.packet[..], &data[..]);
+}
+
+#[test]
+fn test_corrupted_subpacket() {
+    let mut out = vec![];
+    let encoding = Encoding::Crc32;
+    encode_subpacket(b"hello", ZCRCE, encoding, &mut Escaper::default(), &mut out);
+    out[1] ^= 0x02;
+
+    let mut zmodem = Zmodem::new(Cursor::new(out));
+    zmodem.rx_encoding = encoding;
+    let e = zmodem.read_subpacket().expect_err("bad CRC");
+    assert!(matches!(e, Error::Checksum), "{:?}", e);
+}
+
+#[test]
+fn test_cancel() {
+    let mut zmodem = Zmodem::new(Cursor::new(vec![b'x', ZDLE, ZDLE, ZDLE, ZDLE, ZDLE]));
+    let e = zmodem.read_header().expect_err("cancelled");
+    assert!(matches!(e, Error::Cancelled), "{:?}", e);
+}
+
+#[test]
+fn test_batch() {
+    let files = vec![
+        (FileInfo::new("kernel8.img", 100_000), tricky_data(100_000)),
+        (FileInfo::new("empty", 0), vec![]),
+        (FileInfo::new("block", 1024), tricky_data(1024)),
+        (
+            FileInfo {
+                name: "config.txt".to_string(),
+                len: Some(13),
+                mtime: Some(1_500_000_000),
+                mode: Some(0o100644),
+            },
+            b"arm_64bit=1\n\n".to_vec(),
+        ),
+    ];
+
+    let (tx, rx) = pipe();
+    let received = transfer(files.clone(), rx, tx, |_| Some(0));
+    assert_eq!(received, files);
+}
+
+#[test]
+fn test_resume_and_skip() {
+    let files = vec![
+        (FileInfo::new("skipped", 5000), tricky_data(5000)),
+        (FileInfo::new("resumed", 70_000), tricky_data(70_000)),
+    ];
+
+    let (tx, rx) = pipe();
+    let received = transfer(files.clone(), rx, tx, |info| match &info.name[..] {
+        "resumed" => Some(1234),
+        _ => None,
+    });
+
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].0, files[1].0);
+    assert_eq!(&received[0].1[..], &files[1].1[1234..]);
+}
+
+#[test]
+fn test_noisy_transfer() {
+    let files = vec![(FileInfo::new("noisy", 100_000), tricky_data(100_000))];
+
+    let (tx, rx) = pipe();
+    let noisy = Noisy {
+        pipe: rx,
+        written: 0,
+        corrupt: vec![3000, 40_000, 40_001, 90_000, 130_000],
+    };
+
+    let received = transfer(files.clone(), noisy, tx, |_| Some(0));
+    assert_eq!(received, files);
+}
+
+/// Interoperability with the `rz` and `sz` commands of lrzsz, run on the
+/// slave side of a pseudo-terminal. The tests pass trivially when lrzsz isn't
+/// installed.
+#[cfg(unix)]
+mod lrzsz {
+    use super::*;
+    use libc;
+    use std::fs::{self, File};
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::{Path, PathBuf};
+    use std::process::{self, Child, Command, ExitStatus, Stdio};
+    use std::{env, mem, ptr};
+
+    /// How long a read waits for the program before timing out.
+    const READ_TIMEOUT_MS: libc::c_int = 3000;
+
+    /// The master side of a pseudo-terminal and the program running on its
+    /// slave side. The program is killed if it's still running on drop.
+    struct Pty {
+        master: File,
+        child: Child,
+    }
+
+    impl Pty {
+        /// Runs `program` with `args` in `dir`, with its standard input and
+        /// output connected to a new pseudo-terminal in raw mode.
+        fn spawn(program: &Path, args: &[&str], dir: &Path) -> Pty {
+            let (mut master, mut slave) = (0, 0);
+            unsafe {
+                let null = ptr::null_mut();
+                let opened = libc::openpty(&mut master, &mut slave, null, null as _, null as _);
+                assert_eq!(opened, 0, "openpty: {}", io::Error::last_os_error());
+
+                let mut termios = mem::zeroed();
+                assert_eq!(libc::tcgetattr(slave, &mut termios), 0, "tcgetattr");
+                libc::cfmakeraw(&mut termios);
+                assert_eq!(libc::tcsetattr(slave, libc::TCSANOW, &termios), 0);
+            }
+
+            let (master, slave) = unsafe { (File::from_raw_fd(master), File::from_raw_fd(slave)) };
+            let child = Command::new(program)
+                .args(args)
+                .current_dir(dir)
+                .stdin(Stdio::from(slave.try_clone().expect("duplicate pty slave")))
+                .stdout(Stdio::from(slave))
+                .stderr(Stdio::null())
+                .spawn()
+                .expect("spawn lrzsz");
+
+            Pty { master, child }
+        }
+
+        fn wait(&mut self) -> ExitStatus {
+            self.child.wait().expect("wait for lrzsz")
+        }
+    }
+
+    impl Drop for Pty {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+
+    impl io::Read for Pty {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut poll = libc::pollfd {
+                fd: self.master.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+
+            match unsafe { libc::poll(&mut poll, 1, READ_TIMEOUT_MS) } {
+                -1 => Err(io::Error::last_os_error()),
+                0 => Err(io::ErrorKind::TimedOut.into()),
+                _ => match self.master.read(buf) {
+                    // The master reports a closed slave as `EIO`.
+                    Err(ref e) if e.raw_os_error() == Some(libc::EIO) => Ok(0),
+                    result => result,
+                },
+            }
+        }
+    }
+
+    impl io::Write for Pty {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.master.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.master.flush()
+        }
+    }
+
+    /// Returns the path of the first program in `names` found in `PATH`.
+    fn find_program(names: &[&str]) -> Option<PathBuf> {
+        let path = env::var_os("PATH")?;
+        env::split_paths(&path)
+            .flat_map(|dir| names.iter().map(move |name| dir.join(name)))
+            .find(|program| program.is_file())
+    }
+
+    /// Returns a new, empty directory for the test `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("xmodem-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("create scratch directory");
+        dir
+    }
+
+    #[test]
+    fn test_send_to_rz() {
+        let rz = match find_program(&["rz", "lrz"]) {
+            Some(rz) => rz,
+            None => return eprintln!("skipping: lrzsz's `rz` isn't installed"),
+        };
+
+        let dir = scratch_dir("rz");
+        let data = tricky_data(100_000);
+        let mut pty = Pty::spawn(&rz, &["-b", "-y"], &dir);
+        {
+            let mut zmodem = Zmodem::new(&mut pty);
+            let info = FileInfo::new("interop.bin", data.len() as u64);
+            let sent = zmodem
+                .send_file(&info, Cursor::new(&data[..]))
+                .expect("send to rz");
+            assert_eq!(sent, data.len() as u64);
+            zmodem.finish().expect("finish session");
+        }
+
+        assert!(pty.wait().success(), "rz failed");
+        let received = fs::read(dir.join("interop.bin")).expect("read rz's copy");
+        assert!(received == data, "rz's copy differs");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_receive_from_sz() {
+        let sz = match find_program(&["sz", "lsz"]) {
+            Some(sz) => sz,
+            None => return eprintln!("skipping: lrzsz's `sz` isn't installed"),
+        };
+
+        let dir = scratch_dir("sz");
+        let data = tricky_data(100_000);
+        fs::write(dir.join("interop.bin"), &data).expect("write file for sz");
+        let mut pty = Pty::spawn(&sz, &["-b", "interop.bin"], &dir);
+        let mut received = vec![];
+        {
+            let mut zmodem = Zmodem::new(&mut pty);
+            let info = zmodem.recv_header().expect("header").expect("a file");
+            assert_eq!(info.name, "interop.bin");
+            assert_eq!(info.len, Some(data.len() as u64));
+
+            let n = zmodem.recv_data(0, &mut received).expect("receive from sz");
+            assert_eq!(n, data.len() as u64);
+            assert!(zmodem.recv_header().expect("end of session").is_none());
+        }
+
+        assert!(pty.wait().success(), "sz failed");
+        assert!(received == data, "received data differs");
+        let _ = fs::remove_dir_all(&dir);
+    }
+}